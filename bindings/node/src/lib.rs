@@ -0,0 +1,137 @@
+//! Node.js bindings for `ceres-mpq`, built with [napi-rs](https://napi.rs).
+//!
+//! Exposes `Archive` and `Creator` classes for opening, listing, reading and creating archives,
+//! e.g. to inspect uploaded maps from a Node.js backend. Build with `cargo build --release`
+//! in this directory, and rename the resulting library to `ceres_mpq.node` to load it.
+
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+
+use napi::bindgen_prelude::Buffer;
+use napi_derive::napi;
+
+use ceres_mpq::{Archive, Compression, Creator, DynArchive, Error, FileOptions};
+
+fn to_js_error(error: Error) -> napi::Error {
+    napi::Error::from_reason(error.to_string())
+}
+
+#[napi(js_name = "Archive")]
+/// An archive opened from a file or a buffer.
+pub struct JsArchive {
+    archive: DynArchive,
+}
+
+#[napi]
+impl JsArchive {
+    /// Opens the archive at the specified path.
+    #[napi(factory)]
+    pub fn open(path: String) -> napi::Result<JsArchive> {
+        let file = File::open(path).map_err(|error| to_js_error(error.into()))?;
+        let archive = Archive::open_dyn(BufReader::new(file)).map_err(to_js_error)?;
+
+        Ok(JsArchive { archive })
+    }
+
+    /// Opens an archive held in a buffer, e.g. an uploaded file.
+    #[napi(factory)]
+    pub fn from_buffer(data: Buffer) -> napi::Result<JsArchive> {
+        let data: Vec<u8> = data.into();
+        let archive = Archive::open_dyn(Cursor::new(data)).map_err(to_js_error)?;
+
+        Ok(JsArchive { archive })
+    }
+
+    /// Returns the names listed in the archive's `(listfile)`, or `null` if it has none.
+    #[napi]
+    pub fn files(&mut self) -> napi::Result<Option<Vec<String>>> {
+        self.archive.files_result().map_err(to_js_error)
+    }
+
+    /// Returns whether the archive contains a file with the specified name.
+    #[napi]
+    pub fn has_file(&self, name: String) -> bool {
+        self.archive.file_info(&name).is_some()
+    }
+
+    /// Reads the contents of a file.
+    #[napi]
+    pub fn read_file(&mut self, name: String) -> napi::Result<Buffer> {
+        self.archive
+            .read_file(&name)
+            .map(Buffer::from)
+            .map_err(to_js_error)
+    }
+}
+
+#[napi(object)]
+/// Options for files added to a `Creator`. Omitted options are disabled.
+pub struct JsFileOptions {
+    pub compress: Option<bool>,
+    pub encrypt: Option<bool>,
+    pub adjust_key: Option<bool>,
+}
+
+impl From<JsFileOptions> for FileOptions {
+    fn from(options: JsFileOptions) -> FileOptions {
+        FileOptions {
+            compression: if options.compress.unwrap_or(false) {
+                Compression::Deflate
+            } else {
+                Compression::None
+            },
+            encrypt: options.encrypt.unwrap_or(false),
+            adjust_key: options.adjust_key.unwrap_or(false),
+            ..Default::default()
+        }
+    }
+}
+
+#[napi(js_name = "Creator")]
+/// Collects files and writes them into a new archive.
+pub struct JsCreator {
+    creator: Creator,
+}
+
+#[napi]
+impl JsCreator {
+    #[napi(constructor)]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> JsCreator {
+        JsCreator {
+            creator: Creator::default(),
+        }
+    }
+
+    /// Adds a file, compressed unless specified otherwise.
+    #[napi]
+    pub fn add_file(&mut self, name: String, data: Buffer, options: Option<JsFileOptions>) {
+        let options = options.map(FileOptions::from).unwrap_or(FileOptions {
+            compression: Compression::Deflate,
+            ..Default::default()
+        });
+        let data: Vec<u8> = data.into();
+
+        self.creator.add_file(&name, data, options);
+    }
+
+    /// Writes the archive into a new buffer.
+    #[napi]
+    pub fn write(&mut self) -> napi::Result<Buffer> {
+        let mut cursor = Cursor::new(Vec::new());
+        self.creator
+            .write(&mut cursor)
+            .map_err(|error| to_js_error(error.into()))?;
+
+        Ok(cursor.into_inner().into())
+    }
+
+    /// Writes the archive to the specified path.
+    #[napi]
+    pub fn write_to_file(&mut self, path: String) -> napi::Result<()> {
+        let file = File::create(path).map_err(|error| to_js_error(error.into()))?;
+        self.creator
+            .write(file)
+            .map_err(|error| to_js_error(error.into()))
+    }
+}
@@ -0,0 +1,310 @@
+//! IMA ADPCM compression as used by MPQ archives for `.wav` files.
+//!
+//! This is Blizzard's own variant of IMA ADPCM, which differs from the standard one in its
+//! stream layout and in additional markers which adjust the step index without producing a sample.
+
+const INITIAL_STEP_INDEX: usize = 0x2C;
+const MAX_STEP_INDEX: usize = 0x58;
+
+const MARKER_DECREASE_STEP: u8 = 0x80;
+const MARKER_INCREASE_STEP: u8 = 0x81;
+
+const NEXT_STEP_TABLE: [i32; 32] = [
+    -1, 0, -1, 4, -1, 2, -1, 6, -1, 1, -1, 5, -1, 3, -1, 7, -1, 1, -1, 5, -1, 3, -1, 7, -1, 2, -1,
+    4, -1, 6, -1, 8,
+];
+
+const STEP_SIZE_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+/// Writes 16-bit samples into a byte buffer, refusing to write past its end.
+struct SampleWriter<'a> {
+    output: &'a mut [u8],
+    position: usize,
+}
+
+impl<'a> SampleWriter<'a> {
+    fn write(&mut self, sample: i16) -> bool {
+        match self.output.get_mut(self.position..self.position + 2) {
+            Some(slot) => {
+                slot.copy_from_slice(&sample.to_le_bytes());
+                self.position += 2;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn next_step_index(step_index: usize, encoded_sample: u8) -> usize {
+    let step_index = step_index as i32 + NEXT_STEP_TABLE[(encoded_sample & 0x1F) as usize];
+
+    step_index.clamp(0, 88) as usize
+}
+
+fn decode_sample(predicted_sample: i32, encoded_sample: u8, step_size: i32, bit_shift: u8) -> i32 {
+    let mut difference = step_size >> bit_shift;
+
+    for bit in 0..6 {
+        if encoded_sample & (1 << bit) != 0 {
+            difference += step_size >> bit;
+        }
+    }
+
+    if encoded_sample & 0x40 != 0 {
+        (predicted_sample - difference).max(-32768)
+    } else {
+        (predicted_sample + difference).min(32767)
+    }
+}
+
+/// Decompresses ADPCM data with the specified number of channels (1 or 2) into `output`,
+/// returning the number of bytes written. Decoding stops early if `output` is full.
+///
+/// Returns `None` if the input is too short to contain the stream header.
+pub(crate) fn decompress(input: &[u8], output: &mut [u8], channels: usize) -> Option<usize> {
+    let header_size = 2 + channels * 2;
+    if input.len() < header_size {
+        return None;
+    }
+
+    // the first byte is always zero, the second one is the bit shift
+    let bit_shift = input[1];
+    if bit_shift > 31 {
+        return None;
+    }
+
+    let mut writer = SampleWriter {
+        output,
+        position: 0,
+    };
+    let mut predicted_samples = [0i32; 2];
+    let mut step_indices = [INITIAL_STEP_INDEX; 2];
+
+    // every channel starts with an uncompressed sample
+    for (predicted_sample, bytes) in predicted_samples
+        .iter_mut()
+        .zip(input[2..header_size].chunks_exact(2))
+    {
+        let sample = i16::from_le_bytes([bytes[0], bytes[1]]);
+
+        *predicted_sample = i32::from(sample);
+        if !writer.write(sample) {
+            return Some(writer.position);
+        }
+    }
+
+    let mut channel = channels - 1;
+    for &encoded_sample in &input[header_size..] {
+        channel = (channel + 1) % channels;
+
+        match encoded_sample {
+            MARKER_DECREASE_STEP => {
+                step_indices[channel] = step_indices[channel].saturating_sub(1);
+
+                if !writer.write(predicted_samples[channel] as i16) {
+                    break;
+                }
+            }
+            MARKER_INCREASE_STEP => {
+                step_indices[channel] = (step_indices[channel] + 8).min(MAX_STEP_INDEX);
+
+                // the next byte belongs to the same channel
+                channel = (channel + 1) % channels;
+            }
+            _ => {
+                let step_index = step_indices[channel];
+                let step_size = STEP_SIZE_TABLE[step_index];
+
+                predicted_samples[channel] = decode_sample(
+                    predicted_samples[channel],
+                    encoded_sample,
+                    step_size,
+                    bit_shift,
+                );
+
+                if !writer.write(predicted_samples[channel] as i16) {
+                    break;
+                }
+
+                step_indices[channel] = next_step_index(step_index, encoded_sample);
+            }
+        }
+    }
+
+    Some(writer.position)
+}
+
+/// Compresses 16-bit samples with the specified number of channels (1 or 2) into `output`,
+/// returning the compressed size, or `None` if it does not fit.
+///
+/// `bit_shift` is the compression level minus one. Blizzard uses levels 4 to 6,
+/// where higher levels preserve more detail.
+pub(crate) fn compress(
+    input: &[u8],
+    output: &mut [u8],
+    channels: usize,
+    bit_shift: u8,
+) -> Option<usize> {
+    let header_size = 2 + channels * 2;
+    if !input.len().is_multiple_of(2) || input.len() < channels * 2 || output.len() < header_size {
+        return None;
+    }
+
+    let mut samples = input
+        .chunks_exact(2)
+        .map(|bytes| i32::from(i16::from_le_bytes([bytes[0], bytes[1]])));
+
+    output[0] = 0;
+    output[1] = bit_shift;
+
+    // every channel starts with an uncompressed sample
+    let mut predicted_samples = [0i32; 2];
+    for (predicted_sample, bytes) in predicted_samples
+        .iter_mut()
+        .zip(output[2..header_size].chunks_exact_mut(2))
+    {
+        *predicted_sample = samples.next()?;
+        bytes.copy_from_slice(&(*predicted_sample as i16).to_le_bytes());
+    }
+
+    let mut position = header_size;
+    let mut write = |byte: u8| -> Option<()> {
+        *output.get_mut(position)? = byte;
+        position += 1;
+        Some(())
+    };
+
+    let max_bit = (1u8 << (bit_shift - 1)).min(0x20);
+    let mut step_indices = [INITIAL_STEP_INDEX; 2];
+    let mut channel = channels - 1;
+
+    for sample in samples {
+        channel = (channel + 1) % channels;
+
+        let mut encoded_sample = 0u8;
+        let mut difference = sample - predicted_samples[channel];
+        if difference < 0 {
+            difference = -difference;
+            encoded_sample |= 0x40;
+        }
+
+        let mut step_size = STEP_SIZE_TABLE[step_indices[channel]];
+        if difference < (step_size >> (bit_shift + 1)) {
+            step_indices[channel] = step_indices[channel].saturating_sub(1);
+            write(MARKER_DECREASE_STEP)?;
+            continue;
+        }
+
+        while difference > (step_size << 1) && step_indices[channel] < MAX_STEP_INDEX {
+            step_indices[channel] = (step_indices[channel] + 8).min(MAX_STEP_INDEX);
+            step_size = STEP_SIZE_TABLE[step_indices[channel]];
+            write(MARKER_INCREASE_STEP)?;
+        }
+
+        let mut bit = 1;
+        let mut total_step_size = 0;
+        while bit <= max_bit {
+            if total_step_size + step_size <= difference {
+                total_step_size += step_size;
+                encoded_sample |= bit;
+            }
+
+            step_size >>= 1;
+            bit <<= 1;
+        }
+
+        let step_index = step_indices[channel];
+        predicted_samples[channel] = decode_sample(
+            predicted_samples[channel],
+            encoded_sample,
+            STEP_SIZE_TABLE[step_index],
+            bit_shift,
+        );
+        write(encoded_sample)?;
+        step_indices[channel] = next_step_index(step_index, encoded_sample);
+    }
+
+    Some(position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::ADPCM_BIT_SHIFT;
+
+    /// A sine wave per channel, with a different frequency on each channel.
+    fn samples(channels: usize, count: usize) -> Vec<i16> {
+        (0..count * channels)
+            .map(|i| {
+                let (frame, channel) = (i / channels, i % channels);
+                let phase = frame as f64 * (0.01 + 0.02 * channel as f64);
+
+                (phase.sin() * 12000.0) as i16
+            })
+            .collect()
+    }
+
+    fn round_trip(samples: &[i16], channels: usize, bit_shift: u8) -> Vec<i16> {
+        let input: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+
+        let mut compressed = vec![0; input.len()];
+        let size = compress(&input, &mut compressed, channels, bit_shift).unwrap();
+        compressed.truncate(size);
+        assert!(size < input.len() * 3 / 4);
+
+        let mut output = vec![0; input.len()];
+        assert_eq!(
+            decompress(&compressed, &mut output, channels),
+            Some(input.len())
+        );
+
+        output
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_mono_and_stereo() {
+        for channels in 1..=2 {
+            for bit_shift in 3..=ADPCM_BIT_SHIFT + 1 {
+                let samples = samples(channels, 4000);
+                let decoded = round_trip(&samples, channels, bit_shift);
+
+                // the first sample of every channel is stored as it is
+                assert_eq!(decoded[..channels], samples[..channels]);
+
+                let max_error = samples
+                    .iter()
+                    .zip(&decoded)
+                    .map(|(&a, &b)| (i32::from(a) - i32::from(b)).abs())
+                    .max()
+                    .unwrap();
+                assert!(
+                    max_error < 256,
+                    "error of {} with bit shift {}",
+                    max_error,
+                    bit_shift
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let mut output = vec![0; 64];
+
+        // too short for the header of a stereo stream
+        assert_eq!(decompress(&[0, 4, 1, 0], &mut output, 2), None);
+        assert_eq!(decompress(&[0, 32, 1, 0], &mut output, 1), None);
+
+        // odd number of bytes is not a whole number of samples
+        assert_eq!(compress(&[0; 7], &mut output, 1, ADPCM_BIT_SHIFT), None);
+    }
+}
@@ -1,9 +1,354 @@
-use std::io::{Read, Seek};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
 
+use md5::{Digest, Md5};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+use super::attributes::*;
+use super::consts::*;
+use super::contents::FileContents;
+use super::creator::*;
+use super::entries::*;
 use super::error::*;
+use super::extract::*;
+use super::file::*;
+use super::flags::FileFlags;
+use super::hooks::*;
+use super::listfile::*;
+use super::manifest::*;
+use super::map::*;
+use super::matching::*;
+use super::names::*;
 use super::seeker::*;
+use super::signature::*;
 use super::table::*;
+use super::tree::*;
 use super::util::*;
+use super::verify::*;
+
+/// The largest scratch buffer an archive keeps between reads.
+const MAX_SCRATCH_SIZE: usize = 16 * 1024 * 1024;
+
+/// The most memory that is reserved for the contents of a file before they are decoded.
+const MAX_PREALLOCATION: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Determines how forward slashes (`/`) in file names are treated.
+///
+/// MPQ archives use backslashes (`\\`) as path separators, and the name hashing
+/// algorithm treats both kinds of slashes as different characters.
+pub enum SlashPolicy {
+    /// Forward slashes are converted to backslashes.
+    Normalize,
+    /// Names are used exactly as given.
+    #[default]
+    Preserve,
+}
+
+impl SlashPolicy {
+    pub(crate) fn apply<'a>(self, name: &'a str) -> Cow<'a, str> {
+        match self {
+            SlashPolicy::Normalize if name.contains('/') => Cow::Owned(name.replace('/', "\\")),
+            _ => Cow::Borrowed(name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Represents various options that can be used when opening an archive.
+pub struct OpenOptions {
+    /// Whether to check if the underlying reader has changed before
+    /// every read, and re-parse the archive tables if it did.
+    ///
+    /// This is useful for long-lived archives backed by a file that may be
+    /// rewritten by other programs. Archives opened with [`open_path`](struct.Archive.html#method.open_path)
+    /// are checked for changes of the file's size and modification time. For other readers,
+    /// only changes of the size are detected, and other changes can be picked up with
+    /// [`reload_if_modified`](struct.Archive.html#method.reload_if_modified) for file-backed archives,
+    /// or [`reload`](struct.Archive.html#method.reload) otherwise.
+    pub auto_reload: bool,
+    /// How to treat forward slashes in names that are looked up in the archive.
+    ///
+    /// By default, names are looked up exactly as given.
+    pub slash_policy: SlashPolicy,
+    /// Whether to convert names that are looked up in the archive to Unicode Normalization Form C
+    /// before hashing them.
+    ///
+    /// This allows names in decomposed form, as produced by some operating systems and editors,
+    /// to resolve to entries stored in the more common composed form.
+    pub normalize_unicode: bool,
+    /// Whether names are matched case-sensitively, by hashing them without converting them to uppercase.
+    ///
+    /// Regular MPQ lookups ignore case. This is only useful for archives whose hash table entries
+    /// were deliberately created from case-sensitive hashes, or when diagnosing hash collisions.
+    pub case_sensitive: bool,
+    /// Whether to verify the contents of read files against the CRC32 and MD5 checksums
+    /// in the archive's `(attributes)`, failing with [Error::AttributesMismatch](enum.Error.html)
+    /// if they differ.
+    ///
+    /// The `(attributes)` are read when the archive is opened or reloaded. Files without
+    /// checksums, and partial reads, e.g. with [ReadOptions::max_size](struct.ReadOptions.html),
+    /// are not verified.
+    pub verify_attributes: bool,
+    /// Whether to remember the hash table entries found for every looked up name,
+    /// so that repeated lookups of the same names only cost a single hash map probe.
+    ///
+    /// This is useful for long-running programs which read the same files over and over.
+    /// The cache is cleared whenever the archive is reloaded, and stops growing after
+    /// a fixed number of names.
+    pub cache_lookups: bool,
+    /// Whether to read the `(listfile)` when the archive is opened or reloaded, so that
+    /// [`iter`](struct.Archive.html#method.iter) yields the files listed in it.
+    ///
+    /// Failing to read the `(listfile)` is not an error, the archive is then treated as having none.
+    pub read_listfile: bool,
+}
+
+impl OpenOptions {
+    /// Applies the configured normalizations to a name that is looked up in the archive.
+    pub(crate) fn lookup_name<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        let name = self.slash_policy.apply(name);
+
+        if self.normalize_unicode && !is_nfc(&name) {
+            Cow::Owned(name.nfc().collect())
+        } else {
+            name
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Metadata about a file stored in an archive, as recorded in its block table entry.
+pub struct FileInfo {
+    block_index: usize,
+    file_pos: u64,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    flags: u32,
+    locale: u16,
+    platform: u16,
+}
+
+impl FileInfo {
+    fn new(
+        block_index: usize,
+        block_entry: &BlockEntry,
+        hash_entry: Option<&HashEntry>,
+    ) -> FileInfo {
+        FileInfo {
+            block_index,
+            file_pos: block_entry.file_pos,
+            compressed_size: block_entry.compressed_size,
+            uncompressed_size: block_entry.uncompressed_size,
+            flags: block_entry.flags,
+            locale: hash_entry.map(|e| e.locale).unwrap_or(0),
+            platform: hash_entry.map(|e| e.platform).unwrap_or(0),
+        }
+    }
+
+    /// Index of the file's entry in the block table.
+    pub fn block_index(&self) -> usize {
+        self.block_index
+    }
+
+    /// Position of the file's data, relative to the start of the archive.
+    pub fn file_pos(&self) -> u64 {
+        self.file_pos
+    }
+
+    /// Size of the file's data as it is stored in the archive.
+    pub fn compressed_size(&self) -> u64 {
+        self.compressed_size
+    }
+
+    /// Size of the file's contents once decoded.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.uncompressed_size
+    }
+
+    /// The unmodified flags value of the block table entry,
+    /// including any bits not known to this library.
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// The flags of the block table entry, as a [`FileFlags`](struct.FileFlags.html) set.
+    pub fn file_flags(&self) -> FileFlags {
+        FileFlags::from_bits_retain(self.flags)
+    }
+
+    /// The locale of the file's hash table entry, where `0` is the neutral locale.
+    ///
+    /// For block table entries which are not referenced by any hash table entry, this is `0`.
+    pub fn locale(&self) -> u16 {
+        self.locale
+    }
+
+    /// The platform field of the file's hash table entry.
+    ///
+    /// For block table entries which are not referenced by any hash table entry, this is `0`.
+    pub fn platform(&self) -> u16 {
+        self.platform
+    }
+
+    /// Whether the block table entry is in use.
+    pub fn exists(&self) -> bool {
+        (self.flags & MPQ_FILE_EXISTS) != 0
+    }
+
+    /// Whether the file is compressed using PKWare DCL as a whole.
+    pub fn is_imploded(&self) -> bool {
+        (self.flags & MPQ_FILE_IMPLODE) != 0
+    }
+
+    /// Whether the file's sectors are compressed.
+    pub fn is_compressed(&self) -> bool {
+        (self.flags & MPQ_FILE_COMPRESS) != 0
+    }
+
+    /// Whether the file is encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        (self.flags & MPQ_FILE_ENCRYPTED) != 0
+    }
+
+    /// Whether the encryption key of the file is adjusted by its position and size.
+    pub fn is_key_adjusted(&self) -> bool {
+        (self.flags & MPQ_FILE_ADJUST_KEY) != 0
+    }
+
+    /// Whether the file is stored as a single block instead of being split into sectors.
+    pub fn is_single_unit(&self) -> bool {
+        (self.flags & MPQ_FILE_SINGLE_UNIT) != 0
+    }
+
+    /// Whether the file has checksums of its sectors, which are verified when it is read.
+    pub fn has_sector_checksums(&self) -> bool {
+        (self.flags & MPQ_FILE_SECTOR_CRC) != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The reason why the name of a file is not known.
+pub enum UnresolvedReason {
+    /// The file is referenced by the hash table, but none of the known names
+    /// match its hash table entry.
+    NameUnknown,
+    /// The file is not referenced by any hash table entry at all,
+    /// so it cannot be looked up by any name.
+    Unreferenced,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A file whose name could not be resolved, as reported by
+/// [`unresolved_encrypted_files`](struct.Archive.html#method.unresolved_encrypted_files).
+pub struct UnresolvedFile {
+    /// Metadata of the unresolved file.
+    pub info: FileInfo,
+    /// Why the file's name could not be resolved.
+    pub reason: UnresolvedReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Determines how names which could escape the target directory are handled when extracting files,
+/// e.g. names containing `..` components, drive letters or leading slashes.
+pub enum PathPolicy {
+    /// Offending path components are removed, and the file is extracted
+    /// to the remaining path inside of the target directory.
+    #[default]
+    Sanitize,
+    /// The file is not extracted, and is reported with an
+    /// [`UnsafePath`](enum.Error.html#variant.UnsafePath) error.
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Represents various options that can be used when extracting files
+/// with [`extract_all_with_options`](struct.Archive.html#method.extract_all_with_options).
+pub struct ExtractOptions {
+    /// Whether to set the modification time of extracted files to the time recorded
+    /// in the archive's `(attributes)`, if it has any.
+    pub preserve_timestamps: bool,
+    /// How to handle names which could escape the target directory.
+    ///
+    /// Files are never written outside of the target directory, regardless of the policy.
+    pub path_policy: PathPolicy,
+}
+
+#[derive(Debug, Clone)]
+/// The stored sectors of a file, as returned by
+/// [`read_file_sectors`](struct.Archive.html#method.read_file_sectors).
+pub struct RawSectors {
+    /// Offsets of the sectors relative to the start of the file's data,
+    /// followed by the offset of the end of the last sector.
+    pub offsets: Vec<u32>,
+    /// Decrypted, but still compressed data of each sector.
+    pub sectors: Vec<Vec<u8>>,
+}
+
+#[derive(Debug)]
+/// The outcome of extracting a single file, as reported by
+/// [`extract_all`](struct.Archive.html#method.extract_all).
+pub struct ExtractedFile {
+    /// Name of the file inside the archive.
+    pub name: String,
+    /// Path the file was written to, or why it could not be extracted.
+    pub result: Result<PathBuf, Error>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Represents various options that can be used when reading a single file
+/// with [`read_file_with_options`](struct.Archive.html#method.read_file_with_options).
+///
+/// The defaults are equivalent to [`read_file`](struct.Archive.html#method.read_file).
+pub struct ReadOptions {
+    /// Do not decrypt the file.
+    ///
+    /// The sector offset table of an encrypted file is itself encrypted,
+    /// so if the file is encrypted, its data is returned exactly as it is stored
+    /// in the archive, including the sector offset table.
+    pub skip_decryption: bool,
+    /// Do not decompress the file's sectors.
+    ///
+    /// The sectors are still decrypted if needed, and are returned back-to-back
+    /// as they are stored in the archive, without the sector offset table.
+    pub skip_decompression: bool,
+    /// The maximum amount of bytes to return. If set, only the sectors needed
+    /// to produce this amount of bytes are read and decoded.
+    pub max_size: Option<u64>,
+    /// Use this key to decrypt the file, instead of deriving it from the file's name.
+    ///
+    /// This is the key of the file's first sector, after any adjustment. It can be used
+    /// to read encrypted files which were renamed, whose key was recovered some other way.
+    pub file_key: Option<u32>,
+}
+
+/// A name as it is looked up in the hash table,
+/// together with its precomputed hashes if they apply to it.
+struct Lookup<'n> {
+    name: Cow<'n, str>,
+    precomputed: Option<&'n FileName>,
+}
+
+impl Lookup<'_> {
+    /// Calculates the encryption key of the file with this name.
+    fn file_key(&self, block_entry: &BlockEntry) -> u32 {
+        let file_pos = block_entry.file_pos as u32;
+        let file_size = block_entry.uncompressed_size as u32;
+        let adjusted = block_entry.is_key_adjusted();
+
+        match self.precomputed {
+            Some(file_name) => {
+                file_key_from_hash(file_name.key_hash(), file_pos, file_size, adjusted)
+            }
+            None => calculate_file_key(&self.name, file_pos, file_size, adjusted),
+        }
+    }
+}
 
 #[derive(Debug)]
 /// Implementation of a MoPaQ archive viewer.
@@ -15,6 +360,18 @@ pub struct Archive<R: Read + Seek> {
     seeker: Seeker<R>,
     hash_table: FileHashTable,
     block_table: FileBlockTable,
+    options: OpenOptions,
+    modified: Option<SystemTime>,
+    // reads the modification time of the reader, set only for archives opened from a path
+    modified_probe: Option<fn(&R) -> Option<SystemTime>>,
+    known_names: Vec<String>,
+    hooks: HookSlot,
+    // loaded only if `OpenOptions::verify_attributes` is set
+    verified_attributes: Option<Attributes>,
+    // reused for the stored data of read files, to avoid allocating it for every read
+    scratch: Vec<u8>,
+    // read when the archive is opened or reloaded if `OpenOptions::read_listfile` is set
+    listfile: Option<Listfile>,
 }
 
 impl<R: Read + Seek> Archive<R> {
@@ -31,144 +388,1780 @@ impl<R: Read + Seek> Archive<R> {
     ///
     /// No other operations will be performed.
     pub fn open(reader: R) -> Result<Archive<R>, Error> {
+        Self::open_with_options(reader, OpenOptions::default())
+    }
+
+    /// Same as [`open`](struct.Archive.html#method.open), but allows
+    /// specifying additional [`OpenOptions`](struct.OpenOptions.html).
+    pub fn open_with_options(reader: R, options: OpenOptions) -> Result<Archive<R>, Error> {
         let mut seeker = Seeker::new(reader)?;
 
-        let hash_table = FileHashTable::from_seeker(&mut seeker)?;
-        let block_table = FileBlockTable::from_seeker(&mut seeker)?;
+        let (mut hash_table, block_table) = read_tables(&mut seeker)?;
+        if options.cache_lookups {
+            hash_table.enable_lookup_cache();
+        }
 
-        Ok(Archive {
+        let mut archive = Archive {
             seeker,
             hash_table,
             block_table,
-        })
+            options,
+            modified: None,
+            modified_probe: None,
+            known_names: Vec::new(),
+            hooks: HookSlot::default(),
+            verified_attributes: None,
+            scratch: Vec::new(),
+            listfile: None,
+        };
+
+        if options.verify_attributes {
+            archive.verified_attributes = archive.attributes()?;
+        }
+
+        if options.read_listfile {
+            // a missing or unreadable listfile only means that no names are known
+            archive.listfile = archive.listfile().ok().flatten();
+        }
+
+        Ok(archive)
+    }
+
+    /// Re-parses the MPQ header and the hash and block tables
+    /// from the underlying reader.
+    ///
+    /// If this fails, the archive is left in the state it was in
+    /// before the reload.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        // read before the tables, so that changes made while reloading are picked up later
+        let modified = self
+            .modified_probe
+            .and_then(|probe| probe(self.seeker.reader_ref()));
+        let old_info = self.seeker.info().clone();
+        let result = self
+            .seeker
+            .reload()
+            .and_then(|_| read_tables(&mut self.seeker));
+
+        let (mut hash_table, block_table) = match result {
+            Ok(tables) => tables,
+            Err(error) => {
+                self.seeker.set_info(old_info);
+
+                return Err(error);
+            }
+        };
+        if self.options.cache_lookups {
+            hash_table.enable_lookup_cache();
+        }
+
+        // the attributes are read through the new tables, so those are swapped in first,
+        // and swapped out again if the attributes cannot be read
+        let old_hash_table = std::mem::replace(&mut self.hash_table, hash_table);
+        let old_block_table = std::mem::replace(&mut self.block_table, block_table);
+        // the old attributes must not be used to verify the new ones
+        let old_attributes = self.verified_attributes.take();
+        // reading the attributes and the listfile must not trigger another reload
+        let old_modified = self.modified;
+        if self.modified_probe.is_some() {
+            self.modified = modified;
+        }
+
+        if self.options.verify_attributes {
+            match self.attributes() {
+                Ok(attributes) => self.verified_attributes = attributes,
+                Err(error) => {
+                    self.hash_table = old_hash_table;
+                    self.block_table = old_block_table;
+                    self.verified_attributes = old_attributes;
+                    self.modified = old_modified;
+                    self.seeker.set_info(old_info);
+
+                    return Err(error);
+                }
+            }
+        }
+
+        // the listfile of the previous tables must not be listed for the new ones
+        self.listfile = None;
+        if self.options.read_listfile {
+            self.listfile = self.listfile().ok().flatten();
+        }
+
+        Ok(())
+    }
+
+    /// If `auto_reload` is enabled, reloads the archive when the size of the underlying
+    /// reader has changed, or its modification time if it is known.
+    fn refresh(&mut self) -> Result<(), Error> {
+        if !self.options.auto_reload {
+            return Ok(());
+        }
+
+        let size_changed = self.seeker.stream_len()? != self.seeker.info().file_size;
+        let modified = self
+            .modified_probe
+            .and_then(|probe| probe(self.seeker.reader_ref()));
+        let time_changed = match (self.modified, modified) {
+            (Some(old), Some(new)) => old != new,
+            _ => false,
+        };
+
+        if size_changed || time_changed {
+            self.reload()?;
+        }
+
+        Ok(())
     }
 
     /// Read a file's contents.
     ///
     /// Notably, the filename resolution algorithm
-    /// is case, and will treat backslashes (`\`) and forward slashes (`/`)
-    /// as different characters.
+    /// is case-insensitive, and by default will treat backslashes (`\`) and forward slashes (`/`)
+    /// as different characters. This can be changed with [`OpenOptions`](struct.OpenOptions.html).
+    ///
+    /// The name can also be given as a [FileName](struct.FileName.html) with precomputed hashes.
+    pub fn read_file<N>(&mut self, name: &N) -> Result<Vec<u8>, Error>
+    where
+        N: AsFileName + ?Sized,
+    {
+        self.read_file_with_options(name, ReadOptions::default())
+    }
+
+    /// Same as [`read_file`](struct.Archive.html#method.read_file), but allows
+    /// specifying [`ReadOptions`](struct.ReadOptions.html) for this call.
+    pub fn read_file_with_options<N>(
+        &mut self,
+        name: &N,
+        options: ReadOptions,
+    ) -> Result<Vec<u8>, Error>
+    where
+        N: AsFileName + ?Sized,
+    {
+        self.read_file_impl(name, None, options)
+    }
+
+    /// Same as [`read_file`](struct.Archive.html#method.read_file), but only finds the file
+    /// if its hash table entry has the specified platform.
     ///
-    /// Does not support single-unit files or uncompressed files.
-    pub fn read_file(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+    /// [`read_file`](struct.Archive.html#method.read_file) itself does not look at the platform,
+    /// and reads the first neutral-locale entry of a name.
+    pub fn read_file_with_platform(&mut self, name: &str, platform: u16) -> Result<Vec<u8>, Error> {
+        self.read_file_impl(name, Some(platform), ReadOptions::default())
+    }
+
+    fn read_file_impl<N>(
+        &mut self,
+        name: &N,
+        platform: Option<u16>,
+        options: ReadOptions,
+    ) -> Result<Vec<u8>, Error>
+    where
+        N: AsFileName + ?Sized,
+    {
+        self.refresh()?;
+
+        let lookup = self.lookup(name);
+        let name = lookup.name.as_ref();
+
         // find the hash entry and use it to find the block entry
         let hash_entry = self
-            .hash_table
-            .find_entry(name)
+            .find_lookup_entries(&lookup)
+            .into_iter()
+            .find(|entry| entry.locale == 0 && platform.is_none_or(|p| entry.platform == p))
             .ok_or(Error::FileNotFound)?;
         let block_entry = self
             .block_table
             .get(hash_entry.block_index as usize)
             .ok_or(Error::FileNotFound)?;
 
+        let max_size = options.max_size.unwrap_or(u64::MAX);
+
+        // without decryption, the sector offset table cannot be read,
+        // so return the file's data as it is stored
+        if block_entry.is_encrypted() && options.skip_decryption {
+            let size = block_entry.compressed_size.min(max_size);
+
+            return self.seeker.read(block_entry.file_pos, size);
+        }
+
         // calculate the file key
-        let encryption_key = if block_entry.is_encrypted() {
-            Some(calculate_file_key(
-                name,
-                block_entry.file_pos as u32,
-                block_entry.uncompressed_size as u32,
-                block_entry.is_key_adjusted(),
-            ))
-        } else {
+        let encryption_key = if !block_entry.is_encrypted() {
             None
+        } else if let Some(file_key) = options.file_key {
+            Some(file_key)
+        } else {
+            Some(lookup.file_key(block_entry))
         };
 
+        // files of modest size are read at once, instead of reading their sector offsets
+        // and sectors separately
+        self.seeker
+            .prefetch(block_entry.file_pos, block_entry.compressed_size)?;
+
         // read the sector offsets
         let sector_offsets = SectorOffsets::from_reader(
             &mut self.seeker,
             block_entry,
-            encryption_key.map(|k| k - 1),
+            encryption_key.map(|k| k.wrapping_sub(1)),
         )?;
 
-        // read out all the sectors
-        let sector_range = sector_offsets.all();
-        let raw_data = self.seeker.read(
-            block_entry.file_pos + u64::from(sector_range.0),
-            u64::from(sector_range.1),
+        let sector_checksums = sector_offsets.read_checksums(&mut self.seeker, block_entry)?;
+        let sector_size = block_entry.sector_size(self.seeker.info().sector_size);
+        let sector_count = sector_offsets.count();
+
+        // only read as many sectors as needed to satisfy `max_size`
+        let read_count = if max_size < block_entry.uncompressed_size {
+            sector_count.min(sector_count_from_size(max_size, sector_size) as usize)
+        } else {
+            sector_count
+        };
+
+        // read out all the sectors into the scratch buffer, where they are decrypted in place
+        let first_sector_offset = sector_offsets.one(0).ok_or(Error::Corrupted)?.0;
+        let last_sector = sector_offsets.one(read_count - 1).ok_or(Error::Corrupted)?;
+        let mut raw_data = std::mem::take(&mut self.scratch);
+        self.seeker.read_into(
+            block_entry.file_pos + u64::from(first_sector_offset),
+            u64::from(last_sector.0 + last_sector.1 - first_sector_offset),
+            &mut raw_data,
         )?;
 
-        let mut result = Vec::with_capacity(block_entry.uncompressed_size as usize);
+        // the size in the block table is not trusted for more than a reasonable preallocation
+        let result_size = block_entry.uncompressed_size.min(max_size);
+        let mut result = Vec::with_capacity(result_size.min(MAX_PREALLOCATION) as usize);
 
-        let sector_size = self.seeker.info().sector_size;
-        let sector_count = sector_offsets.count();
-        let first_sector_offset = sector_offsets.one(0).unwrap().0;
-        for i in 0..sector_count {
-            let sector_offset = sector_offsets.one(i).unwrap();
+        for i in 0..read_count {
+            let sector_offset = sector_offsets.one(i).ok_or(Error::Corrupted)?;
             let slice_start = (sector_offset.0 - first_sector_offset) as usize;
             let slice_end = slice_start + sector_offset.1 as usize;
+            let raw_sector = raw_data
+                .get_mut(slice_start..slice_end)
+                .ok_or(Error::Corrupted)?;
+
+            if let Some(key) = encryption_key {
+                decrypt_mpq_block(raw_sector, key.wrapping_add(i as u32));
+            }
+
+            if let Some(checksums) = &sector_checksums {
+                verify_sector_checksum(
+                    raw_sector,
+                    None,
+                    checksums.get(i).copied().unwrap_or(0),
+                    i,
+                )?;
+            }
 
             // if this is the last sector, then its size will be less than
             // one archive sector size, so account for that
-            let uncompressed_size = if (i + 1) == sector_count {
-                let size = block_entry.uncompressed_size % sector_size;
-
-                if size == 0 {
-                    sector_size
-                } else {
-                    size
-                }
+            let uncompressed_size = if options.skip_decompression {
+                raw_sector.len() as u64
             } else {
-                sector_size
+                sector_size.min(block_entry.uncompressed_size - i as u64 * sector_size)
             };
 
-            // decode the block and append it to the final result buffer
-            let decoded_sector = decode_mpq_block(
-                &raw_data[slice_start..slice_end],
+            // decode the sector straight into the final result buffer
+            let sector_start = result.len();
+            block_entry.decode_decrypted_sector_append(
+                raw_sector,
                 uncompressed_size,
-                encryption_key.map(|k| k + i as u32),
+                &mut result,
             )?;
 
-            result.extend(decoded_sector.iter());
+            if let Some(hooks) = self.hooks.get() {
+                hooks.on_sector_decoded(name, i, &result[sector_start..]);
+            }
+        }
+
+        // very large buffers are not kept around
+        if raw_data.capacity() <= MAX_SCRATCH_SIZE {
+            self.scratch = raw_data;
+        }
+
+        result.truncate(max_size.min(usize::MAX as u64) as usize);
+
+        if !options.skip_decompression && result.len() as u64 == block_entry.uncompressed_size {
+            self.verify_attributes(name, hash_entry.block_index as usize, &Digests::of(&result))?;
+        }
+
+        if let Some(hooks) = self.hooks.get() {
+            hooks.on_file_read(name, &result);
         }
 
         Ok(result)
     }
 
-    /// If the archive contains a `(listfile)`, this will method
-    /// parse it and return a `Vec` containing all known filenames.
-    pub fn files(&mut self) -> Option<Vec<String>> {
-        let listfile = self.read_file("(listfile)").ok()?;
-
-        let mut list = Vec::new();
-        let mut line_start = 0;
-        for i in 0..listfile.len() {
-            let byte = listfile[i];
+    /// Same as [`read_file`](struct.Archive.html#method.read_file), but returns the contents
+    /// as [`Bytes`](https://docs.rs/bytes), which can be cloned and shared cheaply.
+    ///
+    /// Requires the `bytes` feature.
+    #[cfg(feature = "bytes")]
+    pub fn read_file_bytes(&mut self, name: &str) -> Result<bytes::Bytes, Error> {
+        self.read_file(name).map(bytes::Bytes::from)
+    }
 
-            if byte == b'\r' || byte == b'\n' {
-                if i - line_start > 0 {
-                    let line = &listfile[line_start..i];
-                    let line = std::str::from_utf8(line);
+    /// Reads a file sector by sector, passing every decoded sector to `callback`
+    /// in order, instead of collecting the whole file in memory.
+    ///
+    /// Only one sector of the file is held in memory at a time, which allows very large
+    /// files to be hashed or streamed elsewhere. If `callback` returns an error,
+    /// reading stops and the error is returned.
+    ///
+    /// Uses the same filename resolution as [`read_file`](struct.Archive.html#method.read_file).
+    pub fn read_file_chunked<F>(&mut self, name: &str, mut callback: F) -> Result<(), Error>
+    where
+        F: FnMut(&[u8]) -> Result<(), Error>,
+    {
+        let sectors = self.file_sectors(name)?;
+        let mut digests = DigestHasher::default();
 
-                    if let Ok(line) = line {
-                        list.push(line.to_string());
-                    }
-                }
+        for i in 0..sectors.offsets.count() {
+            let decoded_sector = self.decode_file_sector(name, &sectors, i)?;
 
-                line_start = i + 1;
+            if self.verified_attributes.is_some() {
+                digests.update(&decoded_sector);
             }
+
+            callback(&decoded_sector)?;
         }
 
-        Some(list)
+        self.verify_attributes(name, sectors.block_index, &digests.finish())
     }
 
-    // Returns the start of the archive in the reader, which is the MPQ header,
-    // relative to the beginning of the reader.
-    pub fn start(&self) -> u64 {
-        self.seeker.info().header_offset
+    /// Decodes a file sector by sector straight into `writer`, e.g. a file, a socket or a hasher,
+    /// without holding its whole contents in memory. Returns the number of bytes written.
+    ///
+    /// If reading fails midway, the data written so far is left in `writer`.
+    /// Uses the same filename resolution as [`read_file`](struct.Archive.html#method.read_file).
+    pub fn extract_to<W>(&mut self, name: &str, mut writer: W) -> Result<u64, Error>
+    where
+        W: Write,
+    {
+        let mut written = 0;
+        self.read_file_chunked(name, |sector| {
+            writer.write_all(sector)?;
+            written += sector.len() as u64;
+
+            Ok(())
+        })?;
+        writer.flush()?;
+
+        Ok(written)
     }
 
-    // Returns the end of the archive in the reader, relative to the beginning of the reader.
-    pub fn end(&self) -> u64 {
-        self.seeker.info().header_offset + self.seeker.info().archive_size
+    /// Reads `len` bytes of a file, starting at `offset` in its decoded contents,
+    /// e.g. to inspect the header of a large file. Only the sectors overlapping the range are decoded.
+    ///
+    /// The range is cut off at the end of the file, so less data than requested may be returned.
+    /// The contents are only verified against the `(attributes)` if the range covers the whole file.
+    ///
+    /// Uses the same filename resolution as [`read_file`](struct.Archive.html#method.read_file).
+    pub fn read_file_range(&mut self, name: &str, offset: u64, len: u64) -> Result<Vec<u8>, Error> {
+        let sectors = self.file_sectors(name)?;
+        let file_size = sectors.block_entry.uncompressed_size;
+        let sector_size = sectors.sector_size;
+
+        let start = offset.min(file_size);
+        let end = offset.saturating_add(len).min(file_size);
+        let mut result = Vec::with_capacity((end - start).min(MAX_PREALLOCATION) as usize);
+        if start == end {
+            return Ok(result);
+        }
+
+        for i in start / sector_size..=(end - 1) / sector_size {
+            let decoded_sector = self.decode_file_sector(name, &sectors, i as usize)?;
+
+            let sector_start = i * sector_size;
+            let from = start.saturating_sub(sector_start) as usize;
+            let to = (end - sector_start).min(sector_size) as usize;
+            result.extend_from_slice(decoded_sector.get(from..to).ok_or(Error::Corrupted)?);
+        }
+
+        if start == 0 && end == file_size {
+            self.verify_attributes(name, sectors.block_index, &Digests::of(&result))?;
+        }
+
+        Ok(result)
     }
 
-    // Returns the size of the archive as specified in the MPQ header.
-    pub fn size(&self) -> u64 {
-        self.seeker.info().archive_size
+    /// Opens a file for streaming, returning an [MpqFile](struct.MpqFile.html) which implements
+    /// [Read] and [Seek] and decodes the file's sectors on demand, e.g. to feed large files into
+    /// parsers without decoding them completely up front.
+    ///
+    /// Uses the same filename resolution as [`read_file`](struct.Archive.html#method.read_file).
+    pub fn open_file(&mut self, name: &str) -> Result<MpqFile<'_, R>, Error> {
+        let sectors = self.file_sectors(name)?;
+
+        Ok(MpqFile::new(self, name, sectors))
     }
 
-    // Returns a mutable reference to the underlying reader.
-    pub fn reader(&mut self) -> &mut R {
-        self.seeker.reader()
+    /// Resolves a file and reads its sector offsets and checksums,
+    /// so that its sectors can be decoded one by one.
+    fn file_sectors(&mut self, name: &str) -> Result<FileSectors, Error> {
+        let (block_index, block_entry, encryption_key) = self.resolve_file(name)?;
+
+        self.block_sectors(block_index, block_entry, encryption_key)
+    }
+
+    /// Reads the sector offsets and checksums of a block table entry.
+    fn block_sectors(
+        &mut self,
+        block_index: usize,
+        block_entry: BlockEntry,
+        encryption_key: Option<u32>,
+    ) -> Result<FileSectors, Error> {
+        self.seeker
+            .prefetch(block_entry.file_pos, block_entry.compressed_size)?;
+
+        let offsets = SectorOffsets::from_reader(
+            &mut self.seeker,
+            &block_entry,
+            encryption_key.map(|k| k.wrapping_sub(1)),
+        )?;
+        let checksums = offsets.read_checksums(&mut self.seeker, &block_entry)?;
+        let sector_size = block_entry.sector_size(self.seeker.info().sector_size);
+
+        Ok(FileSectors {
+            block_index,
+            block_entry,
+            encryption_key,
+            offsets,
+            checksums,
+            sector_size,
+        })
+    }
+
+    /// Reads and decodes a single sector of a file, verifying its checksum if it has one.
+    pub(crate) fn decode_file_sector(
+        &mut self,
+        name: &str,
+        sectors: &FileSectors,
+        index: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let block_entry = &sectors.block_entry;
+        let sector_key = sectors.encryption_key.map(|k| k.wrapping_add(index as u32));
+
+        let sector_offset = sectors.offsets.one(index).ok_or(Error::Corrupted)?;
+        let raw_sector = self.seeker.read(
+            block_entry.file_pos + u64::from(sector_offset.0),
+            u64::from(sector_offset.1),
+        )?;
+
+        if let Some(checksums) = &sectors.checksums {
+            verify_sector_checksum(
+                &raw_sector,
+                sector_key,
+                checksums.get(index).copied().unwrap_or(0),
+                index,
+            )?;
+        }
+
+        let decoded_sector = block_entry
+            .decode_sector(&raw_sector, sectors.uncompressed_size(index), sector_key)?
+            .into_owned();
+
+        if let Some(hooks) = self.hooks.get() {
+            hooks.on_sector_decoded(name, index, &decoded_sector);
+        }
+
+        Ok(decoded_sector)
+    }
+
+    /// Reads the sectors of a file as they are stored in the archive, along with its
+    /// sector offset table, e.g. to debug decoding failures or analyze protection schemes.
+    ///
+    /// Sectors and the sector offset table are decrypted, but not decompressed.
+    /// Files which are neither compressed nor imploded have no sector offset table
+    /// in the archive, in which case the offsets are calculated from the sector size.
+    ///
+    /// Uses the same filename resolution as [`read_file`](struct.Archive.html#method.read_file).
+    pub fn read_file_sectors(&mut self, name: &str) -> Result<RawSectors, Error> {
+        let (_, block_entry, encryption_key) = self.resolve_file(name)?;
+        let sector_offsets = SectorOffsets::from_reader(
+            &mut self.seeker,
+            &block_entry,
+            encryption_key.map(|k| k.wrapping_sub(1)),
+        )?;
+
+        let mut sectors = Vec::with_capacity(sector_offsets.count());
+        for i in 0..sector_offsets.count() {
+            let sector_offset = sector_offsets.one(i).ok_or(Error::Corrupted)?;
+            let mut sector = self.seeker.read(
+                block_entry.file_pos + u64::from(sector_offset.0),
+                u64::from(sector_offset.1),
+            )?;
+
+            if let Some(key) = encryption_key {
+                decrypt_mpq_block(&mut sector, key.wrapping_add(i as u32));
+            }
+
+            sectors.push(sector);
+        }
+
+        Ok(RawSectors {
+            offsets: sector_offsets.offsets().to_vec(),
+            sectors,
+        })
+    }
+
+    /// Checks the digests of a file's contents against the loaded `(attributes)`, if any.
+    pub(crate) fn verify_attributes(
+        &self,
+        name: &str,
+        block_index: usize,
+        digests: &Digests,
+    ) -> Result<(), Error> {
+        match &self.verified_attributes {
+            Some(attributes) if !attributes.matches(block_index, digests) => {
+                Err(Error::AttributesMismatch {
+                    name: name.to_string(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Finds the block index and block table entry of a file,
+    /// and calculates its encryption key, if it is encrypted.
+    fn resolve_file(&mut self, name: &str) -> Result<(usize, BlockEntry, Option<u32>), Error> {
+        self.refresh()?;
+
+        let lookup = self.lookup(name);
+        let hash_entry = self
+            .find_lookup_entries(&lookup)
+            .into_iter()
+            .find(|entry| entry.locale == 0)
+            .ok_or(Error::FileNotFound)?;
+        let block_entry = self
+            .block_table
+            .get(hash_entry.block_index as usize)
+            .ok_or(Error::FileNotFound)?;
+
+        let encryption_key = if block_entry.is_encrypted() {
+            Some(lookup.file_key(block_entry))
+        } else {
+            None
+        };
+
+        Ok((
+            hash_entry.block_index as usize,
+            block_entry.clone(),
+            encryption_key,
+        ))
+    }
+
+    /// Returns the metadata of a file, such as its sizes, flags, locale and position,
+    /// without reading its contents.
+    ///
+    /// Uses the same filename resolution as [`read_file`](struct.Archive.html#method.read_file).
+    pub fn file_info<N>(&self, name: &N) -> Option<FileInfo>
+    where
+        N: AsFileName + ?Sized,
+    {
+        let hash_entry = self
+            .find_lookup_entries(&self.lookup(name))
+            .into_iter()
+            .find(|entry| entry.locale == 0)?;
+        let block_index = hash_entry.block_index as usize;
+        let block_entry = self.block_table.get(block_index)?;
+
+        Some(FileInfo::new(block_index, block_entry, Some(&hash_entry)))
+    }
+
+    /// Whether the archive contains a file, using the same filename resolution as
+    /// [`read_file`](struct.Archive.html#method.read_file).
+    pub fn contains<N>(&self, name: &N) -> bool
+    where
+        N: AsFileName + ?Sized,
+    {
+        self.file_info(name).is_some_and(|info| info.exists())
+    }
+
+    /// Applies the configured normalizations to a name, keeping its precomputed hashes
+    /// only if they still apply to the normalized name.
+    fn lookup<'n, N>(&self, name: &'n N) -> Lookup<'n>
+    where
+        N: AsFileName + ?Sized,
+    {
+        let lookup_name = self.options.lookup_name(name.name());
+        let precomputed = match lookup_name {
+            Cow::Borrowed(_) if !self.options.case_sensitive => name.precomputed(),
+            _ => None,
+        };
+
+        Lookup {
+            name: lookup_name,
+            precomputed,
+        }
+    }
+
+    fn find_lookup_entries(&self, lookup: &Lookup) -> Vec<HashEntry> {
+        match lookup.precomputed {
+            Some(file_name) => self
+                .hash_table
+                .find_entries_hashed(&lookup.name, file_name.hash()),
+            None => self
+                .hash_table
+                .find_entries(&lookup.name, self.options.case_sensitive),
+        }
+    }
+
+    /// Returns every locale for which a file exists, in the order of its hash table entries.
+    ///
+    /// The neutral locale is `0`. Archives with a HET table have no locale information,
+    /// so their files are only ever reported with the neutral locale.
+    pub fn file_locales(&self, name: &str) -> Vec<u16> {
+        let name = self.options.lookup_name(name);
+        let mut locales = Vec::new();
+
+        for hash_entry in self
+            .hash_table
+            .find_entries(&name, self.options.case_sensitive)
+        {
+            let exists = self
+                .block_table
+                .get(hash_entry.block_index as usize)
+                .is_some_and(|block_entry| block_entry.flags & MPQ_FILE_EXISTS != 0);
+
+            if exists && !locales.contains(&hash_entry.locale) {
+                locales.push(hash_entry.locale);
+            }
+        }
+
+        locales
+    }
+
+    /// Returns the metadata of every entry in the block table, in order,
+    /// including entries whose names are not known.
+    pub fn blocks(&self) -> impl Iterator<Item = FileInfo> + '_ {
+        let block_count = self.block_table.entries().len();
+        let mut hash_entries = vec![None; block_count];
+        for hash_entry in self.hash_table.entries() {
+            let index = hash_entry.block_index as usize;
+
+            if index < block_count && hash_entries[index].is_none() {
+                hash_entries[index] = Some(hash_entry);
+            }
+        }
+
+        self.block_table
+            .entries()
+            .iter()
+            .enumerate()
+            .map(move |(index, entry)| FileInfo::new(index, entry, hash_entries[index]))
+    }
+
+    /// Lists all files which are encrypted, but whose name is not known.
+    ///
+    /// Since the encryption key of a file is derived from its name, these files
+    /// cannot be decrypted unless their key is supplied with [`ReadOptions`](struct.ReadOptions.html).
+    ///
+    /// Names are taken from the archive's `(listfile)`, if it has one,
+    /// and from the additional `names` supplied by the caller. Technical files,
+    /// such as `(listfile)` itself, are always considered known.
+    pub fn unresolved_encrypted_files<S: AsRef<str>>(
+        &mut self,
+        names: &[S],
+    ) -> Vec<UnresolvedFile> {
+        let listfile = self.files().unwrap_or_default();
+        let known_names = listfile
+            .iter()
+            .map(|name| name.as_str())
+            .chain(TECHNICAL_FILE_NAMES.iter().copied())
+            .chain(names.iter().map(|name| name.as_ref()));
+
+        let block_count = self.block_table.entries().len();
+        let mut resolved = vec![false; block_count];
+        for name in known_names {
+            let name = self.options.lookup_name(name);
+
+            for hash_entry in self
+                .hash_table
+                .find_entries(&name, self.options.case_sensitive)
+            {
+                if let Some(resolved) = resolved.get_mut(hash_entry.block_index as usize) {
+                    *resolved = true;
+                }
+            }
+        }
+
+        let mut referenced = vec![false; block_count];
+        for hash_entry in self.hash_table.entries() {
+            if let Some(referenced) = referenced.get_mut(hash_entry.block_index as usize) {
+                *referenced = true;
+            }
+        }
+
+        self.blocks()
+            .filter(|info| info.exists() && info.is_encrypted() && !resolved[info.block_index()])
+            .map(|info| UnresolvedFile {
+                info,
+                reason: if referenced[info.block_index()] {
+                    UnresolvedReason::NameUnknown
+                } else {
+                    UnresolvedReason::Unreferenced
+                },
+            })
+            .collect()
+    }
+
+    /// Builds a tree of directories from the names of all known files, i.e. those in the `(listfile)`
+    /// and those registered with [add_known_names](struct.Archive.html#method.add_known_names).
+    ///
+    /// Names are split on backslashes. Names of files that do not exist in the archive are skipped,
+    /// and every file is included only once.
+    pub fn directory_tree(&mut self) -> Result<Directory, Error> {
+        let mut names = self.files_result()?.unwrap_or_default();
+        names.extend(self.known_names.iter().cloned());
+
+        let mut root = Directory::new("");
+        let mut seen = HashSet::new();
+        for name in names {
+            if let Some(info) = self.file_info(&name) {
+                if seen.insert(info.block_index()) {
+                    root.insert(&name, info);
+                }
+            }
+        }
+        root.sort();
+
+        Ok(root)
+    }
+
+    /// Creates a [NameMatcher](struct.NameMatcher.html) for finding the names of hash table entries
+    /// whose names are not known yet.
+    ///
+    /// Names are known if they are listed in the `(listfile)` or were registered with
+    /// [add_known_names](struct.Archive.html#method.add_known_names). Technical files,
+    /// such as `(listfile)` itself, are always considered known.
+    pub fn name_matcher(&mut self) -> Result<NameMatcher, Error> {
+        let listfile = self.listfile()?;
+        let known_names = listfile
+            .iter()
+            .flat_map(|listfile| listfile.iter())
+            .chain(TECHNICAL_FILE_NAMES.iter().copied())
+            .chain(self.known_names.iter().map(|name| name.as_str()));
+
+        let mut resolved = HashSet::new();
+        for name in known_names {
+            let name = self.options.lookup_name(name);
+
+            for hash_entry in self
+                .hash_table
+                .find_entries(&name, self.options.case_sensitive)
+            {
+                resolved.insert((hash_entry.hash_a, hash_entry.hash_b));
+            }
+        }
+
+        let block_count = self.block_table.entries().len();
+        let mut unresolved = HashSet::new();
+        for hash_entry in self.hash_table.entries() {
+            let key = (hash_entry.hash_a, hash_entry.hash_b);
+
+            if (hash_entry.block_index as usize) < block_count && !resolved.contains(&key) {
+                unresolved.insert(key);
+            }
+        }
+
+        Ok(NameMatcher::new(unresolved, self.options))
+    }
+
+    /// If the archive contains a `(listfile)`, this will method
+    /// parse it and return a `Vec` containing all known filenames.
+    ///
+    /// Returns `None` both if the archive has no `(listfile)` and if it could not
+    /// be read. Use [`files_result`](struct.Archive.html#method.files_result)
+    /// to distinguish between the two.
+    pub fn files(&mut self) -> Option<Vec<String>> {
+        self.files_result().ok()?
+    }
+
+    /// Returns an iterator over the names and metadata of all files whose names are known,
+    /// i.e. those listed in the `(listfile)` and those registered with
+    /// [`add_known_names`](struct.Archive.html#method.add_known_names).
+    ///
+    /// Unlike [`files`](struct.Archive.html#method.files), names are not collected up front,
+    /// but looked up one by one while iterating. The `(listfile)` is only listed if
+    /// [`OpenOptions::read_listfile`](struct.OpenOptions.html#structfield.read_listfile) is set,
+    /// and is the one read when the archive was opened or last reloaded, including reloads after
+    /// in-place edits and those triggered by `auto_reload`. Since this method does not read
+    /// the archive, it cannot detect changes itself, so the archive should be reloaded
+    /// before iterating if the underlying reader may have changed.
+    ///
+    /// Files which do not exist are skipped, and every file is yielded only once.
+    ///
+    /// `&Archive` also implements `IntoIterator`, so `for (name, info) in &archive` works as well.
+    pub fn iter(&self) -> Entries<'_, R> {
+        let listed = self.listfile.iter().flat_map(|listfile| listfile.iter());
+        let known = self.known_names.iter().map(String::as_str);
+
+        Entries::new(self, Box::new(listed.chain(known)))
+    }
+
+    /// Same as [`files`](struct.Archive.html#method.files), but returns `Ok(None)`
+    /// only if the archive has no `(listfile)`, and an error if it exists but could not be read.
+    pub fn files_result(&mut self) -> Result<Option<Vec<String>>, Error> {
+        let listfile = self.listfile()?;
+
+        Ok(listfile.map(|listfile| listfile.iter().map(|name| name.to_string()).collect()))
+    }
+
+    /// Reads the `(listfile)` without parsing it up front. Names can then be scanned
+    /// lazily with [Listfile::iter](struct.Listfile.html#method.iter), without allocating each of them.
+    ///
+    /// Returns `Ok(None)` if the archive has no `(listfile)`.
+    pub fn listfile(&mut self) -> Result<Option<Listfile>, Error> {
+        match self.read_file("(listfile)") {
+            Ok(data) => Ok(Some(Listfile::new(data))),
+            Err(Error::FileNotFound) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Reads many files at once, returning their contents or the error reading them
+    /// in the order of `names`.
+    ///
+    /// The files are read in the order of their positions in the archive rather than in the
+    /// given order, which turns random seeking into mostly sequential reads, e.g. to extract many
+    /// files from a hard disk or network storage. Names are resolved like in
+    /// [`read_file`](struct.Archive.html#method.read_file).
+    pub fn read_many<N>(&mut self, names: &[N]) -> Vec<Result<Vec<u8>, Error>>
+    where
+        N: AsFileName,
+    {
+        // names which cannot be resolved are read last, where they fail without any seeking
+        let mut order: Vec<(u64, usize)> = names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| {
+                let file_pos = self
+                    .file_info(name)
+                    .map_or(u64::MAX, |info| info.file_pos());
+
+                (file_pos, index)
+            })
+            .collect();
+        order.sort_unstable();
+
+        let mut results: Vec<Option<Result<Vec<u8>, Error>>> = names.iter().map(|_| None).collect();
+        for (_, index) in order {
+            results[index] = Some(self.read_file(&names[index]));
+        }
+
+        results.into_iter().flatten().collect()
+    }
+
+    /// Reads all files listed in the `(listfile)` whose names match the glob `pattern`,
+    /// e.g. `units/**/*.txt`, and returns their names and contents.
+    ///
+    /// Matching is case-insensitive, and `/` and `\\` are interchangeable as separators.
+    /// Names in the `(listfile)` which do not exist in the archive are skipped.
+    /// If the archive has no `(listfile)`, nothing is matched.
+    pub fn read_files_matching(&mut self, pattern: &str) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let matcher = compile_glob(pattern)?;
+        let listfile = match self.listfile()? {
+            Some(listfile) => listfile,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut files = Vec::new();
+        for name in listfile.iter() {
+            if !matcher.is_match(glob_name(name)) {
+                continue;
+            }
+
+            match self.read_file(name) {
+                Ok(contents) => files.push((name.to_string(), contents)),
+                Err(Error::FileNotFound) => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Registers file names that are known to exist in the archive, but may be missing
+    /// from its `(listfile)`, e.g. names obtained from an external listfile.
+    ///
+    /// These names are considered by [`find_names`](struct.Archive.html#method.find_names).
+    pub fn add_known_names<I, S>(&mut self, names: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.known_names.extend(names.into_iter().map(Into::into));
+    }
+
+    /// Searches all known file names, i.e. those in the `(listfile)` and those registered with
+    /// [`add_known_names`](struct.Archive.html#method.add_known_names),
+    /// for names matching the regular expression `pattern`.
+    ///
+    /// Only names of files that exist in the archive are returned, each once,
+    /// together with the file's metadata.
+    pub fn find_names(&mut self, pattern: &str) -> Result<Vec<(String, FileInfo)>, Error> {
+        let regex = compile_regex(pattern)?;
+        let mut names = self.files_result()?.unwrap_or_default();
+        names.extend(self.known_names.iter().cloned());
+
+        let mut seen = HashSet::new();
+        let mut found = Vec::new();
+        for name in names {
+            if !regex.is_match(&name) {
+                continue;
+            }
+
+            if let Some(info) = self.file_info(&name) {
+                if seen.insert(info.block_index()) {
+                    found.push((name, info));
+                }
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Extracts all files listed in the `(listfile)` for which `filter` returns `true`
+    /// into the directory `dir`, creating subdirectories as needed.
+    ///
+    /// File names are sanitized so that files are never written outside of `dir`.
+    /// Names which would escape it can instead be rejected by setting
+    /// [`ExtractOptions.path_policy`](struct.ExtractOptions.html#structfield.path_policy).
+    /// Extraction continues past files that fail to be read or written, and
+    /// the outcome for every file is returned in the order they were extracted.
+    ///
+    /// Fails only if the `(listfile)` exists but could not be read. If the archive has no `(listfile)`,
+    /// nothing is extracted.
+    pub fn extract_all<P, F>(&mut self, dir: P, filter: F) -> Result<Vec<ExtractedFile>, Error>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&str) -> bool,
+    {
+        self.extract_all_with_options(dir, filter, ExtractOptions::default())
+    }
+
+    /// Same as [`extract_all`](struct.Archive.html#method.extract_all), but allows
+    /// specifying additional [`ExtractOptions`](struct.ExtractOptions.html).
+    ///
+    /// If timestamps should be preserved but the `(attributes)` cannot be read,
+    /// files are extracted without setting their timestamps.
+    pub fn extract_all_with_options<P, F>(
+        &mut self,
+        dir: P,
+        mut filter: F,
+        options: ExtractOptions,
+    ) -> Result<Vec<ExtractedFile>, Error>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&str) -> bool,
+    {
+        let dir = dir.as_ref();
+        let names = self.files_result()?.unwrap_or_default();
+        let attributes = if options.preserve_timestamps {
+            self.attributes().ok().flatten()
+        } else {
+            None
+        };
+
+        let results = names
+            .into_iter()
+            .filter(|name| filter(name))
+            .map(|name| {
+                let result =
+                    self.extract_file(dir, &name, options.path_policy, attributes.as_ref());
+
+                ExtractedFile { name, result }
+            })
+            .collect();
+
+        Ok(results)
+    }
+
+    fn extract_file(
+        &mut self,
+        dir: &Path,
+        name: &str,
+        path_policy: PathPolicy,
+        attributes: Option<&Attributes>,
+    ) -> Result<PathBuf, Error> {
+        let unsafe_path = || Error::UnsafePath {
+            path: name.to_string(),
+        };
+
+        if path_policy == PathPolicy::Reject && is_unsafe_name(name) {
+            return Err(unsafe_path());
+        }
+
+        let path = sanitize_path(dir, name).ok_or_else(unsafe_path)?;
+        let contents = self.read_file(name)?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, contents)?;
+
+        let modified = attributes.and_then(|attributes| {
+            let info = self.file_info(name)?;
+
+            attributes.modified(info.block_index())
+        });
+        if let Some(modified) = modified {
+            File::options()
+                .write(true)
+                .open(&path)?
+                .set_modified(modified)?;
+        }
+
+        Ok(path)
+    }
+
+    /// Reads and parses the archive's `(attributes)` file.
+    ///
+    /// Returns `Ok(None)` if the archive has no `(attributes)`.
+    pub fn attributes(&mut self) -> Result<Option<Attributes>, Error> {
+        let data = match self.read_file("(attributes)") {
+            Ok(data) => data,
+            Err(Error::FileNotFound) => return Ok(None),
+            Err(error) => return Err(error),
+        };
+
+        let block_count = self.block_table.entries().len();
+
+        Attributes::from_bytes(&data, block_count).map(Some)
+    }
+
+    /// Verifies the archive's weak digital signature, a 512-bit RSA signature made with
+    /// Blizzard's private key, which is stored in the `(signature)` file.
+    ///
+    /// Returns `Ok(None)` if the archive has no `(signature)`, and `Ok(Some(false))`
+    /// if the archive was modified after signing or the signature is malformed.
+    pub fn verify_signature(&mut self) -> Result<Option<bool>, Error> {
+        let info = match self.file_info("(signature)") {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+
+        if info.compressed_size() != SIGNATURE_FILE_SIZE as u64
+            || info.flags() & (MPQ_FILE_IMPLODE | MPQ_FILE_COMPRESS | MPQ_FILE_ENCRYPTED) != 0
+        {
+            return Ok(Some(false));
+        }
+
+        let signature_file = self
+            .seeker
+            .read(info.file_pos(), SIGNATURE_FILE_SIZE as u64)?;
+
+        // the archive is hashed with the whole signature file zeroed out
+        let excluded = info.file_pos()..info.file_pos() + SIGNATURE_FILE_SIZE as u64;
+        let archive_size = self.seeker.info().archive_size;
+
+        let mut hasher = Md5::new();
+        let mut offset = 0;
+        while offset < archive_size {
+            let size = (archive_size - offset).min(MANIFEST_CHUNK_SIZE);
+            let mut chunk = self.seeker.read(offset, size)?;
+
+            let start = excluded.start.clamp(offset, offset + size);
+            let end = excluded.end.clamp(offset, offset + size);
+            chunk[(start - offset) as usize..(end - offset) as usize].fill(0);
+
+            hasher.update(&chunk);
+            offset += size;
+        }
+
+        Ok(Some(verify_weak_signature(
+            &signature_file[SIGNATURE_OFFSET..],
+            &hasher.finalize().into(),
+        )))
+    }
+
+    /// Creates a [Manifest](struct.Manifest.html) of the archive, holding the sizes, flags
+    /// and MD5 hashes of all files listed in the `(listfile)`, and an MD5 hash of the whole archive.
+    ///
+    /// Files whose names are unknown are not included. Fails if any listed file cannot be read.
+    pub fn manifest(&mut self) -> Result<Manifest, Error> {
+        let mut names = self.files_result()?.unwrap_or_default();
+        names.sort();
+        names.dedup();
+
+        let mut files = Vec::with_capacity(names.len());
+        for name in names {
+            let contents = self.read_file(&name)?;
+            let info = self.file_info(&name).ok_or(Error::FileNotFound)?;
+
+            files.push(ManifestEntry {
+                md5: Md5::digest(&contents).into(),
+                size: contents.len() as u64,
+                compressed_size: info.compressed_size(),
+                flags: info.flags(),
+                name,
+            });
+        }
+
+        // the header may claim a larger size than is actually available
+        let info = self.seeker.info();
+        let archive_size = info
+            .archive_size
+            .min(info.file_size.saturating_sub(info.header_offset));
+
+        let mut hasher = Md5::new();
+        let mut offset = 0;
+        while offset < archive_size {
+            let size = (archive_size - offset).min(MANIFEST_CHUNK_SIZE);
+            hasher.update(self.seeker.read(offset, size)?);
+            offset += size;
+        }
+
+        Ok(Manifest {
+            archive_md5: hasher.finalize().into(),
+            archive_size,
+            files,
+        })
+    }
+
+    /// Checks whether this archive is logically identical to the archive described by `expected`,
+    /// e.g. one parsed from a stored manifest or created from another archive, and returns the
+    /// first [Divergence](enum.Divergence.html) if it is not.
+    ///
+    /// See [Manifest.compare](struct.Manifest.html#method.compare) for what is compared.
+    pub fn verify_against(&mut self, expected: &Manifest) -> Result<Option<Divergence>, Error> {
+        let actual = self.manifest()?;
+
+        Ok(expected.compare(&actual))
+    }
+
+    /// Decodes every file in the archive and checks it against its sector checksums and the
+    /// `(attributes)`, if the archive has them, e.g. to validate an uploaded archive.
+    ///
+    /// Unlike reading files one by one, this does not stop at the first problem, but returns
+    /// a report for every existing entry of the block table, in order. Encrypted files can only be
+    /// decoded if their name is known from the `(listfile)` or
+    /// [add_known_names](struct.Archive.html#method.add_known_names), and are otherwise reported
+    /// with [Error::UnknownName](enum.Error.html). With the `parallel` feature, files are decoded
+    /// on all available threads. Fails only if reading the archive itself fails.
+    pub fn verify_all(&mut self) -> Result<Vec<FileVerification>, Error> {
+        self.refresh()?;
+
+        let mut names = self.files().unwrap_or_default();
+        names.extend(self.known_names.iter().cloned());
+        names.extend(TECHNICAL_FILE_NAMES.iter().map(|name| name.to_string()));
+
+        let infos: Vec<FileInfo> = self.blocks().collect();
+        let mut block_names = vec![None; infos.len()];
+        for name in names {
+            let lookup_name = self.options.lookup_name(&name).into_owned();
+
+            for hash_entry in self
+                .hash_table
+                .find_entries(&lookup_name, self.options.case_sensitive)
+            {
+                if let Some(block_name @ None) =
+                    block_names.get_mut(hash_entry.block_index as usize)
+                {
+                    *block_name = Some(lookup_name.clone());
+                }
+            }
+        }
+
+        // a damaged (attributes) file is reported like any other file
+        let attributes = self.attributes().ok().flatten();
+
+        let mut report = Vec::with_capacity(infos.len());
+        let mut batch = Vec::new();
+        let mut batch_size = 0;
+        for (info, name) in infos.into_iter().zip(block_names) {
+            if !info.exists() {
+                continue;
+            }
+
+            let block_index = info.block_index();
+            let block_entry = self.block_table.entries()[block_index].clone();
+            let encryption_key = match (&name, block_entry.is_encrypted()) {
+                (_, false) => None,
+                (Some(name), true) => Some(calculate_file_key(
+                    name,
+                    block_entry.file_pos as u32,
+                    block_entry.uncompressed_size as u32,
+                    block_entry.is_key_adjusted(),
+                )),
+                (None, true) => {
+                    report.push(FileVerification {
+                        info,
+                        name,
+                        error: Some(Error::UnknownName { block_index }),
+                    });
+                    continue;
+                }
+            };
+
+            let job = self
+                .block_sectors(block_index, block_entry, encryption_key)
+                .and_then(|sectors| Ok((sectors, self.read_stored(block_index)?)));
+            match job {
+                Ok((sectors, stored)) => {
+                    batch_size += stored.len() as u64;
+                    batch.push(VerifyJob {
+                        info,
+                        name,
+                        sectors,
+                        stored,
+                    });
+                }
+                // errors of the reader itself are not a problem of the file
+                Err(error @ Error::IoError { .. }) => return Err(error),
+                Err(error) => report.push(FileVerification {
+                    info,
+                    name,
+                    error: Some(error),
+                }),
+            }
+
+            if batch_size >= VERIFY_BATCH_SIZE {
+                report.extend(verify_batch(
+                    std::mem::take(&mut batch),
+                    attributes.as_ref(),
+                ));
+                batch_size = 0;
+            }
+        }
+        report.extend(verify_batch(batch, attributes.as_ref()));
+
+        report.sort_by_key(|verification| verification.info.block_index());
+
+        Ok(report)
+    }
+
+    /// Checks the archive for the structural essentials of a playable Warcraft III map,
+    /// and returns every issue that was found.
+    ///
+    /// Checks for the `HM3W` preamble preceding the archive, the presence of `war3map.w3i`
+    /// and `war3map.wts`, a readable JASS or Lua map script, and a `(listfile)` naming all files.
+    /// Fails only if reading the archive itself fails.
+    pub fn check_map(&mut self) -> Result<Vec<MapIssue>, Error> {
+        let mut issues = Vec::new();
+
+        let header_offset = self.seeker.info().header_offset;
+        let preamble = if header_offset >= MAP_PREAMBLE_MAGIC.len() as u64 {
+            Some(
+                self.seeker
+                    .read_absolute(0, MAP_PREAMBLE_MAGIC.len() as u64)?,
+            )
+        } else {
+            None
+        };
+
+        if preamble.as_deref() != Some(&MAP_PREAMBLE_MAGIC[..]) {
+            issues.push(MapIssue::MissingPreamble);
+        } else if header_offset != HEADER_BOUNDARY {
+            issues.push(MapIssue::UnexpectedHeaderOffset {
+                offset: header_offset,
+            });
+        }
+
+        for name in MAP_REQUIRED_FILES.iter() {
+            if self.file_info(name).is_none() {
+                issues.push(MapIssue::MissingFile {
+                    name: name.to_string(),
+                });
+            }
+        }
+
+        let script = MAP_SCRIPT_NAMES
+            .iter()
+            .find(|name| self.file_info(name).is_some());
+        match script {
+            Some(name) => {
+                if let Err(error) = self.read_file(name) {
+                    issues.push(MapIssue::UnreadableScript {
+                        name: name.to_string(),
+                        message: error.to_string(),
+                    });
+                }
+            }
+            None => issues.push(MapIssue::MissingScript),
+        }
+
+        if self.listfile()?.is_none() {
+            issues.push(MapIssue::MissingListfile);
+        } else {
+            let count = self.name_matcher()?.remaining();
+
+            if count > 0 {
+                issues.push(MapIssue::UnlistedFiles { count });
+            }
+        }
+
+        Ok(issues)
+    }
+
+    /// Sets [Hooks](trait.Hooks.html) which are notified of files and sectors read from the archive.
+    pub fn set_hooks(&mut self, hooks: Option<Arc<dyn Hooks>>) {
+        self.hooks = HookSlot::new(hooks);
+    }
+
+    /// Reads the data of a block table entry as it is stored in the archive.
+    pub(crate) fn read_stored(&mut self, block_index: usize) -> Result<Vec<u8>, Error> {
+        let block_entry = self
+            .block_table
+            .get(block_index)
+            .ok_or(Error::FileNotFound)?;
+
+        self.seeker
+            .read(block_entry.file_pos, block_entry.compressed_size)
+    }
+
+    pub(crate) fn known_names(&self) -> &[String] {
+        &self.known_names
+    }
+
+    pub(crate) fn hash_table(&self) -> &FileHashTable {
+        &self.hash_table
+    }
+
+    pub(crate) fn block_table(&self) -> &FileBlockTable {
+        &self.block_table
+    }
+
+    pub(crate) fn seeker(&mut self) -> &mut Seeker<R> {
+        &mut self.seeker
+    }
+
+    /// Returns the start of the archive in the reader, which is the MPQ header,
+    /// relative to the beginning of the reader.
+    pub fn start(&self) -> u64 {
+        self.seeker.info().header_offset
+    }
+
+    /// Returns the end of the archive in the reader, relative to the beginning of the reader.
+    pub fn end(&self) -> u64 {
+        self.seeker.info().header_offset + self.seeker.info().archive_size
+    }
+
+    /// Returns the size of the archive as specified in the MPQ header.
+    pub fn size(&self) -> u64 {
+        self.seeker.info().archive_size
+    }
+
+    /// Returns information about the location and layout of the archive,
+    /// such as its sector size, the offset of its header and the locations of its tables.
+    pub fn info(&self) -> &ArchiveInfo {
+        self.seeker.info()
+    }
+
+    /// Returns the size in bytes of the sectors that files in the archive are split into.
+    pub fn sector_size(&self) -> u64 {
+        self.seeker.info().sector_size
+    }
+
+    /// Returns the number of entries in the hash table, including free entries.
+    pub fn hash_table_entries(&self) -> u64 {
+        self.seeker.info().hash_table_info.entries
+    }
+
+    /// Returns the number of entries in the block table.
+    pub fn block_table_entries(&self) -> u64 {
+        self.seeker.info().block_table_info.entries
+    }
+
+    /// Returns a mutable reference to the underlying reader.
+    pub fn reader(&mut self) -> &mut R {
+        self.seeker.reader()
+    }
+}
+
+impl<R: Read + Write + Seek> Archive<R> {
+    /// Adds a file to the archive in place, replacing an existing file with the same name,
+    /// locale and platform, e.g. to update a single script of a map without repacking it.
+    ///
+    /// This is a shortcut for [Creator::append](struct.Creator.html#method.append) with a single file,
+    /// followed by a [reload](struct.Archive.html#method.reload), so the same limitations apply:
+    /// the file is written after the existing file data, the hash table must have space for it,
+    /// and the archive is left damaged if writing fails midway. The name is normalized according
+    /// to the archive's [`OpenOptions`](struct.OpenOptions.html), so it can be read back by the same name.
+    ///
+    /// The `(attributes)` are not updated, so they no longer match the added file.
+    pub fn insert_file<C>(
+        &mut self,
+        name: &str,
+        contents: C,
+        options: FileOptions,
+    ) -> Result<(), Error>
+    where
+        C: Into<FileContents>,
+    {
+        let name = self.options.lookup_name(name);
+
+        let mut creator = Creator::default();
+        creator.set_slash_policy(SlashPolicy::Preserve);
+        creator.add_file(&name, contents, options);
+        creator.append(self.seeker.reader())?;
+
+        self.reload()
+    }
+
+    /// Removes a file from the archive in place, e.g. to strip debug assets from a release map.
+    ///
+    /// The file's hash table entry is marked as deleted and its block table entry is cleared,
+    /// and its name is removed from the `(listfile)`. Uses the same filename resolution as
+    /// [`read_file`](struct.Archive.html#method.read_file), so only the neutral locale is removed.
+    ///
+    /// The file's data is left in the archive as unused space. As with
+    /// [insert_file](struct.Archive.html#method.insert_file), new tables are written after the file data,
+    /// the archive is left damaged if writing fails midway, and the `(attributes)` are not updated.
+    /// The archive keeps its header version, and HET and BET tables are rebuilt without the file.
+    pub fn remove_file(&mut self, name: &str) -> Result<(), Error> {
+        self.refresh()?;
+
+        let name = self.options.lookup_name(name);
+        let hash_entry = self
+            .hash_table
+            .find_entry(&name, self.options.case_sensitive)
+            .ok_or(Error::FileNotFound)?;
+
+        let mut creator = Creator::default();
+        creator.append_removing(self.seeker.reader(), &[hash_entry])?;
+
+        self.reload()
+    }
+
+    /// Rewrites the archive in place, keeping only the files which are still in use,
+    /// e.g. to reclaim the space left behind by [insert_file](struct.Archive.html#method.insert_file)
+    /// and [remove_file](struct.Archive.html#method.remove_file).
+    ///
+    /// Files are packed back-to-back and the hash table is shrunk to the smallest size that fits them.
+    /// The archive keeps its header offset and format version. Otherwise, this works like
+    /// [Creator::from_archive](struct.Creator.html#method.from_archive), so it fails if the name of any file
+    /// is unknown, and a `(signature)` is dropped. The stored data of all files is held in memory while rewriting.
+    ///
+    /// The archive is overwritten from its start, so it is left damaged if writing fails midway,
+    /// and can then no longer be read. Write a copy with [Creator::write](struct.Creator.html#method.write)
+    /// instead if the archive must survive a failure.
+    ///
+    /// Returns the position in the reader at which the archive now ends.
+    /// Any data following it is left untouched and can be truncated by the caller.
+    pub fn compact(&mut self) -> Result<u64, Error> {
+        self.refresh()?;
+
+        let header_offset = self.seeker.info().header_offset;
+        let mut creator = Creator::from_archive(self)?;
+
+        let writer = self.seeker.reader();
+        writer.seek(SeekFrom::Start(header_offset))?;
+        creator.write(writer)?;
+
+        self.reload()?;
+
+        Ok(self.end())
+    }
+}
+
+/// A reader that can be used behind a trait object, since `dyn Read + Seek` is not a valid type.
+///
+/// Implemented for every type that implements both [Read] and [Seek].
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek + ?Sized> ReadSeek for T {}
+
+/// An archive whose reader is chosen at runtime, e.g. either a file or an in-memory buffer,
+/// without making the type of the reader part of the archive's type.
+pub type DynArchive = Archive<Box<dyn ReadSeek + Send>>;
+
+impl Archive<Box<dyn ReadSeek + Send>> {
+    /// Same as [`open`](struct.Archive.html#method.open), but boxes the `reader`,
+    /// producing a [DynArchive](type.DynArchive.html).
+    pub fn open_dyn<T>(reader: T) -> Result<DynArchive, Error>
+    where
+        T: Read + Seek + Send + 'static,
+    {
+        Archive::open(Box::new(reader))
+    }
+}
+
+impl<'a> Archive<Cursor<&'a [u8]>> {
+    /// Opens an archive from data that is already in memory, e.g. a memory-mapped file.
+    ///
+    /// Files that are stored unencrypted and uncompressed can then be read without
+    /// copying them, using [read_file_borrowed](struct.Archive.html#method.read_file_borrowed).
+    pub fn from_bytes(data: &'a [u8]) -> Result<Archive<Cursor<&'a [u8]>>, Error> {
+        Archive::open(Cursor::new(data))
+    }
+
+    /// Same as [`read_file`](struct.Archive.html#method.read_file), but returns a slice of the
+    /// underlying data instead of a copy if the file is stored unencrypted and uncompressed.
+    ///
+    /// Files are only borrowed if there are no checksums to verify, i.e. if the file has no sector
+    /// checksums and [`OpenOptions::verify_attributes`](struct.OpenOptions.html#structfield.verify_attributes)
+    /// is not set. Otherwise, they are read and verified with `read_file`.
+    pub fn read_file_borrowed(&mut self, name: &str) -> Result<Cow<'a, [u8]>, Error> {
+        let (_, block_entry, encryption_key) = self.resolve_file(name)?;
+
+        let needs_verification =
+            self.options.verify_attributes || block_entry.flags & MPQ_FILE_SECTOR_CRC != 0;
+        if needs_verification
+            || encryption_key.is_some()
+            || block_entry.is_compressed()
+            || block_entry.is_imploded()
+        {
+            return self.read_file(name).map(Cow::Owned);
+        }
+
+        let data: &'a [u8] = self.seeker.reader_ref().get_ref();
+        let start = self.seeker.info().header_offset + block_entry.file_pos;
+        let end = start + block_entry.uncompressed_size;
+
+        let contents = data
+            .get(start as usize..end as usize)
+            .ok_or(Error::Corrupted)?;
+
+        if let Some(hooks) = self.hooks.get() {
+            hooks.on_sector_decoded(name, 0, contents);
+            hooks.on_file_read(name, contents);
+        }
+
+        Ok(Cow::Borrowed(contents))
+    }
+}
+
+impl Archive<File> {
+    /// Opens the MPQ archive at `path` with the specified [`OpenOptions`](struct.OpenOptions.html).
+    ///
+    /// Unlike archives opened with [`open_with_options`](struct.Archive.html#method.open_with_options),
+    /// the modification time of the file is remembered, so that `auto_reload` and
+    /// [`reload_if_modified`](struct.Archive.html#method.reload_if_modified)
+    /// also pick up changes which do not affect the size of the file.
+    pub fn open_path<P: AsRef<Path>>(
+        path: P,
+        options: OpenOptions,
+    ) -> Result<Archive<File>, Error> {
+        let file = File::open(path)?;
+        let modified = file_modified(&file);
+
+        let mut archive = Archive::open_with_options(file, options)?;
+        archive.modified = modified;
+        archive.modified_probe = Some(file_modified);
+
+        Ok(archive)
+    }
+
+    /// Reloads the archive if the backing file's size or modification time
+    /// has changed since it was opened or last reloaded.
+    ///
+    /// If the archive was not opened with [`open_path`](struct.Archive.html#method.open_path),
+    /// the first call only remembers the current modification time, and will
+    /// reload the archive only if its size has changed since it was opened.
+    ///
+    /// Returns `true` if the archive was reloaded. If reloading fails, the modification
+    /// time is not updated, so the next call will try to reload the archive again.
+    pub fn reload_if_modified(&mut self) -> Result<bool, Error> {
+        let metadata = self.seeker.reader().metadata()?;
+        let modified = metadata.modified().ok();
+
+        let size_changed = metadata.len() != self.seeker.info().file_size;
+        let time_changed = match (self.modified, modified) {
+            (Some(old), Some(new)) => old != new,
+            _ => false,
+        };
+
+        if size_changed || time_changed {
+            self.reload()?;
+            self.modified = modified;
+
+            Ok(true)
+        } else {
+            if self.modified.is_none() {
+                self.modified = modified;
+            }
+
+            Ok(false)
+        }
+    }
+}
+
+fn file_modified(file: &File) -> Option<SystemTime> {
+    file.metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{Compression, Creator, FileOptions, FormatVersion};
+
+    const NAMES: [&str; 3] = ["war3map.j", "units\\footman.mdx", "readme.txt"];
+
+    fn contents(name: &str) -> Vec<u8> {
+        name.bytes().cycle().take(name.len() * 300).collect()
+    }
+
+    fn options() -> FileOptions {
+        FileOptions {
+            compression: Compression::Deflate,
+            ..Default::default()
+        }
+    }
+
+    fn fixture(format_version: FormatVersion, names: &[&str]) -> Vec<u8> {
+        let mut creator = Creator::default();
+        creator.set_format_version(format_version);
+        for name in names {
+            creator.add_file(name, contents(name), options());
+        }
+
+        let mut buf = Vec::new();
+        creator.write(Cursor::new(&mut buf)).unwrap();
+
+        buf
+    }
+
+    #[test]
+    fn inserts_files_in_place() {
+        let mut buf = fixture(FormatVersion::V1, &NAMES);
+
+        let mut archive = Archive::open(Cursor::new(&mut buf)).unwrap();
+        archive
+            .insert_file("war3map.j", b"replaced".to_vec(), options())
+            .unwrap();
+        archive
+            .insert_file("added.txt", contents("added.txt"), options())
+            .unwrap();
+        assert_eq!(archive.read_file("war3map.j").unwrap(), b"replaced");
+
+        let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+        assert_eq!(archive.read_file("war3map.j").unwrap(), b"replaced");
+        for name in ["units\\footman.mdx", "readme.txt", "added.txt"] {
+            assert_eq!(archive.read_file(name).unwrap(), contents(name));
+        }
+
+        let mut files = archive.files().unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            ["added.txt", "readme.txt", "units\\footman.mdx", "war3map.j"]
+        );
+    }
+
+    #[test]
+    fn rejects_inserting_into_full_hash_table() {
+        // together with the (listfile), the files fill the smallest hash table
+        let names: Vec<String> = (0..MIN_HASH_TABLE_SIZE - 1)
+            .map(|i| format!("file{}.txt", i))
+            .collect();
+        let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+        let mut buf = fixture(FormatVersion::V1, &names);
+        let original = buf.clone();
+
+        let mut archive = Archive::open(Cursor::new(&mut buf)).unwrap();
+        assert_eq!(archive.hash_table_entries(), MIN_HASH_TABLE_SIZE as u64);
+        assert!(archive
+            .insert_file("added.txt", contents("added.txt"), options())
+            .is_err());
+        assert_eq!(buf, original);
+
+        let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+        for name in names {
+            assert_eq!(archive.read_file(name).unwrap(), contents(name));
+        }
+    }
+
+    #[test]
+    fn removes_files_in_place() {
+        for format_version in [
+            FormatVersion::V1,
+            FormatVersion::V2,
+            FormatVersion::V3,
+            FormatVersion::V4,
+        ] {
+            let mut buf = fixture(format_version, &NAMES);
+            let header_version = Archive::open(Cursor::new(&buf))
+                .unwrap()
+                .info()
+                .header()
+                .format_version();
+
+            let mut archive = Archive::open(Cursor::new(&mut buf)).unwrap();
+            archive.remove_file("readme.txt").unwrap();
+            assert!(archive.file_info("readme.txt").is_none());
+
+            let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+            assert_eq!(archive.info().header().format_version(), header_version);
+            assert!(matches!(
+                archive.read_file("readme.txt"),
+                Err(Error::FileNotFound)
+            ));
+            for name in ["war3map.j", "units\\footman.mdx"] {
+                assert_eq!(archive.read_file(name).unwrap(), contents(name));
+            }
+            assert_eq!(
+                archive.files().unwrap(),
+                ["war3map.j", "units\\footman.mdx"]
+            );
+        }
+    }
+
+    #[test]
+    fn compacts_archive_in_place() {
+        for (format_version, header_version) in [(FormatVersion::V3, 2), (FormatVersion::V4, 3)] {
+            let mut buf = fixture(format_version, &NAMES);
+
+            let mut archive = Archive::open(Cursor::new(&mut buf)).unwrap();
+            archive.remove_file("units\\footman.mdx").unwrap();
+            let size = archive.size();
+            let end = archive.compact().unwrap();
+            assert!(archive.size() < size);
+            buf.truncate(end as usize);
+
+            let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+            assert_eq!(archive.info().header().format_version(), header_version);
+            for name in ["war3map.j", "readme.txt"] {
+                assert_eq!(archive.read_file(name).unwrap(), contents(name));
+            }
+            assert!(archive.file_info("units\\footman.mdx").is_none());
+        }
+    }
+
+    #[test]
+    fn reads_file_ranges() {
+        // spans several sectors of the default size, with a partial last sector
+        let large: Vec<u8> = (0..0x10000 * 3 + 0x1234)
+            .map(|i: u32| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let mut creator = Creator::default();
+        creator.add_file("large.bin", large.clone(), options());
+        let mut buf = Vec::new();
+        creator.write(Cursor::new(&mut buf)).unwrap();
+
+        let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+        let expected = archive.read_file("large.bin").unwrap();
+        assert_eq!(expected, large);
+        let size = expected.len() as u64;
+
+        let mut check = |offset: u64, len: u64, expected: &[u8]| {
+            assert_eq!(
+                archive.read_file_range("large.bin", offset, len).unwrap(),
+                expected
+            );
+        };
+
+        check(0, 100, &expected[..100]);
+        check(0x10000 - 10, 20, &expected[0x10000 - 10..0x10000 + 10]);
+        check(0x10000, 0x10000, &expected[0x10000..0x20000]);
+        check(100, 0x20000, &expected[100..100 + 0x20000]);
+        check(0, u64::MAX, &expected);
+
+        // ranges are cut off at the end of the file
+        check(size - 10, 10, &expected[size as usize - 10..]);
+        check(size - 10, 100, &expected[size as usize - 10..]);
+        check(size, 10, &[]);
+        check(size + 10, 10, &[]);
+        check(u64::MAX, u64::MAX, &[]);
+        check(100, 0, &[]);
+
+        assert!(matches!(
+            archive.read_file_range("missing.bin", 0, 10),
+            Err(Error::FileNotFound)
+        ));
     }
 }
@@ -0,0 +1,204 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use md5::{Digest, Md5};
+
+use super::consts::*;
+use super::error::Error;
+
+/// The number of 100-nanosecond intervals between 1601-01-01 and 1970-01-01,
+/// for converting between Windows `FILETIME`s and Unix time.
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+/// The checksums of a file's uncompressed contents, as stored in `(attributes)`.
+/// Zeroed checksums mean that they are unknown.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Digests {
+    pub crc32: u32,
+    pub md5: [u8; 16],
+}
+
+impl Digests {
+    pub fn of(contents: &[u8]) -> Digests {
+        Digests {
+            crc32: crc32fast::hash(contents),
+            md5: Md5::digest(contents).into(),
+        }
+    }
+}
+
+/// Calculates [Digests](struct.Digests.html) of contents which are processed piece by piece.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DigestHasher {
+    crc32: crc32fast::Hasher,
+    md5: Md5,
+}
+
+impl DigestHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        self.crc32.update(data);
+        self.md5.update(data);
+    }
+
+    pub fn finish(&self) -> Digests {
+        Digests {
+            crc32: self.crc32.clone().finalize(),
+            md5: self.md5.clone().finalize().into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// The contents of an archive's `(attributes)` file, which stores additional metadata
+/// for every entry of the block table, as returned by [attributes](struct.Archive.html#method.attributes).
+///
+/// Each kind of metadata is optional, and values are indexed by block table index
+/// (see [FileInfo::block_index](struct.FileInfo.html#method.block_index)).
+pub struct Attributes {
+    crc32: Option<Vec<u32>>,
+    filetimes: Option<Vec<u64>>,
+    md5: Option<Vec<[u8; 16]>>,
+}
+
+impl Attributes {
+    /// Parses an `(attributes)` file of an archive with `block_count` block table entries.
+    ///
+    /// Some tools write fewer entries than there are blocks, e.g. omitting `(attributes)` itself,
+    /// so truncated arrays are accepted, as long as all present entries are complete.
+    pub(crate) fn from_bytes(data: &[u8], block_count: usize) -> Result<Attributes, Error> {
+        let mut reader = data;
+        let version = reader.read_u32::<LE>()?;
+        let flags = reader.read_u32::<LE>()?;
+
+        if version != ATTRIBUTES_VERSION {
+            return Err(Error::Corrupted);
+        }
+
+        // arrays are stored back-to-back, so the number of entries is the same in each of them
+        let entry_size = [
+            (ATTRIBUTES_CRC32, 4),
+            (ATTRIBUTES_FILETIME, 8),
+            (ATTRIBUTES_MD5, 16),
+        ]
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, size)| size)
+        .sum::<usize>();
+        let count = reader
+            .len()
+            .checked_div(entry_size)
+            .map_or(0, |count| count.min(block_count));
+
+        let mut attributes = Attributes::default();
+
+        if flags & ATTRIBUTES_CRC32 != 0 {
+            let mut crc32 = Vec::with_capacity(count);
+            for _ in 0..count {
+                crc32.push(reader.read_u32::<LE>()?);
+            }
+            attributes.crc32 = Some(crc32);
+        }
+
+        if flags & ATTRIBUTES_FILETIME != 0 {
+            let mut filetimes = Vec::with_capacity(count);
+            for _ in 0..count {
+                filetimes.push(reader.read_u64::<LE>()?);
+            }
+            attributes.filetimes = Some(filetimes);
+        }
+
+        if flags & ATTRIBUTES_MD5 != 0 {
+            let mut md5 = Vec::with_capacity(count);
+            for _ in 0..count {
+                let mut digest = [0u8; 16];
+                std::io::Read::read_exact(&mut reader, &mut digest)?;
+                md5.push(digest);
+            }
+            attributes.md5 = Some(md5);
+        }
+
+        Ok(attributes)
+    }
+
+    /// Creates attributes with the CRC32 and MD5 checksums of every block, in block table order.
+    pub(crate) fn from_digests(digests: &[Digests]) -> Attributes {
+        Attributes {
+            crc32: Some(digests.iter().map(|digests| digests.crc32).collect()),
+            filetimes: None,
+            md5: Some(digests.iter().map(|digests| digests.md5).collect()),
+        }
+    }
+
+    /// Serializes the attributes into the contents of an `(attributes)` file.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.crc32.is_some() {
+            flags |= ATTRIBUTES_CRC32;
+        }
+        if self.filetimes.is_some() {
+            flags |= ATTRIBUTES_FILETIME;
+        }
+        if self.md5.is_some() {
+            flags |= ATTRIBUTES_MD5;
+        }
+
+        // writing into a vector cannot fail
+        let mut data = Vec::new();
+        data.write_u32::<LE>(ATTRIBUTES_VERSION).unwrap();
+        data.write_u32::<LE>(flags).unwrap();
+
+        for crc32 in self.crc32.iter().flatten() {
+            data.write_u32::<LE>(*crc32).unwrap();
+        }
+        for filetime in self.filetimes.iter().flatten() {
+            data.write_u64::<LE>(*filetime).unwrap();
+        }
+        for md5 in self.md5.iter().flatten() {
+            data.extend_from_slice(md5);
+        }
+
+        data
+    }
+
+    /// Checks the checksums of a block's uncompressed contents against the stored ones.
+    /// Checksums which are missing or zero are not checked.
+    pub(crate) fn matches(&self, block_index: usize, digests: &Digests) -> bool {
+        let crc32_matches = self
+            .crc32(block_index)
+            .filter(|crc32| *crc32 != 0)
+            .is_none_or(|crc32| crc32 == digests.crc32);
+        let md5_matches = self
+            .md5(block_index)
+            .filter(|md5| *md5 != [0; 16])
+            .is_none_or(|md5| md5 == digests.md5);
+
+        crc32_matches && md5_matches
+    }
+
+    /// The CRC32 checksum of the uncompressed contents of a block, if known.
+    pub fn crc32(&self, block_index: usize) -> Option<u32> {
+        self.crc32.as_ref()?.get(block_index).copied()
+    }
+
+    /// The raw Windows `FILETIME` of a block, i.e. the number of 100-nanosecond intervals
+    /// since 1601-01-01, if known.
+    pub fn filetime(&self, block_index: usize) -> Option<u64> {
+        self.filetimes.as_ref()?.get(block_index).copied()
+    }
+
+    /// The modification time of a block, if known and representable as a `SystemTime`.
+    /// A `FILETIME` of zero is treated as unknown.
+    pub fn modified(&self, block_index: usize) -> Option<SystemTime> {
+        let filetime = self
+            .filetime(block_index)
+            .filter(|filetime| *filetime != 0)?;
+        let unix_time = filetime.checked_sub(FILETIME_UNIX_EPOCH)?;
+
+        Some(UNIX_EPOCH + Duration::from_nanos(unix_time.checked_mul(100)?))
+    }
+
+    /// The MD5 digest of the uncompressed contents of a block, if known.
+    pub fn md5(&self, block_index: usize) -> Option<[u8; 16]> {
+        self.md5.as_ref()?.get(block_index).copied()
+    }
+}
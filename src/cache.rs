@@ -0,0 +1,137 @@
+use std::cmp::min;
+use std::fs;
+use std::io::Error as IoError;
+use std::path::PathBuf;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use md5::{Digest, Md5};
+
+use super::creator::Compression;
+use super::util::*;
+
+/// Identifies the compression settings that cached sectors were produced with,
+/// so that changing them invalidates existing entries.
+fn compression_settings(compression: Compression) -> &'static str {
+    match compression {
+        Compression::None => "none",
+        Compression::Deflate => "zlib-best",
+        Compression::Bzip2 => "bzip2-best",
+        Compression::Sparse => "sparse",
+        Compression::AdpcmMono => "adpcm-mono-5",
+        Compression::AdpcmStereo => "adpcm-stereo-5",
+    }
+}
+
+#[derive(Debug, Clone)]
+/// An on-disk cache of compressed file data, which a [Creator](struct.Creator.html) consults before
+/// compressing a file, set with [set_compression_cache](struct.Creator.html#method.set_compression_cache).
+///
+/// Entries are keyed by a hash of a file's contents, the sector size and the compression settings,
+/// so the same cache can be shared between builds and projects. Every entry holds the compressed,
+/// but unencrypted sectors of one file.
+///
+/// The cache never causes writing an archive to fail: entries that cannot be read are treated
+/// as missing, and entries that cannot be stored are skipped. Nothing is ever removed from
+/// the cache directory, so it can grow without bound and should be cleaned up externally.
+pub struct CompressionCache {
+    dir: PathBuf,
+}
+
+impl CompressionCache {
+    /// Creates a cache which stores its entries in `dir`.
+    /// The directory is created when the first entry is stored.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> CompressionCache {
+        CompressionCache { dir: dir.into() }
+    }
+
+    /// Returns the compressed sectors of `contents`, either from the cache,
+    /// or by compressing them and storing the result in the cache.
+    pub(crate) fn compress(
+        &self,
+        contents: &[u8],
+        sector_size: u64,
+        compression: Compression,
+    ) -> Vec<Vec<u8>> {
+        let path = self.entry_path(contents, sector_size, compression);
+        let sector_count = sector_count_from_size(contents.len() as u64, sector_size);
+
+        if let Some(sectors) = fs::read(&path)
+            .ok()
+            .and_then(|data| parse_entry(&data, sector_count))
+        {
+            return sectors;
+        }
+
+        let sectors: Vec<Vec<u8>> = (0..sector_count)
+            .map(|i| {
+                let sector_start = i * sector_size;
+                let sector_end = min((i + 1) * sector_size, contents.len() as u64);
+
+                compress_mpq_block(
+                    &contents[sector_start as usize..sector_end as usize],
+                    compression.for_sector(i),
+                )
+                .into_owned()
+            })
+            .collect();
+
+        // a failure to store the entry only means that it will be compressed again next time
+        let _ = self.store(path, &sectors);
+
+        sectors
+    }
+
+    fn entry_path(&self, contents: &[u8], sector_size: u64, compression: Compression) -> PathBuf {
+        let hash = to_hex(&Md5::digest(contents));
+        let settings = compression_settings(compression);
+
+        self.dir
+            .join(format!("{}-{}-{}", hash, sector_size, settings))
+    }
+
+    fn store(&self, path: PathBuf, sectors: &[Vec<u8>]) -> Result<(), IoError> {
+        let mut data = Vec::new();
+        data.write_u32::<LE>(sectors.len() as u32)?;
+        for sector in sectors {
+            data.write_u32::<LE>(sector.len() as u32)?;
+        }
+        for sector in sectors {
+            data.extend_from_slice(sector);
+        }
+
+        // write to a temporary file first, so that concurrent builds never see partial entries
+        fs::create_dir_all(&self.dir)?;
+        let temp_path = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&temp_path, data)?;
+        fs::rename(temp_path, path)
+    }
+}
+
+fn parse_entry(mut data: &[u8], sector_count: u64) -> Option<Vec<Vec<u8>>> {
+    let count = data.read_u32::<LE>().ok()?;
+    if u64::from(count) != sector_count {
+        return None;
+    }
+
+    let mut sizes = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        sizes.push(data.read_u32::<LE>().ok()? as usize);
+    }
+
+    let mut sectors = Vec::with_capacity(count as usize);
+    for size in sizes {
+        if data.len() < size {
+            return None;
+        }
+
+        let (sector, rest) = data.split_at(size);
+        sectors.push(sector.to_vec());
+        data = rest;
+    }
+
+    if !data.is_empty() {
+        return None;
+    }
+
+    Some(sectors)
+}
@@ -1,34 +1,123 @@
-pub(crate) const HEADER_BOUNDARY: u64 = 512;
-pub(crate) const HEADER_MPQ_MAGIC: u32 = 0x1A51_504D;
+//! Constants of the MPQ format, such as header signatures, block table flags,
+//! compression type bytes, hash types and the keys of the tables.
+//!
+//! These are provided for tooling which inspects archives at a lower level than
+//! [Archive](../struct.Archive.html) does.
+
+/// Headers are only searched for at multiples of this offset.
+pub const HEADER_BOUNDARY: u64 = 512;
+/// The signature of the MPQ header, `MPQ\x1A`.
+pub const HEADER_MPQ_MAGIC: u32 = 0x1A51_504D;
 pub(crate) const HEADER_MPQ_SIZE: u64 = 32;
-pub(crate) const HEADER_USER_MAGIC: u32 = 0x1B51_504D;
+pub(crate) const HEADER_MPQ_SIZE_V2: u64 = 44;
+pub(crate) const HEADER_MPQ_SIZE_V3: u64 = 68;
+pub(crate) const HEADER_MPQ_SIZE_V4: u64 = 208;
+/// The signature of the user data header, `MPQ\x1B`, which may precede the MPQ header.
+pub const HEADER_USER_MAGIC: u32 = 0x1B51_504D;
+
+pub(crate) const MAX_FILE_NAME_LENGTH: usize = 259;
+pub(crate) const INVALID_NAME_CHARS: [char; 8] = [':', '*', '?', '"', '<', '>', '|', ';'];
+
+pub(crate) const MAP_PREAMBLE_MAGIC: &[u8; 4] = b"HM3W";
+pub(crate) const MAP_REQUIRED_FILES: [&str; 2] = ["war3map.w3i", "war3map.wts"];
+pub(crate) const MAP_SCRIPT_NAMES: [&str; 4] = [
+    "war3map.j",
+    "scripts\\war3map.j",
+    "war3map.lua",
+    "scripts\\war3map.lua",
+];
+pub(crate) const MAP_MEDIA_EXTENSIONS: [&str; 10] = [
+    "mdx", "mdl", "blp", "tga", "dds", "wav", "mp3", "ogg", "flac", "png",
+];
+
+pub(crate) const TECHNICAL_FILE_NAMES: [&str; 3] = ["(listfile)", "(attributes)", "(signature)"];
+
+pub(crate) const ATTRIBUTES_VERSION: u32 = 100;
+pub(crate) const ATTRIBUTES_CRC32: u32 = 0x0000_0001;
+pub(crate) const ATTRIBUTES_FILETIME: u32 = 0x0000_0002;
+pub(crate) const ATTRIBUTES_MD5: u32 = 0x0000_0004;
+
+/// The `(signature)` file holds 8 reserved bytes, followed by the weak signature.
+pub(crate) const SIGNATURE_FILE_SIZE: usize = 72;
+pub(crate) const SIGNATURE_OFFSET: usize = 8;
+
+pub(crate) const MANIFEST_CHUNK_SIZE: u64 = 0x10_0000;
+
+pub(crate) const INCOMPRESSIBLE_EXTENSIONS: [&str; 15] = [
+    "mp3", "ogg", "flac", "jpg", "jpeg", "png", "gif", "webp", "zip", "gz", "bz2", "7z", "rar",
+    "w3x", "w3m",
+];
+pub(crate) const INCOMPRESSIBLE_ENTROPY: f64 = 7.9;
+pub(crate) const MIN_ENTROPY_SAMPLE_SIZE: usize = 4096;
 
 pub(crate) const MIN_HASH_TABLE_SIZE: usize = 32;
 
-pub(crate) const HASH_TABLE_KEY: u32 = 0xC3AF_3770;
+/// The key of the hash table, i.e. the key hash of `(hash table)`.
+pub const HASH_TABLE_KEY: u32 = 0xC3AF_3770;
 pub(crate) const HASH_TABLE_ENTRY_SIZE: u32 = 16;
-pub(crate) const HASH_TABLE_EMPTY_ENTRY: u32 = 0xFFFF_FFFF;
-pub(crate) const BLOCK_TABLE_KEY: u32 = 0xEC83_B3A3;
+/// The block index of hash table entries which were never used.
+pub const HASH_TABLE_EMPTY_ENTRY: u32 = 0xFFFF_FFFF;
+/// The block index of hash table entries whose file was removed.
+pub const HASH_TABLE_DELETED_ENTRY: u32 = 0xFFFF_FFFE;
+/// The key of the block table, i.e. the key hash of `(block table)`.
+pub const BLOCK_TABLE_KEY: u32 = 0xEC83_B3A3;
 pub(crate) const BLOCK_TABLE_ENTRY_SIZE: u32 = 16;
 
-pub(crate) const MPQ_HASH_TABLE_INDEX: u32 = 0x000;
-pub(crate) const MPQ_HASH_NAME_A: u32 = 0x100;
-pub(crate) const MPQ_HASH_NAME_B: u32 = 0x200;
-pub(crate) const MPQ_HASH_FILE_KEY: u32 = 0x300;
+pub(crate) const HET_TABLE_MAGIC: u32 = 0x1A54_4548;
+pub(crate) const BET_TABLE_MAGIC: u32 = 0x1A54_4542;
+pub(crate) const EXT_TABLE_HEADER_SIZE: u64 = 12;
+pub(crate) const EXT_TABLE_VERSION: u32 = 1;
+pub(crate) const HET_ENTRY_FREE: u8 = 0x00;
+
+/// The hash type used for the position of a name in the hash table.
+pub const MPQ_HASH_TABLE_INDEX: u32 = 0x000;
+/// The hash type of the first name hash stored in hash table entries.
+pub const MPQ_HASH_NAME_A: u32 = 0x100;
+/// The hash type of the second name hash stored in hash table entries.
+pub const MPQ_HASH_NAME_B: u32 = 0x200;
+/// The hash type used for the encryption keys of files.
+pub const MPQ_HASH_FILE_KEY: u32 = 0x300;
 pub(crate) const MPQ_HASH_KEY2_MIX: u32 = 0x400;
 
-pub(crate) const MPQ_FILE_IMPLODE: u32 = 0x0000_0100;
-pub(crate) const MPQ_FILE_COMPRESS: u32 = 0x0000_0200;
-pub(crate) const MPQ_FILE_ENCRYPTED: u32 = 0x0001_0000;
-pub(crate) const MPQ_FILE_ADJUST_KEY: u32 = 0x0002_0000;
-pub(crate) const MPQ_FILE_EXISTS: u32 = 0x8000_0000;
+/// The file is compressed using PKWare DCL as a whole.
+pub const MPQ_FILE_IMPLODE: u32 = 0x0000_0100;
+/// The file's sectors are compressed.
+pub const MPQ_FILE_COMPRESS: u32 = 0x0000_0200;
+/// The file is encrypted.
+pub const MPQ_FILE_ENCRYPTED: u32 = 0x0001_0000;
+/// The encryption key of the file is adjusted by its position and size.
+pub const MPQ_FILE_ADJUST_KEY: u32 = 0x0002_0000;
+/// The file is a patch file.
+pub const MPQ_FILE_PATCH_FILE: u32 = 0x0010_0000;
+/// The file is stored as a single block instead of being split into sectors.
+pub const MPQ_FILE_SINGLE_UNIT: u32 = 0x0100_0000;
+/// The file is a deletion marker.
+pub const MPQ_FILE_DELETE_MARKER: u32 = 0x0200_0000;
+/// The file has checksums of its sectors.
+pub const MPQ_FILE_SECTOR_CRC: u32 = 0x0400_0000;
+/// The block table entry is in use.
+pub const MPQ_FILE_EXISTS: u32 = 0x8000_0000;
 
-pub(crate) const COMPRESSION_IMA_ADPCM_MONO_MONO: u8 = 0x40;
-pub(crate) const COMPRESSION_IMA_ADPCM_MONO_STEREO: u8 = 0x80;
-pub(crate) const COMPRESSION_HUFFMAN: u8 = 0x01;
-pub(crate) const COMPRESSION_ZLIB: u8 = 0x02;
-pub(crate) const COMPRESSION_PKWARE: u8 = 0x08;
-pub(crate) const COMPRESSION_BZIP2: u8 = 0x10;
+/// IMA ADPCM compression of mono audio.
+pub const COMPRESSION_IMA_ADPCM_MONO_MONO: u8 = 0x40;
+/// IMA ADPCM compression of stereo audio.
+pub const COMPRESSION_IMA_ADPCM_MONO_STEREO: u8 = 0x80;
+/// Huffman coding.
+pub const COMPRESSION_HUFFMAN: u8 = 0x01;
+/// Deflate compression with a zlib wrapper.
+pub const COMPRESSION_ZLIB: u8 = 0x02;
+/// PKWare DCL compression.
+pub const COMPRESSION_PKWARE: u8 = 0x08;
+/// Bzip2 compression.
+pub const COMPRESSION_BZIP2: u8 = 0x10;
+/// Sparse (run-length) compression.
+pub const COMPRESSION_SPARSE: u8 = 0x20;
+/// LZMA compression.
+pub const COMPRESSION_LZMA: u8 = 0x12;
+pub(crate) const ADPCM_COMPRESSIONS: u8 =
+    COMPRESSION_IMA_ADPCM_MONO_MONO | COMPRESSION_IMA_ADPCM_MONO_STEREO;
+/// Corresponds to ADPCM compression level 5, which StormLib uses by default.
+pub(crate) const ADPCM_BIT_SHIFT: u8 = 4;
 
 pub(crate) const ASCII_UPPER_LOOKUP_SLASH_INSENSITIVE: [u8; 256] = [
     0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
@@ -67,3 +156,16 @@ pub(crate) const ASCII_UPPER_LOOKUP_SLASH_SENSITIVE: [u8; 256] = [
     0xE0, 0xE1, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA, 0xEB, 0xEC, 0xED, 0xEE, 0xEF,
     0xF0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA, 0xFB, 0xFC, 0xFD, 0xFE, 0xFF,
 ];
+
+pub(crate) const ASCII_IDENTITY_LOOKUP: [u8; 256] = identity_lookup();
+
+const fn identity_lookup() -> [u8; 256] {
+    let mut lookup = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        lookup[i] = i as u8;
+        i += 1;
+    }
+
+    lookup
+}
@@ -0,0 +1,133 @@
+use std::borrow::Cow;
+use std::ops::Deref;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+/// The contents of a file added to a [Creator](struct.Creator.html).
+///
+/// Contents can be created from owned buffers, which are moved without copying,
+/// or from shared buffers such as `Arc<[u8]>`, which are kept alive until the archive
+/// has been written instead of being copied. Borrowed slices are copied.
+pub struct FileContents {
+    repr: Repr,
+}
+
+#[derive(Debug, Clone)]
+enum Repr {
+    Owned(Vec<u8>),
+    Shared(Arc<[u8]>),
+    #[cfg(feature = "bytes")]
+    Bytes(bytes::Bytes),
+}
+
+impl FileContents {
+    /// Returns the contents as a slice.
+    pub fn as_slice(&self) -> &[u8] {
+        match &self.repr {
+            Repr::Owned(data) => data,
+            Repr::Shared(data) => data,
+            #[cfg(feature = "bytes")]
+            Repr::Bytes(data) => data,
+        }
+    }
+
+    /// Returns a mutable reference to the contents as an owned buffer,
+    /// copying them first if they are shared.
+    pub(crate) fn to_mut(&mut self) -> &mut Vec<u8> {
+        if !matches!(self.repr, Repr::Owned(_)) {
+            self.repr = Repr::Owned(self.as_slice().to_vec());
+        }
+
+        match &mut self.repr {
+            Repr::Owned(data) => data,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Default for FileContents {
+    fn default() -> FileContents {
+        FileContents::from(Vec::new())
+    }
+}
+
+impl Deref for FileContents {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl AsRef<[u8]> for FileContents {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl From<Vec<u8>> for FileContents {
+    fn from(data: Vec<u8>) -> FileContents {
+        FileContents {
+            repr: Repr::Owned(data),
+        }
+    }
+}
+
+impl From<Arc<[u8]>> for FileContents {
+    fn from(data: Arc<[u8]>) -> FileContents {
+        FileContents {
+            repr: Repr::Shared(data),
+        }
+    }
+}
+
+impl From<Box<[u8]>> for FileContents {
+    fn from(data: Box<[u8]>) -> FileContents {
+        FileContents::from(data.into_vec())
+    }
+}
+
+impl From<String> for FileContents {
+    fn from(data: String) -> FileContents {
+        FileContents::from(data.into_bytes())
+    }
+}
+
+impl<'a> From<Cow<'a, [u8]>> for FileContents {
+    fn from(data: Cow<'a, [u8]>) -> FileContents {
+        FileContents::from(data.into_owned())
+    }
+}
+
+impl<'a> From<&'a [u8]> for FileContents {
+    fn from(data: &'a [u8]) -> FileContents {
+        FileContents::from(data.to_vec())
+    }
+}
+
+impl<'a> From<&'a str> for FileContents {
+    fn from(data: &'a str) -> FileContents {
+        FileContents::from(data.as_bytes())
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for FileContents {
+    fn from(data: [u8; N]) -> FileContents {
+        FileContents::from(data.to_vec())
+    }
+}
+
+impl<'a, const N: usize> From<&'a [u8; N]> for FileContents {
+    fn from(data: &'a [u8; N]) -> FileContents {
+        FileContents::from(&data[..])
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl From<bytes::Bytes> for FileContents {
+    fn from(data: bytes::Bytes) -> FileContents {
+        FileContents {
+            repr: Repr::Bytes(data),
+        }
+    }
+}
@@ -1,49 +1,133 @@
 use std::borrow::Cow;
-use std::cmp::min;
+use std::cmp::{min, Ordering};
+use std::collections::HashSet;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io::Error as IoError;
-use std::io::{Seek, SeekFrom, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex};
 
 use byteorder::{WriteBytesExt, LE};
 use indexmap::IndexMap;
+use md5::{Digest, Md5};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
-// use super::archive::Archive;
+use super::archive::{Archive, SlashPolicy};
+use super::attributes::{Attributes, DigestHasher, Digests};
+use super::cache::CompressionCache;
 use super::consts::*;
+use super::contents::FileContents;
+use super::error::{Error, SizeLimitExceeded};
 use super::header::*;
+use super::het::ExtendedTables;
+use super::hooks::*;
+use super::names::*;
 use super::table::*;
 use super::util::*;
+use super::validation::*;
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+/// The number of compressed files the write pipeline may prepare ahead of the file being written.
+const PIPELINE_DEPTH: usize = 1;
+
+#[derive(Debug, Clone, Copy)]
 struct FileKey {
     hash_a: u32,
     hash_b: u32,
     index: u32,
+    locale: u16,
+    platform: u16,
 }
 
 impl FileKey {
-    fn new(name: &str) -> FileKey {
-        let hash_a = hash_string(name.as_bytes(), MPQ_HASH_NAME_A);
-        let hash_b = hash_string(name.as_bytes(), MPQ_HASH_NAME_B);
-        let index = hash_string(name.as_bytes(), MPQ_HASH_TABLE_INDEX);
+    fn new(name: &str, locale: u16, platform: u16) -> FileKey {
+        FileKey::from_hash(NameHash::new(name), locale, platform)
+    }
 
+    fn from_hash(hash: NameHash, locale: u16, platform: u16) -> FileKey {
         FileKey {
-            hash_a,
-            hash_b,
-            index,
+            hash_a: hash.hash_a,
+            hash_b: hash.hash_b,
+            index: hash.index,
+            locale,
+            platform,
+        }
+    }
+
+    /// Creates a key for an existing hash table entry, whose name may not be known.
+    /// Since the table index hash cannot be recovered, the entry's position is used instead.
+    fn from_hash_entry(entry: &HashEntry, position: usize) -> FileKey {
+        FileKey {
+            hash_a: entry.hash_a,
+            hash_b: entry.hash_b,
+            index: position as u32,
+            locale: entry.locale,
+            platform: entry.platform,
         }
     }
+
+    /// Whether both keys refer to the same file name, regardless of locale and platform.
+    fn same_name(&self, other: &FileKey) -> bool {
+        self.hash_a == other.hash_a && self.hash_b == other.hash_b
+    }
+}
+
+// the table index hash is not part of the key's identity,
+// since it cannot be recovered for files with unknown names
+impl PartialEq for FileKey {
+    fn eq(&self, other: &FileKey) -> bool {
+        self.same_name(other) && self.locale == other.locale && self.platform == other.platform
+    }
+}
+
+impl Eq for FileKey {}
+
+impl Hash for FileKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash_a.hash(state);
+        self.hash_b.hash(state);
+        self.locale.hash(state);
+        self.platform.hash(state);
+    }
+}
+
+/// Describes a file whose contents are already in their stored form,
+/// i.e. compressed and encrypted as they should appear in the archive.
+#[derive(Debug, Clone, Copy)]
+struct RawBlock {
+    flags: u32,
+    uncompressed_size: u64,
+    // checksums of the uncompressed contents, if they were known when the block was taken over
+    digests: Option<Digests>,
+    // the key the block is encrypted with, if it must be re-encrypted for its new position
+    previous_key: Option<u32>,
+    // whether the block is not encrypted yet, and must be encrypted according to its flags
+    encrypt: bool,
 }
 
 #[derive(Debug)]
 struct FileRecord {
     file_name: String,
-    contents: Vec<u8>,
+    contents: FileContents,
     offset: u64,
     compressed_size: u64,
     options: FileOptions,
+    raw: Option<RawBlock>,
+    // used by exact copies to keep the file's original position and block index
+    placement: Option<u64>,
+    block_index: Option<usize>,
+    // sectors which were already compressed when the file was streamed in
+    precompressed: Option<PrecompressedData>,
+    // produces the contents of a lazily added file when it is written
+    provider: Option<ContentProvider>,
+    // the size and checksums of a lazily added file, whose contents are released once written
+    released: Option<(u64, Digests)>,
 }
 
 impl FileRecord {
-    fn new<S: Into<String>, C: Into<Vec<u8>>>(
+    fn new<S: Into<String>, C: Into<FileContents>>(
         name: S,
         contents: C,
         options: FileOptions,
@@ -54,208 +138,2162 @@ impl FileRecord {
             offset: 0,
             compressed_size: 0,
             options,
+            raw: None,
+            placement: None,
+            block_index: None,
+            precompressed: None,
+            provider: None,
+            released: None,
+        }
+    }
+
+    fn flags(&self) -> u32 {
+        match self.raw {
+            Some(raw) => raw.flags,
+            None => self.options.flags(),
+        }
+    }
+
+    /// The checksums of the file's uncompressed contents, if they are known.
+    fn digests(&self) -> Option<Digests> {
+        if let Some((_, digests)) = self.released {
+            return Some(digests);
+        }
+
+        match (&self.raw, &self.precompressed) {
+            (Some(raw), _) => raw.digests,
+            (None, Some(precompressed)) => Some(precompressed.digests.finish()),
+            (None, None) => Some(Digests::of(&self.contents)),
+        }
+    }
+
+    /// Compresses every complete sector of the buffered contents into the precompressed sectors,
+    /// if the file is precompressed. If `flush` is set, the remaining contents are compressed as well.
+    fn compress_sectors(&mut self, sector_size: u64, flush: bool) {
+        let sector_size = sector_size as usize;
+
+        if let Some(precompressed) = self.precompressed.as_mut() {
+            let buffer = self.contents.to_mut();
+
+            while buffer.len() >= sector_size
+                || (flush && (!buffer.is_empty() || precompressed.sectors.is_empty()))
+            {
+                let end = sector_size.min(buffer.len());
+                precompressed.digests.update(&buffer[..end]);
+                let compression = self
+                    .options
+                    .compression
+                    .for_sector(precompressed.sectors.len() as u64);
+                let compressed = compress_mpq_block(&buffer[..end], compression).into_owned();
+
+                precompressed.sectors.push(compressed);
+                precompressed.uncompressed_size += end as u64;
+                buffer.drain(..end);
+            }
+        }
+    }
+
+    fn uncompressed_size(&self) -> u64 {
+        if let Some((size, _)) = self.released {
+            return size;
+        }
+
+        match (&self.raw, &self.precompressed) {
+            (Some(raw), _) => raw.uncompressed_size,
+            (None, Some(precompressed)) => precompressed.uncompressed_size,
+            (None, None) => self.contents.len() as u64,
         }
     }
 }
 
+const PROVIDER_FAILED: &str = "the contents of the file could not be produced by an earlier write";
+const PROVIDER_RELEASED: &str = "the contents of the file were released after an earlier write";
+
+/// A closure producing the contents of a file added with
+/// [add_file_lazy](struct.Creator.html#method.add_file_lazy).
+struct ContentProvider(Box<dyn FnOnce() -> Result<Vec<u8>, IoError> + Send>);
+
+impl ContentProvider {
+    /// Replaces a provider which was used up, so that writing the file again fails,
+    /// instead of writing it empty.
+    fn unavailable(reason: &'static str) -> ContentProvider {
+        ContentProvider(Box::new(move || Err(IoError::other(reason))))
+    }
+}
+
+impl std::fmt::Debug for ContentProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ContentProvider")
+    }
+}
+
+/// Compressed, but not yet encrypted sectors of a file.
+#[derive(Debug, Default)]
+struct PrecompressedData {
+    sectors: Vec<Vec<u8>>,
+    uncompressed_size: u64,
+    digests: DigestHasher,
+}
+
+/// A handle for streaming the contents of a file into a [Creator](struct.Creator.html),
+/// returned by [start_file](struct.Creator.html#method.start_file).
+///
+/// If the file is compressed, every complete sector is compressed as soon as it has been written,
+/// so that only compressed data and at most one uncompressed sector are kept in memory.
+///
+/// The file is staged in the creator when the handle is dropped or [finish](#method.finish)ed.
+#[derive(Debug)]
+pub struct FileWriter<'a> {
+    creator: &'a mut Creator,
+    record: Option<FileRecord>,
+    sector_size: u64,
+}
+
+impl<'a> FileWriter<'a> {
+    /// Stages the file in the creator. Equivalent to dropping the handle.
+    pub fn finish(self) {}
+
+    fn compress_sectors(&mut self, flush: bool) {
+        let sector_size = self.sector_size;
+
+        self.record
+            .as_mut()
+            .unwrap()
+            .compress_sectors(sector_size, flush);
+    }
+}
+
+impl<'a> Write for FileWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        self.record
+            .as_mut()
+            .unwrap()
+            .contents
+            .to_mut()
+            .extend_from_slice(buf);
+        self.compress_sectors(false);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        Ok(())
+    }
+}
+
+impl<'a> Drop for FileWriter<'a> {
+    fn drop(&mut self) {
+        self.compress_sectors(true);
+
+        if let Some(record) = self.record.take() {
+            self.creator.stage(record, None);
+        }
+    }
+}
+
+/// The layout of an existing archive, which is reproduced by an exact copy.
+#[derive(Debug)]
+struct PreservedLayout {
+    /// Names known from the source's `(listfile)`.
+    names: Vec<String>,
+    hash_entries: Vec<HashEntry>,
+    block_entries: Vec<BlockEntry>,
+    /// Data of existing block entries which are not referenced by any hash table entry.
+    orphans: Vec<(u64, Vec<u8>)>,
+    /// The end of all file data in the source, relative to the archive start.
+    data_end: u64,
+    tables: ExistingTables,
+}
+
+/// The header and extended tables of an existing archive, which are kept when its tables are rewritten.
+#[derive(Debug)]
+struct ExistingTables {
+    header: FileHeader,
+    extended: Option<ExtendedTables>,
+}
+
+impl ExistingTables {
+    fn from_archive<R: Read + Seek>(archive: &mut Archive<R>) -> Result<ExistingTables, Error> {
+        let info = archive.seeker().info().clone();
+        let extended = match (info.het_table_info, info.bet_table_info) {
+            (Some(het_info), Some(bet_info)) => Some(ExtendedTables::from_seeker(
+                archive.seeker(),
+                &het_info,
+                &bet_info,
+            )?),
+            _ => None,
+        };
+
+        Ok(ExistingTables {
+            header: info.header.file_header().clone(),
+            extended,
+        })
+    }
+
+    /// The format version of the existing header, which determines the limits of the archive.
+    fn format_version(&self) -> FormatVersion {
+        FormatVersion::of_header(self.header.format_version)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
+/// A view of a file that was added to a [Creator](struct.Creator.html),
+/// used when deciding the order in which files are written.
+pub struct StagedFile<'a> {
+    record: &'a FileRecord,
+}
+
+impl<'a> StagedFile<'a> {
+    /// The name of the file, as it will appear in the archive.
+    pub fn name(&self) -> &'a str {
+        &self.record.file_name
+    }
+
+    /// The uncompressed size of the file.
+    pub fn size(&self) -> u64 {
+        self.record.uncompressed_size()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// The version of the MPQ format written by a [Creator](struct.Creator.html).
+pub enum FormatVersion {
+    /// The original format, understood by all tools and games.
+    #[default]
+    V1,
+    /// Format version 2, introduced with The Burning Crusade. Its header additionally contains
+    /// the upper 16 bits of the table positions, and a hi-block table is written if any file
+    /// is located beyond 4 GiB, so that archives larger than 4 GiB can be written.
+    V2,
+    /// Format version 3, introduced with Cataclysm. Its header additionally contains the 64-bit archive size
+    /// and the positions of HET and BET tables, which are not written. A hi-block table is written
+    /// if any file is located beyond 4 GiB.
+    V3,
+    /// Format version 4, as written by modern Blizzard tooling. Its header additionally contains
+    /// 64-bit table positions and sizes, as well as MD5 checksums of the tables and the header itself.
+    /// A hi-block table is written if any file is located beyond 4 GiB.
+    V4,
+}
+
+impl FormatVersion {
+    /// The format version with the same limits as a header of the specified version.
+    fn of_header(format_version: u16) -> FormatVersion {
+        match format_version {
+            0 => FormatVersion::V1,
+            1 => FormatVersion::V2,
+            2 => FormatVersion::V3,
+            _ => FormatVersion::V4,
+        }
+    }
+
+    /// The largest position, relative to the archive start, that can be stored in the tables.
+    fn max_position(self) -> u64 {
+        match self {
+            FormatVersion::V1 => u64::from(u32::MAX),
+            // the table positions and the hi-block table add 16 bits
+            FormatVersion::V2 | FormatVersion::V3 | FormatVersion::V4 => (1 << 48) - 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// The compression method used for the sectors of a file added to a [Creator](struct.Creator.html).
+///
+/// Sectors which would not get smaller are always stored uncompressed.
+pub enum Compression {
+    /// Store the file uncompressed.
+    #[default]
+    None,
+    /// DEFLATE, using zlib's best compression level. This is what Blizzard uses for Warcraft III maps.
+    Deflate,
+    /// bzip2, which is slower than DEFLATE, but often compresses large text files better.
+    Bzip2,
+    /// Run-length encoding of zero bytes, which is very fast, but only helps with files
+    /// consisting mostly of zeros.
+    Sparse,
+    /// Lossy IMA ADPCM compression of 16-bit mono PCM `.wav` files, which roughly halves their size.
+    ///
+    /// The first sector, which contains the WAV header, is compressed with DEFLATE instead,
+    /// like Blizzard's tools do. Use [for_wav](#method.for_wav) to pick the variant matching a file.
+    AdpcmMono,
+    /// Same as [AdpcmMono](#variant.AdpcmMono), but for 16-bit stereo PCM `.wav` files.
+    AdpcmStereo,
+}
+
+impl Compression {
+    /// Returns the ADPCM variant matching the channels of a 16-bit PCM `.wav` file,
+    /// or [Deflate](#variant.Deflate) if `contents` is not such a file.
+    pub fn for_wav(contents: &[u8]) -> Compression {
+        match wav_channels(contents) {
+            Some(1) => Compression::AdpcmMono,
+            Some(2) => Compression::AdpcmStereo,
+            _ => Compression::Deflate,
+        }
+    }
+
+    /// The compression type byte which precedes compressed sectors.
+    pub(crate) fn type_byte(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => COMPRESSION_ZLIB,
+            Compression::Bzip2 => COMPRESSION_BZIP2,
+            Compression::Sparse => COMPRESSION_SPARSE,
+            Compression::AdpcmMono => COMPRESSION_IMA_ADPCM_MONO_MONO,
+            Compression::AdpcmStereo => COMPRESSION_IMA_ADPCM_MONO_STEREO,
+        }
+    }
+
+    /// The compression used for the sector at `index`.
+    /// Lossy compression is never used for the first sector, which holds the file's header.
+    pub(crate) fn for_sector(self, index: u64) -> Compression {
+        match self {
+            Compression::AdpcmMono | Compression::AdpcmStereo if index == 0 => Compression::Deflate,
+            compression => compression,
+        }
+    }
+}
+
+/// Reads the number of channels from the header of a 16-bit PCM `.wav` file.
+fn wav_channels(contents: &[u8]) -> Option<u16> {
+    if contents.get(0..4)? != b"RIFF" || contents.get(8..12)? != b"WAVE" {
+        return None;
+    }
+
+    let mut chunks = contents.get(12..)?;
+    while chunks.len() >= 8 {
+        let size = u32::from_le_bytes([chunks[4], chunks[5], chunks[6], chunks[7]]) as usize;
+        let data = chunks.get(8..8 + size)?;
+
+        if &chunks[0..4] == b"fmt " {
+            let format = u16::from_le_bytes([*data.first()?, *data.get(1)?]);
+            let channels = u16::from_le_bytes([*data.get(2)?, *data.get(3)?]);
+            let bits_per_sample = u16::from_le_bytes([*data.get(14)?, *data.get(15)?]);
+
+            return if format == 1 && bits_per_sample == 16 {
+                Some(channels)
+            } else {
+                None
+            };
+        }
+
+        // chunks are padded to an even size
+        chunks = chunks.get(8 + size + (size & 1)..)?;
+    }
+
+    None
+}
+
+#[derive(Debug, Clone, Copy, Default)]
 /// Represents various options that can be used when adding a file to an archive.
 pub struct FileOptions {
     /// Whether to encrypt the file using MPQ's encryption scheme.
     /// The encryption key is derived from the file name, so in practice
     /// this is pretty useless.
     pub encrypt: bool,
-    /// Whether to compress the file. Currently will only try to use DEFLATE
-    /// compression.
-    pub compress: bool,
+    /// The compression method for the file's sectors.
+    pub compression: Compression,
     /// If the file is ecnrypted, this will "adjust" the encryption key by
     /// performing some simple transformations on it. By default, this is used for
     /// "technical" files such as `(listfile)`.
     pub adjust_key: bool,
+    /// Additional block table flag bits that will be set on the file as-is.
+    ///
+    /// This is an escape hatch for reproducing flag combinations found in existing archives.
+    /// No validation is performed, and setting bits which affect how the file is stored
+    /// (e.g. `MPQ_FILE_SINGLE_UNIT`) will likely produce a file that cannot be read back.
+    pub extra_flags: u32,
+    /// The locale of the file, written to its hash table entry.
+    ///
+    /// `0` is the neutral locale, which is the one that is used
+    /// when no specific locale is requested. The same file name can be added
+    /// multiple times with different locales.
+    pub locale: u16,
+    /// The platform of the file, written to its hash table entry.
+    ///
+    /// This is always `0` in archives produced by Blizzard.
+    pub platform: u16,
+}
+
+impl FileOptions {
+    fn flags(self) -> u32 {
+        let mut flags = MPQ_FILE_EXISTS | self.extra_flags;
+
+        if self.encrypt {
+            flags |= MPQ_FILE_ENCRYPTED;
+        }
+
+        if self.adjust_key {
+            flags |= MPQ_FILE_ADJUST_KEY;
+        }
+
+        if self.compression != Compression::None {
+            flags |= MPQ_FILE_COMPRESS;
+        }
+
+        flags
+    }
+}
+
+#[derive(Debug)]
+/// Creator capable of creating MPQ archives. Version 1 archives are written by default,
+/// other versions can be selected with [set_format_version](struct.Creator.html#method.set_format_version).
+///
+/// Will hold all the files in memory until asked to [write](struct.Creator.html#method.write) them
+/// to a `writer`.
+///
+/// When writing, a `(listfile)` will be automatically appended to the archive.
+/// By default it is compressed and encrypted with an adjusted key, which can be changed with
+/// [set_technical_file_options](struct.Creator.html#method.set_technical_file_options).
+pub struct Creator {
+    added_files: IndexMap<FileKey, FileRecord>,
+
+    sector_size: u64,
+    technical_file_options: FileOptions,
+    slash_policy: SlashPolicy,
+    format_version: FormatVersion,
+    prefix: Vec<u8>,
+    compression_cache: Option<CompressionCache>,
+    store_incompressible: bool,
+    compress_on_add: bool,
+    spill_to_disk: bool,
+    // holds the contents of added files if `spill_to_disk` is enabled, created when it is first needed
+    spill_file: Option<Arc<Mutex<File>>>,
+    generate_attributes: bool,
+    size_limit: Option<u64>,
+    written_size: Option<u64>,
+    hooks: HookSlot,
+    layout: Option<PreservedLayout>,
 }
 
-impl Default for FileOptions {
-    fn default() -> FileOptions {
-        FileOptions {
-            encrypt: false,
-            compress: false,
-            adjust_key: false,
+impl Default for Creator {
+    fn default() -> Creator {
+        Creator {
+            added_files: IndexMap::new(),
+            sector_size: 0x10000,
+            technical_file_options: FileOptions {
+                compression: Compression::Deflate,
+                encrypt: true,
+                adjust_key: true,
+                ..Default::default()
+            },
+            slash_policy: SlashPolicy::Normalize,
+            format_version: FormatVersion::V1,
+            prefix: Vec::new(),
+            compression_cache: None,
+            store_incompressible: false,
+            compress_on_add: false,
+            spill_to_disk: false,
+            spill_file: None,
+            generate_attributes: false,
+            size_limit: None,
+            written_size: None,
+            hooks: HookSlot::default(),
+            layout: None,
+        }
+    }
+}
+
+impl Creator {
+    /// Adds a file to be later written to the archive.
+    ///
+    /// All forward slashes (`/`) in the file path will be auto-converted to backward slashes (`\`),
+    /// unless a different policy was set with [set_slash_policy](struct.Creator.html#method.set_slash_policy).
+    ///
+    /// [`FileOptions`](struct.FileOptions.html) determine the options for adding the file, e.g. encryption and compression.
+    ///
+    /// Contents can be anything that converts into [`FileContents`](struct.FileContents.html).
+    /// Shared buffers such as `Arc<[u8]>` are not copied.
+    ///
+    /// The name can also be given as a [FileName](struct.FileName.html) with precomputed hashes.
+    pub fn add_file<N, C>(&mut self, file_name: &N, contents: C, options: FileOptions)
+    where
+        N: AsFileName + ?Sized,
+        C: Into<FileContents>,
+    {
+        let (file_name, name_hash) = match self.slash_policy.apply(file_name.name()) {
+            Cow::Borrowed(name) => (
+                name.to_string(),
+                file_name.precomputed().map(|name| name.hash()),
+            ),
+            Cow::Owned(name) => (name, None),
+        };
+        let mut record = FileRecord::new(file_name, contents, options);
+
+        // the store policy cannot be applied later, since the contents are not kept in memory
+        if (self.compress_on_add || self.spill_to_disk)
+            && self.store_incompressible
+            && is_incompressible(
+                &record.file_name,
+                &record.contents,
+                self.sector_size as usize,
+            )
+        {
+            record.options.compression = Compression::None;
+        }
+
+        if self.compress_on_add && record.options.compression != Compression::None {
+            record.precompressed = Some(PrecompressedData::default());
+            record.compress_sectors(self.sector_size, true);
+            record.contents = FileContents::default();
+        } else if self.spill_to_disk {
+            // if the contents cannot be spilled, they are simply kept in memory
+            let _ = self.spill(&mut record);
+        }
+
+        self.stage(record, name_hash);
+    }
+
+    /// Moves the contents of a file into the spill file, from which they are read back when the file is written.
+    /// The record is left unchanged if writing to the spill file fails.
+    fn spill(&mut self, record: &mut FileRecord) -> Result<(), IoError> {
+        let spill_file = match &self.spill_file {
+            Some(spill_file) => Arc::clone(spill_file),
+            None => {
+                let spill_file = Arc::new(Mutex::new(tempfile::tempfile()?));
+                self.spill_file = Some(Arc::clone(&spill_file));
+                spill_file
+            }
+        };
+
+        let offset = {
+            let mut file = spill_file
+                .lock()
+                .map_err(|_| IoError::other("spill file is poisoned"))?;
+            let offset = file.seek(SeekFrom::End(0))?;
+            file.write_all(&record.contents)?;
+            offset
+        };
+
+        let size = record.contents.len();
+        record.released = Some((size as u64, Digests::of(&record.contents)));
+        record.contents = FileContents::default();
+        record.provider = Some(ContentProvider(Box::new(move || {
+            let mut file = spill_file
+                .lock()
+                .map_err(|_| IoError::other("spill file is poisoned"))?;
+            let mut contents = vec![0; size];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut contents)?;
+
+            Ok(contents)
+        })));
+
+        Ok(())
+    }
+
+    /// Adds a file whose sectors were already compressed, e.g. with a custom codec
+    /// or to reproduce an existing archive byte-for-byte.
+    ///
+    /// `stored` is written to the archive as-is, and must be the file's data as it should be stored,
+    /// but without encryption: for compressed files, the sector offset table followed by the
+    /// compressed sectors. The block table flags are derived from `options` as for
+    /// [add_file](struct.Creator.html#method.add_file), including
+    /// [extra_flags](struct.FileOptions.html#structfield.extra_flags), and must describe the stored data.
+    /// No validation is performed. If encryption is requested, the data is encrypted when writing,
+    /// since the key may depend on the file's position.
+    ///
+    /// Since the uncompressed contents are not known, the file has no checksums in generated `(attributes)`.
+    pub fn add_file_raw<C>(
+        &mut self,
+        file_name: &str,
+        stored: C,
+        uncompressed_size: u64,
+        options: FileOptions,
+    ) where
+        C: Into<FileContents>,
+    {
+        let file_name = self.slash_policy.apply(file_name).into_owned();
+        let mut record = FileRecord::new(file_name, stored, options);
+        record.raw = Some(RawBlock {
+            flags: options.flags(),
+            uncompressed_size,
+            digests: None,
+            previous_key: None,
+            encrypt: options.encrypt,
+        });
+
+        self.stage(record, None);
+    }
+
+    /// Adds a file whose contents are only produced by `provider` when the archive is written,
+    /// e.g. to defer generating build artifacts. The contents are released again once the file has been
+    /// written. Since the next files are compressed while a file is being written, the contents of
+    /// up to three lazily added files are held in memory at a time.
+    ///
+    /// If `provider` fails, writing fails with its error. Since `provider` is only called once,
+    /// writing the creator again fails once the file has been written or its provider has failed.
+    /// Names and options are handled the same way as in [add_file](struct.Creator.html#method.add_file),
+    /// except that [set_store_incompressible](struct.Creator.html#method.set_store_incompressible)
+    /// does not apply to lazily added files, and their [size](struct.StagedFile.html#method.size)
+    /// is `0` until written.
+    pub fn add_file_lazy<F>(&mut self, file_name: &str, provider: F, options: FileOptions)
+    where
+        F: FnOnce() -> Result<Vec<u8>, IoError> + Send + 'static,
+    {
+        let file_name = self.slash_policy.apply(file_name).into_owned();
+        let mut record = FileRecord::new(file_name, Vec::new(), options);
+        record.provider = Some(ContentProvider(Box::new(provider)));
+
+        self.stage(record, None);
+    }
+
+    /// Starts adding a file whose contents will be streamed in through the returned
+    /// [`FileWriter`](struct.FileWriter.html), e.g. for generated content that should not
+    /// be collected in memory first.
+    ///
+    /// Names and options are handled the same way as in [add_file](struct.Creator.html#method.add_file).
+    /// The file is staged once the writer is dropped.
+    pub fn start_file(&mut self, file_name: &str, options: FileOptions) -> FileWriter<'_> {
+        let file_name = self.slash_policy.apply(file_name).into_owned();
+        let mut record = FileRecord::new(file_name, Vec::new(), options);
+        if options.compression != Compression::None {
+            record.precompressed = Some(PrecompressedData::default());
+        }
+
+        FileWriter {
+            sector_size: self.sector_size,
+            creator: self,
+            record: Some(record),
+        }
+    }
+
+    /// Stages a file, using the precomputed hashes of its name if there are any.
+    fn stage(&mut self, mut record: FileRecord, name_hash: Option<NameHash>) {
+        let name_hash = name_hash.unwrap_or_else(|| NameHash::new(&record.file_name));
+        let key = FileKey::from_hash(name_hash, record.options.locale, record.options.platform);
+
+        // a replaced file keeps its place in the block table of an exact copy
+        if let Some(existing) = self.added_files.get(&key) {
+            record.block_index = existing.block_index;
+        }
+
+        self.added_files.insert(key, record);
+    }
+
+    /// Creates a creator which reproduces the specified archive exactly.
+    ///
+    /// All files are copied in their stored form, i.e. without being decoded,
+    /// and keep their flags, locales, positions and block table indices.
+    /// The hash and block tables, as well as everything in the archive's reader
+    /// preceding the MPQ header, are copied as-is. If no other changes are made,
+    /// [write](struct.Creator.html#method.write) will reproduce the archive byte-for-byte,
+    /// provided that the archive's tables are located after all file data.
+    ///
+    /// Files added to the resulting creator replace existing files with the same name, locale and platform,
+    /// keeping their block table index, or are added as new files. Their data is written
+    /// after all of the original file data, so the positions of other files do not change.
+    /// If any new names are added, the `(listfile)` is regenerated.
+    ///
+    /// Note that an exact copy always reuses the source archive's sector size and hash table size.
+    /// Writing will fail if the hash table does not have space for all new files.
+    ///
+    /// The copy keeps the header of the source, including its format version, and the hi-block table
+    /// if there is one. If the source has HET and BET tables, they are copied as well,
+    /// or rebuilt if any files were added. Sources without a hash table cannot be copied.
+    pub fn copy_exact<R: Read + Seek>(archive: &mut Archive<R>) -> Result<Creator, Error> {
+        let names = archive.files().unwrap_or_default();
+        let info = archive.seeker().info().clone();
+        let prefix = archive.seeker().read_absolute(0, info.header_offset)?;
+
+        let hash_entries = archive.hash_table().entries().to_vec();
+        let block_entries = archive.block_table().entries().to_vec();
+        let tables = ExistingTables::from_archive(archive)?;
+
+        if hash_entries.is_empty() {
+            return Err(IoError::new(
+                std::io::ErrorKind::InvalidInput,
+                "cannot copy an archive without a hash table",
+            )
+            .into());
+        }
+
+        // resolve the names of hash table entries from the listfile
+        let mut known_names = names.clone();
+        known_names.extend(TECHNICAL_FILE_NAMES.iter().map(|name| name.to_string()));
+        let mut name_lookup = std::collections::HashMap::new();
+        for name in &known_names {
+            let key = FileKey::new(name, 0, 0);
+            name_lookup.insert((key.hash_a, key.hash_b), name.clone());
+        }
+
+        // keep everything that preceded the header in the source
+        let mut creator = Creator {
+            sector_size: info.sector_size,
+            prefix,
+            ..Default::default()
+        };
+
+        let mut referenced = vec![false; block_entries.len()];
+        for (position, hash_entry) in hash_entries.iter().enumerate() {
+            let block_index = hash_entry.block_index as usize;
+            let block_entry = match block_entries.get(block_index) {
+                Some(block_entry) => block_entry,
+                None => continue,
+            };
+
+            referenced[block_index] = true;
+
+            let key = FileKey::from_hash_entry(hash_entry, position);
+            let name = name_lookup
+                .get(&(key.hash_a, key.hash_b))
+                .cloned()
+                .unwrap_or_default();
+            let contents = archive.read_stored(block_index)?;
+
+            let mut record = FileRecord::new(name, contents, FileOptions::default());
+            record.raw = Some(RawBlock {
+                flags: block_entry.flags,
+                uncompressed_size: block_entry.uncompressed_size,
+                digests: None,
+                previous_key: None,
+                encrypt: false,
+            });
+            record.placement = Some(block_entry.file_pos);
+            record.block_index = Some(block_index);
+
+            creator.added_files.insert(key, record);
+        }
+
+        let mut orphans = Vec::new();
+        for (block_index, block_entry) in block_entries.iter().enumerate() {
+            if !referenced[block_index] && block_entry.flags & MPQ_FILE_EXISTS != 0 {
+                orphans.push((block_entry.file_pos, archive.read_stored(block_index)?));
+            }
+        }
+
+        let data_end = data_end(&block_entries, u64::from(tables.header.header_size));
+
+        creator.layout = Some(PreservedLayout {
+            names,
+            hash_entries,
+            block_entries,
+            orphans,
+            data_end,
+            tables,
+        });
+
+        Ok(creator)
+    }
+
+    /// Creates a creator which rewrites the specified archive with another format version,
+    /// e.g. to make an archive produced by modern tooling readable by older clients.
+    ///
+    /// Unlike with [copy_exact](struct.Creator.html#method.copy_exact), the archive is laid out anew,
+    /// using the source's sector size. Files are still copied in their stored form, keeping their
+    /// compression, flags, locales and platforms, and files encrypted with an adjusted key are
+    /// re-encrypted for their new position. The `(listfile)` is regenerated, and if the source
+    /// has `(attributes)`, they are regenerated from its checksums. A `(signature)` is dropped,
+    /// since it would no longer be valid.
+    ///
+    /// Files are found through the names in the `(listfile)` and those registered with
+    /// [add_known_names](struct.Archive.html#method.add_known_names). Fails with
+    /// [Error::UnknownName](enum.Error.html) if any other file exists, since it could
+    /// not be looked up in the converted archive.
+    pub fn convert<R: Read + Seek>(
+        archive: &mut Archive<R>,
+        format_version: FormatVersion,
+    ) -> Result<Creator, Error> {
+        let mut names = archive.files_result()?.unwrap_or_default();
+        names.extend(archive.known_names().iter().cloned());
+        names.extend(TECHNICAL_FILE_NAMES.iter().map(|name| name.to_string()));
+
+        let attributes = archive.attributes()?;
+        let sector_size = archive.sector_size();
+        let block_count = archive.block_table().entries().len();
+
+        let mut files = Vec::new();
+        let mut resolved = vec![false; block_count];
+        for name in &names {
+            for hash_entry in archive.hash_table().find_entries(name, false) {
+                let block_index = hash_entry.block_index as usize;
+                if block_index < block_count && !resolved[block_index] {
+                    resolved[block_index] = true;
+                    files.push((name.clone(), hash_entry, block_index));
+                }
+            }
+        }
+
+        let block_entries = archive.block_table().entries();
+        if let Some(block_index) = (0..block_count)
+            .find(|&i| !resolved[i] && block_entries[i].flags & MPQ_FILE_EXISTS != 0)
+        {
+            return Err(Error::UnknownName { block_index });
+        }
+
+        let mut creator = Creator {
+            sector_size,
+            format_version,
+            generate_attributes: attributes.is_some(),
+            ..Default::default()
+        };
+
+        // keep the order of the source's block table
+        files.sort_by_key(|(_, _, block_index)| *block_index);
+        for (name, hash_entry, block_index) in files {
+            let block_entry = archive.block_table().entries()[block_index].clone();
+            if TECHNICAL_FILE_NAMES.contains(&name.as_str())
+                || block_entry.flags & MPQ_FILE_EXISTS == 0
+            {
+                continue;
+            }
+
+            let previous_key = if block_entry.is_encrypted() && block_entry.is_key_adjusted() {
+                Some(calculate_file_key(
+                    &name,
+                    block_entry.file_pos as u32,
+                    block_entry.uncompressed_size as u32,
+                    true,
+                ))
+            } else {
+                None
+            };
+
+            let digests = attributes.as_ref().map(|attributes| Digests {
+                crc32: attributes.crc32(block_index).unwrap_or_default(),
+                md5: attributes.md5(block_index).unwrap_or_default(),
+            });
+
+            let contents = archive.read_stored(block_index)?;
+            let options = FileOptions {
+                locale: hash_entry.locale,
+                platform: hash_entry.platform,
+                ..Default::default()
+            };
+
+            let mut record = FileRecord::new(name.as_str(), contents, options);
+            record.raw = Some(RawBlock {
+                flags: block_entry.flags,
+                uncompressed_size: block_entry.uncompressed_size,
+                digests,
+                previous_key,
+                encrypt: false,
+            });
+
+            creator.stage(record, None);
+        }
+
+        Ok(creator)
+    }
+
+    /// Creates a creator which holds all files of the specified archive, e.g. to replace a couple
+    /// of files with [add_file](struct.Creator.html#method.add_file) and write a fresh archive.
+    ///
+    /// This is the same as [convert](struct.Creator.html#method.convert) to the archive's own format version.
+    /// Files keep their stored form and flags, but are laid out anew when writing.
+    pub fn from_archive<R: Read + Seek>(archive: &mut Archive<R>) -> Result<Creator, Error> {
+        let format_version = FormatVersion::of_header(archive.seeker().info().format_version);
+
+        Creator::convert(archive, format_version)
+    }
+
+    /// Reuses the stored form of unchanged files from `previous`, e.g. the archive produced
+    /// by the previous build of a map, so that they do not have to be compressed again.
+    ///
+    /// A file is reused if `previous` contains a file with the same name whose contents
+    /// and block table flags are identical. Its compressed and encrypted sectors are then
+    /// copied verbatim when writing. Returns the number of reused files.
+    ///
+    /// Only files with the neutral locale and platform are considered. Files encrypted
+    /// with an adjusted key are never reused, since their key depends on their position
+    /// in the archive. Nothing is reused if the archives' sector sizes differ.
+    /// Reused files keep their previous compression method.
+    ///
+    /// Since the previous contents of every candidate are read and compared,
+    /// this should be called after all files have been added.
+    pub fn reuse_unchanged<R: Read + Seek>(
+        &mut self,
+        previous: &mut Archive<R>,
+    ) -> Result<usize, Error> {
+        if previous.sector_size() != self.sector_size {
+            return Ok(0);
+        }
+
+        let mut reused = 0;
+        for file in self.added_files.values_mut() {
+            let is_candidate = file.raw.is_none()
+                && file.precompressed.is_none()
+                && file.provider.is_none()
+                && file.options.locale == 0
+                && file.options.platform == 0;
+            let flags = file.flags();
+            let is_key_adjusted =
+                flags & MPQ_FILE_ENCRYPTED != 0 && flags & MPQ_FILE_ADJUST_KEY != 0;
+
+            if !is_candidate || is_key_adjusted {
+                continue;
+            }
+
+            let info = match previous.file_info(&file.file_name) {
+                Some(info) => info,
+                None => continue,
+            };
+
+            if info.flags() != flags || info.uncompressed_size() != file.contents.len() as u64 {
+                continue;
+            }
+
+            // files that cannot be read in the previous archive are simply compressed again
+            match previous.read_file(&file.file_name) {
+                Ok(contents) if contents == file.contents.as_slice() => {}
+                _ => continue,
+            }
+
+            let digests = Digests::of(&file.contents);
+            file.contents = previous.read_stored(info.block_index())?.into();
+            file.raw = Some(RawBlock {
+                flags,
+                uncompressed_size: info.uncompressed_size(),
+                digests: Some(digests),
+                previous_key: None,
+                encrypt: false,
+            });
+            reused += 1;
+        }
+
+        Ok(reused)
+    }
+
+    /// Sets the options used for technical files generated by the creator, such as `(listfile)`.
+    ///
+    /// By default, technical files are compressed and encrypted with an adjusted key,
+    /// same as archives produced by Blizzard. Some tools prefer to have them unencrypted.
+    pub fn set_technical_file_options(&mut self, options: FileOptions) {
+        self.technical_file_options = options;
+    }
+
+    /// Sets how forward slashes (`/`) in the names of added files are treated.
+    ///
+    /// By default, they are converted to backslashes (`\\`). With [SlashPolicy::Preserve](enum.SlashPolicy.html),
+    /// names are stored exactly as given, which is useful when replicating archives whose
+    /// names intentionally contain forward slashes.
+    ///
+    /// Only affects files added after calling this method.
+    pub fn set_slash_policy(&mut self, policy: SlashPolicy) {
+        self.slash_policy = policy;
+    }
+
+    /// Sets the version of the MPQ format that will be written.
+    ///
+    /// Defaults to [FormatVersion::V1](enum.FormatVersion.html). Exact copies created with
+    /// [copy_exact](struct.Creator.html#method.copy_exact) always keep the header of their source.
+    pub fn set_format_version(&mut self, version: FormatVersion) {
+        self.format_version = version;
+    }
+
+    /// Sets arbitrary data that will be written before the archive, e.g. when embedding the
+    /// archive in an executable or a custom container. The MPQ header is placed at the first
+    /// valid header position following the prefix.
+    ///
+    /// The prefix must not contain an MPQ header signature at a multiple of 512 bytes,
+    /// since readers would mistake it for the start of the archive.
+    ///
+    /// Exact copies created with [copy_exact](struct.Creator.html#method.copy_exact) start out with
+    /// the data that preceded the header in their source, which this replaces.
+    pub fn set_prefix<C: Into<Vec<u8>>>(&mut self, prefix: C) {
+        self.prefix = prefix.into();
+    }
+
+    /// Sets a [CompressionCache](struct.CompressionCache.html) which is consulted before compressing
+    /// any file, and which stores the compressed data of every file that had to be compressed.
+    ///
+    /// Files streamed in with [start_file](struct.Creator.html#method.start_file) are compressed
+    /// while they are written, and do not use the cache.
+    pub fn set_compression_cache(&mut self, cache: Option<CompressionCache>) {
+        self.compression_cache = cache;
+    }
+
+    /// Reorders the added files so that the files named in `names` are written first,
+    /// in the given order. All other files are written after them, keeping their
+    /// relative order. All locale and platform variants of a named file are moved together.
+    ///
+    /// Names that were not added to the creator are ignored. Files added after
+    /// calling this method are written after all others.
+    pub fn set_file_order<S: AsRef<str>>(&mut self, names: &[S]) {
+        let positions: Vec<FileKey> = names
+            .iter()
+            .map(|name| FileKey::new(&self.slash_policy.apply(name.as_ref()), 0, 0))
+            .collect();
+
+        self.added_files.sort_by(|key_a, _, key_b, _| {
+            let pos_a = positions.iter().position(|key| key.same_name(key_a));
+            let pos_b = positions.iter().position(|key| key.same_name(key_b));
+
+            match (pos_a, pos_b) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+        });
+    }
+
+    /// Sorts the added files with the given comparator, which determines
+    /// the physical order in which they are written to the archive.
+    ///
+    /// The sort is stable. Files added after calling this method are written after all others.
+    pub fn sort_files_by<F>(&mut self, mut compare: F)
+    where
+        F: FnMut(&StagedFile, &StagedFile) -> Ordering,
+    {
+        self.added_files.sort_by(|_, record_a, _, record_b| {
+            compare(
+                &StagedFile { record: record_a },
+                &StagedFile { record: record_b },
+            )
+        });
+    }
+
+    /// Checks the names of all added files for problems which would make the archive
+    /// unusable in the game, and returns every problem that was found.
+    ///
+    /// The following problems are detected:
+    /// - Names longer than the maximum path length.
+    /// - Control characters and characters which are not allowed in paths, such as `:` or `*`.
+    /// - Empty path components, e.g. a leading backslash.
+    /// - Names reserved for technical files, such as `(listfile)`.
+    /// - Distinct names which produce the same hashes.
+    ///
+    /// Files preserved from the source of an exact copy are only checked for hash collisions.
+    pub fn validate_names(&self) -> Vec<NameProblem> {
+        let mut problems = Vec::new();
+
+        for file in self.added_files.values() {
+            if file.placement.is_none() {
+                check_name(&file.file_name, &mut problems);
+            }
+        }
+
+        let names = self
+            .added_files
+            .values()
+            .map(|file| file.file_name.as_str())
+            .filter(|name| !name.is_empty());
+        find_collisions(names, &mut problems);
+
+        problems
+    }
+
+    /// Orders the added files the way Warcraft III loads maps most efficiently:
+    /// `war3map.w3i` first, followed by the map script, other `war3map*` files,
+    /// all remaining files, and finally media such as models, textures and sounds,
+    /// sorted from smallest to largest.
+    ///
+    /// Files added after calling this method are written after all others.
+    pub fn sort_for_wc3(&mut self) {
+        fn rank(file: &StagedFile) -> u8 {
+            let name = file.name();
+            let plain_name = name.rsplit('\\').next().unwrap_or(name);
+
+            if name.eq_ignore_ascii_case("war3map.w3i") {
+                0
+            } else if MAP_SCRIPT_NAMES
+                .iter()
+                .any(|script| script.eq_ignore_ascii_case(name))
+            {
+                1
+            } else if plain_name.to_ascii_lowercase().starts_with("war3map") {
+                2
+            } else if file_extension(name)
+                .map(|extension| MAP_MEDIA_EXTENSIONS.contains(&extension.as_str()))
+                .unwrap_or(false)
+            {
+                4
+            } else {
+                3
+            }
+        }
+
+        self.sort_files_by(|a, b| {
+            let (rank_a, rank_b) = (rank(a), rank(b));
+
+            if rank_a == 4 && rank_b == 4 {
+                a.size().cmp(&b.size())
+            } else {
+                rank_a.cmp(&rank_b)
+            }
+        });
+    }
+
+    /// Writes out the entire archive to the specified writer.
+    ///
+    /// The archive start position is calculated as follows:  
+    /// `((current_pos + (HEADER_BOUNDARY - 1)) / HEADER_BOUNDARY) * HEADER_BOUNDARY`  
+    /// Where `current_pos` is the `writer`'s current seek pos after writing the
+    /// [prefix](struct.Creator.html#method.set_prefix), if any, and `HEADER_BOUNDARY` is 512.
+    ///
+    /// Will write the following:
+    /// - Prefix
+    /// - MPQ Header
+    /// - All files with their sector offset table
+    /// - MPQ hash table
+    /// - MPQ block table
+    ///
+    /// Names are not validated before writing, see [validate_names](struct.Creator.html#method.validate_names).
+    ///
+    /// If a [size limit](struct.Creator.html#method.set_size_limit) is set and the written archive
+    /// exceeds it, an error of kind `Other` is returned whose inner error is a
+    /// [SizeLimitExceeded](struct.SizeLimitExceeded.html). The archive has been fully written at that point.
+    pub fn write<W>(&mut self, writer: W) -> Result<(), IoError>
+    where
+        W: Write + Seek,
+    {
+        self.apply_store_policy();
+
+        let size = if self.layout.is_some() {
+            self.write_exact(writer)?
+        } else {
+            self.write_new(writer)?
+        };
+        self.written_size = Some(size);
+
+        if let Some(limit) = self.size_limit {
+            self.check_size(limit).map_err(IoError::other)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets [Hooks](trait.Hooks.html) which are notified of every file written to the archive.
+    pub fn set_hooks(&mut self, hooks: Option<Arc<dyn Hooks>>) {
+        self.hooks = HookSlot::new(hooks);
+    }
+
+    /// Sets whether files that appear to be compressed already are stored without compression,
+    /// even if their [`FileOptions`](struct.FileOptions.html) request it.
+    ///
+    /// A file is considered compressed if its extension belongs to a compressed format,
+    /// such as `.mp3` or `.png`, or if its first sector looks like random data.
+    /// Compressing such files takes time and usually makes them slightly larger.
+    /// Files streamed in with [start_file](struct.Creator.html#method.start_file) are not affected.
+    ///
+    /// Disabled by default.
+    pub fn set_store_incompressible(&mut self, enabled: bool) {
+        self.store_incompressible = enabled;
+    }
+
+    /// Sets whether files added with [add_file](struct.Creator.html#method.add_file) are compressed
+    /// immediately, the same way as files streamed in with [start_file](struct.Creator.html#method.start_file),
+    /// instead of when the archive is written. Their uncompressed contents are then released right away,
+    /// so that the memory held by the creator is roughly the compressed size of the archive.
+    ///
+    /// Files which were compressed when they were added are not affected by a
+    /// [compression cache](struct.Creator.html#method.set_compression_cache) or
+    /// [reuse_unchanged](struct.Creator.html#method.reuse_unchanged).
+    /// [set_store_incompressible](struct.Creator.html#method.set_store_incompressible) is applied
+    /// when they are added, so it must be enabled before adding them.
+    ///
+    /// Disabled by default.
+    pub fn set_compress_on_add(&mut self, enabled: bool) {
+        self.compress_on_add = enabled;
+    }
+
+    /// Sets whether the contents of files added with [add_file](struct.Creator.html#method.add_file)
+    /// are staged in a temporary file instead of memory, and read back one by one when the archive is written,
+    /// e.g. to pack archives in the gigabyte range on machines with little memory.
+    ///
+    /// The temporary file is created when it is first needed, and deleted once the creator is dropped.
+    /// If it cannot be created or written, contents are kept in memory instead.
+    /// Files which are [compressed on add](struct.Creator.html#method.set_compress_on_add)
+    /// keep their compressed sectors in memory instead of being staged.
+    /// As with [lazily added files](struct.Creator.html#method.add_file_lazy), staged files are not affected
+    /// by [reuse_unchanged](struct.Creator.html#method.reuse_unchanged), and
+    /// [set_store_incompressible](struct.Creator.html#method.set_store_incompressible) is applied
+    /// when they are added.
+    ///
+    /// Disabled by default.
+    pub fn set_spill_to_disk(&mut self, enabled: bool) {
+        self.spill_to_disk = enabled;
+    }
+
+    /// Disables compression of files which are unlikely to benefit from it, if enabled.
+    fn apply_store_policy(&mut self) {
+        if !self.store_incompressible {
+            return;
+        }
+
+        let sector_size = self.sector_size as usize;
+        for file in self.added_files.values_mut() {
+            let is_candidate = file.options.compression != Compression::None
+                && file.raw.is_none()
+                && file.precompressed.is_none()
+                && file.provider.is_none();
+
+            if is_candidate && is_incompressible(&file.file_name, &file.contents, sector_size) {
+                file.options.compression = Compression::None;
+            }
+        }
+    }
+
+    /// Enables generating an `(attributes)` file with the CRC32 and MD5 checksums of all files,
+    /// like the World Editor and StormLib do, so that other tools can verify the archive.
+    ///
+    /// Modification times are not included, so that identical inputs produce identical archives.
+    /// Files whose uncompressed contents are not known, such as preserved files of an
+    /// [exact copy](struct.Creator.html#method.copy_exact), get zeroed checksums, which readers ignore.
+    /// Has no effect on [append](struct.Creator.html#method.append), or when writing an exact copy.
+    pub fn set_generate_attributes(&mut self, enabled: bool) {
+        self.generate_attributes = enabled;
+    }
+
+    /// Sets the maximum size of archives written by [write](struct.Creator.html#method.write),
+    /// e.g. 8 MiB for classic Warcraft III maps, or 128 MiB for current versions.
+    ///
+    /// The size is measured from the MPQ header to the end of the archive, excluding any prefix.
+    /// To only warn about an exceeded size instead of failing, leave the limit unset and call
+    /// [check_size](struct.Creator.html#method.check_size) after writing.
+    pub fn set_size_limit(&mut self, limit: Option<u64>) {
+        self.size_limit = limit;
+    }
+
+    /// Checks whether the most recently written archive fits into `limit` bytes,
+    /// and returns a breakdown of the largest files if it does not.
+    ///
+    /// Always succeeds if nothing has been written yet.
+    pub fn check_size(&self, limit: u64) -> Result<(), SizeLimitExceeded> {
+        let size = match self.written_size {
+            Some(size) if size > limit => size,
+            _ => return Ok(()),
+        };
+
+        let mut files: Vec<(String, u64)> = self
+            .added_files
+            .values()
+            .map(|file| (file.file_name.clone(), file.compressed_size))
+            .collect();
+        files.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        Err(SizeLimitExceeded { limit, size, files })
+    }
+
+    fn write_new<W>(&mut self, mut writer: W) -> Result<u64, IoError>
+    where
+        W: Write + Seek,
+    {
+        let Creator {
+            added_files,
+            sector_size,
+            technical_file_options,
+            format_version,
+            prefix,
+            compression_cache,
+            generate_attributes,
+            hooks,
+            ..
+        } = self;
+        let sector_size = *sector_size;
+        let format_version = *format_version;
+
+        writer.write_all(prefix)?;
+
+        let current_pos = writer.stream_position()?;
+        // starting from the current pos, this will find the closest valid header position
+        let archive_start = current_pos.div_ceil(HEADER_BOUNDARY) * HEADER_BOUNDARY;
+        writer.seek(SeekFrom::Start(archive_start))?;
+
+        // skip writing the header for now
+        let header_size = match format_version {
+            FormatVersion::V1 => HEADER_MPQ_SIZE,
+            FormatVersion::V2 => HEADER_MPQ_SIZE_V2,
+            FormatVersion::V3 => HEADER_MPQ_SIZE_V3,
+            FormatVersion::V4 => HEADER_MPQ_SIZE_V4,
+        };
+        writer.seek(SeekFrom::Current(header_size as i64))?;
+
+        // create a listfile, listing each name only once even if it has multiple locales
+        let mut listfile = String::new();
+        let mut listed = HashSet::new();
+        for (key, file) in added_files.iter() {
+            if !file.file_name.is_empty() && listed.insert((key.hash_a, key.hash_b)) {
+                listfile += &file.file_name;
+                listfile += "\r\n";
+            }
+        }
+
+        // add it to the file list
+        {
+            let key = FileKey::new("(listfile)", 0, 0);
+            added_files.insert(
+                key,
+                FileRecord::new("(listfile)", listfile, *technical_file_options),
+            );
+        }
+
+        // the attributes are written last, since they cover every other file
+        let attributes_key = FileKey::new("(attributes)", 0, 0);
+        if *generate_attributes {
+            added_files.shift_remove(&attributes_key);
+        }
+
+        // write out all the files back-to-back,
+        // while the next files are compressed on a separate thread
+        let pipelined = pipelined_files(added_files, compression_cache.as_ref());
+        let cancelled = AtomicBool::new(false);
+        std::thread::scope(|scope| -> Result<(), IoError> {
+            // at most this many files are handed to the thread at once,
+            // so that sending jobs and results never blocks
+            let max_pending = PIPELINE_DEPTH + 1;
+            let (job_sender, job_receiver) = sync_channel::<PipelineJob>(max_pending);
+            let (result_sender, result_receiver) = sync_channel(max_pending);
+            let cancelled = &cancelled;
+
+            scope.spawn(move || {
+                for job in job_receiver {
+                    let prepared = if cancelled.load(AtomicOrdering::Relaxed) {
+                        job.cancel()
+                    } else {
+                        job.run(sector_size)
+                    };
+
+                    if result_sender.send(prepared).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let (mut sent, mut received) = (0, 0);
+            let mut write_files = || -> Result<(), IoError> {
+                for index in 0..added_files.len() {
+                    // the contents of a file are only taken once its job is sent,
+                    // so that they can be put back if writing fails
+                    while sent < pipelined.len() && sent - received < max_pending {
+                        let job =
+                            PipelineJob::take(pipelined[sent], &mut added_files[pipelined[sent]]);
+                        job_sender
+                            .send(job)
+                            .map_err(|_| IoError::other("compression thread stopped"))?;
+                        sent += 1;
+                    }
+
+                    let file = &mut added_files[index];
+                    if received < sent && pipelined[received] == index {
+                        let prepared = result_receiver
+                            .recv()
+                            .map_err(|_| IoError::other("compression thread stopped"))?;
+                        received += 1;
+
+                        write_prepared_file(
+                            sector_size,
+                            archive_start,
+                            &mut writer,
+                            file,
+                            prepared,
+                        )?;
+                    } else {
+                        write_file(
+                            sector_size,
+                            archive_start,
+                            &mut writer,
+                            file,
+                            compression_cache.as_ref(),
+                        )?;
+                    }
+                    check_file_limits(file, format_version)?;
+
+                    if let Some(hooks) = hooks.get() {
+                        hooks.on_file_written(&file.file_name, file.offset, file.compressed_size);
+                    }
+                }
+
+                Ok(())
+            };
+
+            let written = write_files();
+            if written.is_err() {
+                // the pending jobs are handed back without being run, to restore their files
+                cancelled.store(true, AtomicOrdering::Relaxed);
+                drop(job_sender);
+
+                for prepared in result_receiver {
+                    let index = prepared.index;
+                    prepared.restore(&mut added_files[index]);
+                }
+            }
+
+            written
+        })?;
+
+        // the checksums of lazily added files are only known once they have been written
+        if *generate_attributes {
+            let mut digests: Vec<Digests> = added_files
+                .values()
+                .map(|file| file.digests().unwrap_or_default())
+                .collect();
+            // the attributes cannot contain their own checksums
+            digests.push(Digests::default());
+
+            let attributes = Attributes::from_digests(&digests).to_bytes();
+            added_files.insert(
+                attributes_key,
+                FileRecord::new("(attributes)", attributes, *technical_file_options),
+            );
+            let file = &mut added_files[&attributes_key];
+
+            write_file(
+                sector_size,
+                archive_start,
+                &mut writer,
+                file,
+                compression_cache.as_ref(),
+            )?;
+            check_file_limits(file, format_version)?;
+
+            if let Some(hooks) = hooks.get() {
+                hooks.on_file_written(&file.file_name, file.offset, file.compressed_size);
+            }
+        }
+
+        let mut hashtable_size = MIN_HASH_TABLE_SIZE;
+        while hashtable_size < added_files.len() {
+            hashtable_size *= 2;
+        }
+
+        // write hash table and remember its location
+        let hashtable = build_hashtable(hashtable_size, added_files);
+        let hashtable_location = write_hashtable(&mut writer, archive_start, &hashtable)?;
+
+        // write block table and remember its location
+        let blocktable = build_blocktable(added_files);
+        let blocktable_location = write_blocktable(&mut writer, archive_start, &blocktable)?;
+
+        // the hi-block table follows the block table, if any file is located beyond 4 GiB
+        let needs_hiblocktable = format_version != FormatVersion::V1
+            && blocktable
+                .iter()
+                .any(|entry| entry.file_pos > u64::from(u32::MAX));
+        let hiblocktable_location = if needs_hiblocktable {
+            Some(write_hiblocktable(&mut writer, archive_start, &blocktable)?)
+        } else {
+            None
+        };
+
+        // write header
+        let archive_end = writer.stream_position()?;
+        check_archive_limits(archive_end - archive_start, format_version)?;
+        write_header(
+            &mut writer,
+            (archive_start, archive_end),
+            &hashtable_location,
+            &blocktable_location,
+            hiblocktable_location.as_ref(),
+            sector_size,
+            format_version,
+        )?;
+
+        Ok(archive_end - archive_start)
+    }
+}
+
+impl Creator {
+    /// Appends the added files to an existing archive in place, e.g. to add a few files to a large map
+    /// without rewriting all of it.
+    ///
+    /// The new files are written after the existing file data, followed by updated hash and block tables
+    /// and a new header. Existing files are neither read nor moved. Added files replace existing files
+    /// with the same name, locale and platform, whose old data is left unreferenced in the archive.
+    /// If any new names are added, an extended `(listfile)` is written.
+    ///
+    /// The archive keeps its header offset, sector size, hash table size and header version,
+    /// and HET and BET tables are rebuilt if it had any. Appending fails before anything is written
+    /// if the hash table does not have space for all new files. The creator's prefix and format
+    /// version are ignored.
+    ///
+    /// Returns the position in `target` at which the archive now ends. Any data following it,
+    /// e.g. the old tables if the archive shrank, is left untouched and can be truncated by the caller.
+    ///
+    /// Since the archive is modified in place, it will be left damaged if writing fails midway.
+    /// Creators made with [copy_exact](struct.Creator.html#method.copy_exact) cannot be appended.
+    pub fn append<F>(&mut self, target: F) -> Result<u64, Error>
+    where
+        F: Read + Write + Seek,
+    {
+        self.append_removing(target, &[])
+    }
+
+    /// Same as [append](struct.Creator.html#method.append), but also deletes the `removed` hash table entries.
+    /// The block table entry of a removed file is cleared once no other hash table entry refers to it,
+    /// and its name is dropped from the `(listfile)`.
+    pub(crate) fn append_removing<F>(
+        &mut self,
+        mut target: F,
+        removed: &[HashEntry],
+    ) -> Result<u64, Error>
+    where
+        F: Read + Write + Seek,
+    {
+        if self.layout.is_some() {
+            return Err(IoError::new(
+                std::io::ErrorKind::InvalidInput,
+                "cannot append an exact copy to an archive",
+            )
+            .into());
+        }
+
+        self.apply_store_policy();
+
+        let (info, tables, mut names, mut hashtable, mut blocktable) = {
+            let mut archive = Archive::open(&mut target)?;
+
+            (
+                archive.seeker().info().clone(),
+                ExistingTables::from_archive(&mut archive)?,
+                archive.files_result()?.unwrap_or_default(),
+                archive.hash_table().entries().to_vec(),
+                archive.block_table().entries().to_vec(),
+            )
+        };
+        let archive_start = info.header_offset;
+        let format_version = tables.format_version();
+
+        // files compressed ahead of time were split into sectors of the creator's sector size
+        if info.sector_size != self.sector_size
+            && self
+                .added_files
+                .values()
+                .any(|file| file.precompressed.is_some())
+        {
+            return Err(IoError::new(
+                std::io::ErrorKind::InvalidInput,
+                "cannot append files compressed ahead of time to an archive with another sector size",
+            )
+            .into());
+        }
+
+        if hashtable.is_empty() {
+            return Err(IoError::new(
+                std::io::ErrorKind::InvalidInput,
+                "cannot append to an archive without a hash table",
+            )
+            .into());
+        }
+
+        let names_before_removal = names.len();
+        if !removed.is_empty() {
+            remove_entries(&mut hashtable, &mut blocktable, removed);
+
+            // only drop names which no longer have any variant in the archive
+            names.retain(|name| {
+                let key = FileKey::new(name, 0, 0);
+
+                hashtable.iter().any(|entry| {
+                    !entry.is_free() && entry.hash_a == key.hash_a && entry.hash_b == key.hash_b
+                })
+            });
+        }
+
+        let Creator {
+            added_files,
+            technical_file_options,
+            compression_cache,
+            hooks,
+            ..
+        } = self;
+
+        let listfile = extended_listfile(&names, added_files).or_else(|| {
+            (names.len() != names_before_removal).then(|| {
+                names
+                    .iter()
+                    .map(|name| format!("{}\r\n", name))
+                    .collect::<String>()
+            })
+        });
+        if let Some(listfile) = listfile {
+            let key = FileKey::new("(listfile)", 0, 0);
+            added_files.insert(
+                key,
+                FileRecord::new("(listfile)", listfile, *technical_file_options),
+            );
+        }
+
+        // files which already exist in the archive replace their block table entry
+        for (key, file) in added_files.iter_mut() {
+            file.block_index = hashtable
+                .iter()
+                .enumerate()
+                .find(|(position, entry)| {
+                    (entry.block_index as usize) < blocktable.len()
+                        && FileKey::from_hash_entry(entry, *position) == *key
+                })
+                .map(|(_, entry)| entry.block_index as usize);
+        }
+
+        // the file data may overwrite the old tables, so a full hash table must be detected up front
+        let new_files = added_files
+            .values()
+            .filter(|file| file.block_index.is_none())
+            .count();
+        if new_files > hashtable.iter().filter(|entry| entry.is_free()).count() {
+            return Err(IoError::other("not enough space in the hash table for new files").into());
+        }
+
+        // write the files after all existing file data
+        let data_end = data_end(&blocktable, u64::from(tables.header.header_size));
+        target.seek(SeekFrom::Start(archive_start + data_end))?;
+        for file in added_files.values_mut() {
+            write_file(
+                info.sector_size,
+                archive_start,
+                &mut target,
+                file,
+                compression_cache.as_ref(),
+            )?;
+            check_file_limits(file, format_version)?;
+
+            if let Some(hooks) = hooks.get() {
+                hooks.on_file_written(&file.file_name, file.offset, file.compressed_size);
+            }
+        }
+
+        let archive_end = write_existing_tables(
+            &mut target,
+            archive_start,
+            &tables,
+            (hashtable, blocktable),
+            added_files,
+        )?;
+
+        Ok(archive_end)
+    }
+
+    /// Writes out an exact copy of an archive, as created by [copy_exact](struct.Creator.html#method.copy_exact).
+    fn write_exact<W>(&mut self, mut writer: W) -> Result<u64, IoError>
+    where
+        W: Write + Seek,
+    {
+        let Creator {
+            added_files,
+            sector_size,
+            technical_file_options,
+            prefix,
+            compression_cache,
+            hooks,
+            layout,
+            ..
+        } = self;
+        let sector_size = *sector_size;
+        let layout = layout.as_ref().expect("exact copy without a layout");
+        let format_version = layout.tables.format_version();
+
+        writer.write_all(prefix)?;
+
+        let current_pos = writer.stream_position()?;
+        let archive_start = current_pos.div_ceil(HEADER_BOUNDARY) * HEADER_BOUNDARY;
+
+        // regenerate the listfile only if any new names were added
+        if let Some(listfile) = extended_listfile(&layout.names, added_files) {
+            let key = FileKey::new("(listfile)", 0, 0);
+            let mut record = FileRecord::new("(listfile)", listfile, *technical_file_options);
+            record.block_index = added_files.get(&key).and_then(|file| file.block_index);
+            added_files.insert(key, record);
+        }
+
+        // write the preserved files at their original positions
+        let mut append_pos = layout.data_end;
+        for (offset, contents) in &layout.orphans {
+            writer.seek(SeekFrom::Start(archive_start + offset))?;
+            writer.write_all(contents)?;
+        }
+
+        for file in added_files.values_mut() {
+            if let Some(offset) = file.placement {
+                writer.seek(SeekFrom::Start(archive_start + offset))?;
+                writer.write_all(&file.contents)?;
+
+                file.offset = offset;
+                file.compressed_size = file.contents.len() as u64;
+                append_pos = append_pos.max(offset + file.compressed_size);
+
+                if let Some(hooks) = hooks.get() {
+                    hooks.on_file_written(&file.file_name, file.offset, file.compressed_size);
+                }
+            }
+        }
+
+        // write all new or replaced files after the preserved data
+        writer.seek(SeekFrom::Start(archive_start + append_pos))?;
+        for file in added_files.values_mut() {
+            if file.placement.is_none() {
+                write_file(
+                    sector_size,
+                    archive_start,
+                    &mut writer,
+                    file,
+                    compression_cache.as_ref(),
+                )?;
+                check_file_limits(file, format_version)?;
+
+                if let Some(hooks) = hooks.get() {
+                    hooks.on_file_written(&file.file_name, file.offset, file.compressed_size);
+                }
+            }
         }
+
+        // update the original tables, keeping the original header
+        let archive_end = write_existing_tables(
+            &mut writer,
+            archive_start,
+            &layout.tables,
+            (layout.hash_entries.clone(), layout.block_entries.clone()),
+            added_files,
+        )?;
+
+        Ok(archive_end - archive_start)
     }
 }
 
-impl FileOptions {
-    fn flags(self) -> u32 {
-        let mut flags = MPQ_FILE_EXISTS;
+/// A file which is compressed on the pipeline thread while the preceding files are written.
+struct PipelineJob {
+    index: usize,
+    contents: FileContents,
+    provider: Option<ContentProvider>,
+    compression: Compression,
+}
 
-        if self.encrypt {
-            flags |= MPQ_FILE_ENCRYPTED;
+/// A file which was handed back by the pipeline thread, together with its contents.
+struct PreparedFile {
+    index: usize,
+    contents: FileContents,
+    // only handed back if the job was cancelled before the provider was called
+    provider: Option<ContentProvider>,
+    // whether the contents were produced by a lazy provider, and are released once written
+    lazy: bool,
+    // `None` if the job was cancelled
+    precompressed: Option<Result<PrecompressedData, IoError>>,
+}
+
+impl PipelineJob {
+    fn take(index: usize, file: &mut FileRecord) -> PipelineJob {
+        PipelineJob {
+            index,
+            contents: std::mem::take(&mut file.contents),
+            provider: file.provider.take(),
+            compression: file.options.compression,
         }
+    }
 
-        if self.adjust_key {
-            flags |= MPQ_FILE_ADJUST_KEY;
+    fn run(self, sector_size: u64) -> PreparedFile {
+        let (contents, lazy) = match self.provider {
+            Some(ContentProvider(provider)) => match provider() {
+                Ok(contents) => (contents.into(), true),
+                Err(error) => {
+                    return PreparedFile {
+                        index: self.index,
+                        contents: FileContents::default(),
+                        provider: Some(ContentProvider::unavailable(PROVIDER_FAILED)),
+                        lazy: true,
+                        precompressed: Some(Err(error)),
+                    }
+                }
+            },
+            None => (self.contents, false),
+        };
+
+        let mut digests = DigestHasher::default();
+        digests.update(&contents);
+
+        let precompressed = PrecompressedData {
+            sectors: compress_all_sectors(&contents, sector_size, self.compression),
+            uncompressed_size: contents.len() as u64,
+            digests,
+        };
+
+        PreparedFile {
+            index: self.index,
+            contents,
+            provider: None,
+            lazy,
+            precompressed: Some(Ok(precompressed)),
         }
+    }
 
-        if self.compress {
-            flags |= MPQ_FILE_COMPRESS;
+    fn cancel(self) -> PreparedFile {
+        PreparedFile {
+            index: self.index,
+            contents: self.contents,
+            provider: self.provider,
+            lazy: false,
+            precompressed: None,
         }
+    }
+}
 
-        flags
+impl PreparedFile {
+    /// Puts the contents and provider back into the file, so that it can be written again.
+    fn restore(self, file: &mut FileRecord) {
+        file.contents = self.contents;
+        file.provider = self.provider;
     }
 }
 
-#[derive(Debug)]
-/// Creator capable of creating MPQ Version 1 archives.
-///
-/// Will hold all the files in memory until asked to [write](struct.Creator.html#method.write) them
-/// to a `writer`.
-///
-/// When writing, a `(listfile)` will be automatically appended to the archive.
-// TODO: Add support for multiple compression types
-pub struct Creator {
-    added_files: IndexMap<FileKey, FileRecord>,
+/// Returns the indices of all files which are compressed while writing, in the order they are written.
+/// Files which are compressed already, or whose compression is deduplicated by a cache, are not included.
+fn pipelined_files(
+    added_files: &IndexMap<FileKey, FileRecord>,
+    cache: Option<&CompressionCache>,
+) -> Vec<usize> {
+    if cache.is_some() {
+        return Vec::new();
+    }
 
-    sector_size: u64,
+    added_files
+        .values()
+        .enumerate()
+        .filter(|(_, file)| {
+            file.options.compression != Compression::None
+                && file.raw.is_none()
+                && file.precompressed.is_none()
+        })
+        .map(|(index, _)| index)
+        .collect()
 }
 
-impl Default for Creator {
-    fn default() -> Creator {
-        Creator {
-            added_files: IndexMap::new(),
-            sector_size: 0x10000,
+/// Writes a file compressed by the pipeline, and puts back its contents afterwards,
+/// or releases them if they were produced lazily. If writing fails, the contents are put back as well.
+fn write_prepared_file<W>(
+    sector_size: u64,
+    archive_start: u64,
+    writer: W,
+    file: &mut FileRecord,
+    mut prepared: PreparedFile,
+) -> Result<(), IoError>
+where
+    W: Write + Seek,
+{
+    let precompressed = match prepared.precompressed.take() {
+        Some(Ok(precompressed)) => precompressed,
+        Some(Err(error)) => {
+            prepared.restore(file);
+            return Err(error);
+        }
+        None => {
+            prepared.restore(file);
+            return Err(IoError::other("compression was cancelled"));
         }
+    };
+
+    file.precompressed = Some(precompressed);
+    let written = write_file_contents(sector_size, archive_start, writer, file, None);
+    let precompressed = file.precompressed.take().unwrap();
+
+    if let Err(error) = written {
+        prepared.restore(file);
+        return Err(error);
+    }
+
+    if prepared.lazy {
+        file.released = Some((
+            precompressed.uncompressed_size,
+            precompressed.digests.finish(),
+        ));
+        file.provider = Some(ContentProvider::unavailable(PROVIDER_RELEASED));
+    } else {
+        file.contents = prepared.contents;
     }
+
+    Ok(())
 }
 
-impl Creator {
-    /// Adds a file to be later written to the archive.
-    ///
-    /// All forward slashes (`/`) in the file path will be auto-converted to backward slashes (`\`)
-    ///
-    /// [`FileOptions`](struct.FileOptions.html) determine the options for adding the file, e.g. encryption and compression.
-    pub fn add_file<C>(&mut self, file_name: &str, contents: C, options: FileOptions)
-    where
-        C: Into<Vec<u8>>,
-    {
-        let file_name = file_name.replace('/', "\\");
-        let key = FileKey::new(&file_name);
+/// Compresses all sectors of a file, spreading them over all available threads
+/// with the `parallel` feature.
+fn compress_all_sectors(
+    contents: &[u8],
+    sector_size: u64,
+    compression: Compression,
+) -> Vec<Vec<u8>> {
+    let sector_count = sector_count_from_size(contents.len() as u64, sector_size);
 
-        self.added_files
-            .insert(key, FileRecord::new(file_name, contents, options));
-    }
+    #[cfg(feature = "parallel")]
+    let sectors = (0..sector_count).into_par_iter();
 
-    /// Writes out the entire archive to the specified writer.
-    ///
-    /// The archive start position is calculated as follows:  
-    /// `((current_pos + (HEADER_BOUNDARY - 1)) / HEADER_BOUNDARY) * HEADER_BOUNDARY`  
-    /// Where `current_pos` is the `writer`'s current seek pos, and `HEADER_BOUNDARY` is 512.
-    ///
-    /// Will write the following:
-    /// - MPQ Header
-    /// - All files with their sector offset table
-    /// - MPQ hash table
-    /// - MPQ block table
-    pub fn write<W>(&mut self, mut writer: W) -> Result<(), IoError>
-    where
-        W: Write + Seek,
-    {
-        let (added_files, sector_size) = match self {
-            Creator {
-                added_files,
-                sector_size,
-            } => (added_files, *sector_size),
-        };
+    #[cfg(not(feature = "parallel"))]
+    let sectors = 0..sector_count;
 
-        let current_pos = writer.seek(SeekFrom::Current(0))?;
-        // starting from the current pos, this will find the closest valid header position
-        let archive_start =
-            ((current_pos + (HEADER_BOUNDARY - 1)) / HEADER_BOUNDARY) * HEADER_BOUNDARY;
-        writer.seek(SeekFrom::Start(archive_start))?;
+    sectors
+        .map(|i| {
+            let sector_start = i * sector_size;
+            let sector_end = min((i + 1) * sector_size, contents.len() as u64);
+
+            compress_mpq_block(
+                &contents[sector_start as usize..sector_end as usize],
+                compression.for_sector(i),
+            )
+            .into_owned()
+        })
+        .collect()
+}
 
-        // skip writing the header for now
-        writer.seek(SeekFrom::Current(HEADER_MPQ_SIZE as i64))?;
+/// Returns the end of all existing file data, relative to the archive start.
+/// If there is none, this is the end of the existing header.
+fn data_end(block_entries: &[BlockEntry], header_size: u64) -> u64 {
+    block_entries
+        .iter()
+        .filter(|entry| entry.flags & MPQ_FILE_EXISTS != 0)
+        .map(|entry| entry.file_pos + entry.compressed_size)
+        .max()
+        .unwrap_or(header_size)
+        .max(header_size)
+}
 
-        // create a listfile
-        let mut listfile = String::new();
-        for file in added_files.values() {
-            listfile += &file.file_name;
-            listfile += "\r\n";
+/// Creates a `(listfile)` for an existing archive, listing the existing `names`
+/// followed by the names of all added files which are not placed at an existing position.
+/// Returns `None` if no new names were added.
+fn extended_listfile(
+    names: &[String],
+    added_files: &IndexMap<FileKey, FileRecord>,
+) -> Option<String> {
+    let mut listed: HashSet<(u32, u32)> = names
+        .iter()
+        .map(|name| {
+            let key = FileKey::new(name, 0, 0);
+            (key.hash_a, key.hash_b)
+        })
+        .collect();
+    let mut new_names = Vec::new();
+    for (key, file) in added_files.iter() {
+        let is_new = file.placement.is_none()
+            && !file.file_name.is_empty()
+            && !TECHNICAL_FILE_NAMES.contains(&file.file_name.as_str());
+
+        if is_new && listed.insert((key.hash_a, key.hash_b)) {
+            new_names.push(file.file_name.as_str());
         }
+    }
 
-        // add it to the file list
-        {
-            let key = FileKey::new("(listfile)");
-            added_files.insert(
-                key,
-                FileRecord::new(
-                    "(listfile)",
-                    listfile,
-                    FileOptions {
-                        compress: true,
-                        encrypt: true,
-                        adjust_key: true,
-                    },
-                ),
-            );
-        }
+    if new_names.is_empty() {
+        return None;
+    }
 
-        // write out all the files back-to-back
-        for file in added_files.values_mut() {
-            write_file(sector_size, archive_start, &mut writer, file)?;
+    let mut listfile = String::new();
+    for name in names.iter().map(String::as_str).chain(new_names) {
+        listfile += name;
+        listfile += "\r\n";
+    }
+
+    Some(listfile)
+}
+
+/// Marks the `removed` entries of an existing hash table as deleted, and clears
+/// the block table entries which are no longer referenced by any hash table entry.
+fn remove_entries(
+    hashtable: &mut [HashEntry],
+    blocktable: &mut [BlockEntry],
+    removed: &[HashEntry],
+) {
+    for entry in hashtable.iter_mut() {
+        let is_removed = removed.iter().any(|removed| {
+            removed.hash_a == entry.hash_a
+                && removed.hash_b == entry.hash_b
+                && removed.locale == entry.locale
+                && removed.platform == entry.platform
+                && removed.block_index == entry.block_index
+        });
+
+        if is_removed {
+            *entry = HashEntry::blank();
+            entry.block_index = HASH_TABLE_DELETED_ENTRY;
         }
+    }
 
-        let mut hashtable_size = MIN_HASH_TABLE_SIZE;
-        while hashtable_size < added_files.len() {
-            hashtable_size *= 2;
+    for removed in removed {
+        let block_index = removed.block_index as usize;
+        let referenced = hashtable
+            .iter()
+            .any(|entry| entry.block_index == removed.block_index);
+
+        if !referenced && block_index < blocktable.len() {
+            blocktable[block_index] = BlockEntry::new(0, 0, 0, 0);
         }
+    }
+}
 
-        // write hash table and remember its position
-        let hashtable_pos = write_hashtable(&mut writer, hashtable_size, &added_files)?;
+/// Updates the tables of an existing archive with the written files.
+/// Files with a block index replace that block table entry, all others are added as new entries.
+fn update_tables(
+    hashtable: &mut [HashEntry],
+    blocktable: &mut Vec<BlockEntry>,
+    added_files: &IndexMap<FileKey, FileRecord>,
+) -> Result<(), IoError> {
+    for (key, file) in added_files.iter() {
+        let block_entry = BlockEntry::new(
+            file.offset,
+            file.compressed_size,
+            file.uncompressed_size(),
+            file.flags(),
+        );
 
-        // write block table and remember its position
-        let blocktable_pos = write_blocktable(&mut writer, &added_files)?;
+        match file.block_index {
+            Some(block_index) => blocktable[block_index] = block_entry,
+            None => {
+                let block_index = blocktable.len() as u32;
 
-        // write header
-        let archive_end = writer.seek(SeekFrom::Current(0))?;
-        write_header(
-            &mut writer,
-            (archive_start, archive_end),
-            (hashtable_pos, hashtable_size),
-            (blocktable_pos, added_files.len()),
-            sector_size,
-        )?;
+                if hashtable.is_empty() || !insert_hash_entry(hashtable, key, block_index) {
+                    return Err(IoError::other(
+                        "not enough space in the hash table for new files",
+                    ));
+                }
 
-        Ok(())
+                blocktable.push(block_entry);
+            }
+        }
     }
+
+    Ok(())
 }
 
-fn write_hashtable<W>(
+/// Updates the tables of an existing archive with the written files, and writes them
+/// at the writer's current position, followed by the existing header with their new locations.
+/// Returns the end of the archive.
+///
+/// The hi-block table is written if the archive had one, or if any file requires it.
+/// If the archive had HET and BET tables, they are rebuilt, or reused as-is if the block table is unchanged.
+fn write_existing_tables<W>(
     mut writer: W,
-    hashtable_size: usize,
+    archive_start: u64,
+    existing: &ExistingTables,
+    (mut hashtable, mut blocktable): (Vec<HashEntry>, Vec<BlockEntry>),
     added_files: &IndexMap<FileKey, FileRecord>,
 ) -> Result<u64, IoError>
 where
     W: Write + Seek,
 {
-    let hashtable_pos = writer.seek(SeekFrom::Current(0))?;
+    let format_version = existing.format_version();
+    let existing_blocks = blocktable.clone();
+    update_tables(&mut hashtable, &mut blocktable, added_files)?;
+
+    let (het_table, bet_table) = match &existing.extended {
+        Some(extended) => {
+            let (het_table, bet_table) = if blocktable == existing_blocks {
+                let (het_table, bet_table) = extended.stored();
+                (het_table.to_vec(), bet_table.to_vec())
+            } else {
+                let mut entry_hashes: Vec<Option<u64>> = (0..blocktable.len())
+                    .map(|index| {
+                        let exists = blocktable[index].flags & MPQ_FILE_EXISTS != 0;
+                        extended.entry_hash(index).filter(|_| exists)
+                    })
+                    .collect();
+
+                // new files are added to the block table in order
+                let mut new_index = existing_blocks.len();
+                for file in added_files.values() {
+                    let index = file.block_index.unwrap_or_else(|| {
+                        new_index += 1;
+                        new_index - 1
+                    });
+
+                    if !file.file_name.is_empty() {
+                        entry_hashes[index] = Some(extended.name_hash(&file.file_name));
+                    }
+                }
+
+                extended.build(&blocktable, &entry_hashes)
+            };
+
+            (
+                Some(write_table(&mut writer, archive_start, &het_table, 0)?),
+                Some(write_table(&mut writer, archive_start, &bet_table, 0)?),
+            )
+        }
+        None => (None, None),
+    };
+
+    let hashtable_location = write_hashtable(&mut writer, archive_start, &hashtable)?;
+    let blocktable_location = write_blocktable(&mut writer, archive_start, &blocktable)?;
+
+    let needs_hiblocktable = format_version != FormatVersion::V1
+        && (existing.header.hi_block_table_offset != 0
+            || blocktable
+                .iter()
+                .any(|entry| entry.file_pos > u64::from(u32::MAX)));
+    let hiblocktable_location = if needs_hiblocktable {
+        Some(write_hiblocktable(&mut writer, archive_start, &blocktable)?)
+    } else {
+        None
+    };
+
+    let archive_end = writer.stream_position()?;
+    check_archive_limits(archive_end - archive_start, format_version)?;
+
+    let mut header = existing.header.clone();
+    header.set_tables(
+        archive_end - archive_start,
+        &hashtable_location,
+        &blocktable_location,
+        hiblocktable_location.as_ref(),
+        (het_table.as_ref(), bet_table.as_ref()),
+    );
+    writer.seek(SeekFrom::Start(archive_start))?;
+    header.write(&mut writer)?;
+
+    Ok(archive_end)
+}
+
+fn build_hashtable(
+    hashtable_size: usize,
+    added_files: &IndexMap<FileKey, FileRecord>,
+) -> Vec<HashEntry> {
     let mut hashtable = vec![HashEntry::blank(); hashtable_size];
-    let hash_index_mask = hashtable_size - 1;
 
-    for (block_index, (key, _)) in added_files.iter().enumerate() {
-        let mut hash_index = (key.index as usize) & hash_index_mask;
-        let hash_entry = HashEntry::new(key.hash_a, key.hash_b, block_index as u32);
+    for (block_index, key) in added_files.keys().enumerate() {
+        let inserted = insert_hash_entry(&mut hashtable, key, block_index as u32);
 
-        while !hashtable[hash_index].is_blank() {
-            hash_index += 1;
-            if hash_index == hashtable_size {
-                hash_index = 0;
-            }
+        debug_assert!(inserted, "hash table is too small");
+    }
+
+    hashtable
+}
+
+/// Inserts an entry for the specified key into the first free slot of the hash table.
+/// Returns `false` if the hash table is full.
+fn insert_hash_entry(hashtable: &mut [HashEntry], key: &FileKey, block_index: u32) -> bool {
+    let hashtable_size = hashtable.len();
+    let start_index = (key.index as usize) & (hashtable_size - 1);
+    let mut hash_index = start_index;
+
+    while !hashtable[hash_index].is_free() {
+        hash_index += 1;
+        if hash_index == hashtable_size {
+            hash_index = 0;
         }
 
-        hashtable[hash_index] = hash_entry;
+        if hash_index == start_index {
+            return false;
+        }
     }
 
-    let mut buf = vec![0u8; hashtable_size * HASH_TABLE_ENTRY_SIZE as usize];
+    hashtable[hash_index] = HashEntry::new(
+        key.hash_a,
+        key.hash_b,
+        key.locale,
+        key.platform,
+        block_index,
+    );
+
+    true
+}
+
+fn build_blocktable(added_files: &IndexMap<FileKey, FileRecord>) -> Vec<BlockEntry> {
+    added_files
+        .values()
+        .map(|file| {
+            BlockEntry::new(
+                file.offset,
+                file.compressed_size,
+                file.uncompressed_size(),
+                file.flags(),
+            )
+        })
+        .collect()
+}
+
+fn write_hashtable<W>(
+    writer: W,
+    archive_start: u64,
+    hashtable: &[HashEntry],
+) -> Result<TableLocation, IoError>
+where
+    W: Write + Seek,
+{
+    let mut buf = vec![0u8; hashtable.len() * HASH_TABLE_ENTRY_SIZE as usize];
 
     let mut cursor = buf.as_mut_slice();
     for entry in hashtable {
@@ -263,60 +2301,150 @@ where
     }
     encrypt_mpq_block(&mut buf, HASH_TABLE_KEY);
 
-    writer.write_all(&buf)?;
-
-    Ok(hashtable_pos)
+    write_table(writer, archive_start, &buf, hashtable.len())
 }
 
 fn write_blocktable<W>(
+    writer: W,
+    archive_start: u64,
+    blocktable: &[BlockEntry],
+) -> Result<TableLocation, IoError>
+where
+    W: Write + Seek,
+{
+    let mut buf = vec![0u8; blocktable.len() * BLOCK_TABLE_ENTRY_SIZE as usize];
+
+    let mut cursor = buf.as_mut_slice();
+    for block_entry in blocktable {
+        block_entry.write(&mut cursor)?;
+    }
+
+    encrypt_mpq_block(&mut buf, BLOCK_TABLE_KEY);
+
+    write_table(writer, archive_start, &buf, blocktable.len())
+}
+
+/// Writes the upper 16 bits of each block's file position, which do not fit into the block table.
+/// Unlike the other tables, the hi-block table is not encrypted.
+fn write_hiblocktable<W>(
+    writer: W,
+    archive_start: u64,
+    blocktable: &[BlockEntry],
+) -> Result<TableLocation, IoError>
+where
+    W: Write + Seek,
+{
+    let mut buf = Vec::with_capacity(blocktable.len() * 2);
+    for block_entry in blocktable {
+        buf.write_u16::<LE>((block_entry.file_pos >> 32) as u16)?;
+    }
+
+    write_table(writer, archive_start, &buf, blocktable.len())
+}
+
+/// Writes the stored form of a table at the writer's current position.
+fn write_table<W>(
     mut writer: W,
-    added_files: &IndexMap<FileKey, FileRecord>,
-) -> Result<u64, IoError>
+    archive_start: u64,
+    data: &[u8],
+    entries: usize,
+) -> Result<TableLocation, IoError>
 where
     W: Write + Seek,
 {
-    let blocktable_pos = writer.seek(SeekFrom::Current(0))?;
+    let offset = writer.stream_position()? - archive_start;
 
-    let mut buf = vec![0u8; added_files.len() * BLOCK_TABLE_ENTRY_SIZE as usize];
+    writer.write_all(data)?;
 
-    let mut cursor = buf.as_mut_slice();
-    for file in added_files.values() {
-        let flags = file.options.flags();
+    Ok(TableLocation {
+        offset,
+        size: data.len() as u64,
+        entries: entries as u64,
+        md5: Md5::digest(data).into(),
+    })
+}
 
-        let block_entry = BlockEntry::new(
-            file.offset,
-            file.compressed_size,
-            file.contents.len() as u64,
-            flags,
-        );
+/// Fails if a written file cannot be described by a block table entry of `format_version`.
+fn check_file_limits(file: &FileRecord, format_version: FormatVersion) -> Result<(), IoError> {
+    if file.compressed_size > u64::from(u32::MAX) || file.uncompressed_size() > u64::from(u32::MAX)
+    {
+        return Err(IoError::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is larger than 4 GiB", file.file_name),
+        ));
+    }
 
-        block_entry.write(&mut cursor)?;
+    if file.offset + file.compressed_size > format_version.max_position() {
+        return Err(IoError::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "{} is located beyond the archive size supported by {:?}",
+                file.file_name, format_version
+            ),
+        ));
     }
 
-    encrypt_mpq_block(&mut buf, BLOCK_TABLE_KEY);
-    writer.write_all(&buf)?;
+    Ok(())
+}
+
+/// Fails if the tables end beyond the positions that can be stored in the header of `format_version`.
+fn check_archive_limits(archive_size: u64, format_version: FormatVersion) -> Result<(), IoError> {
+    if archive_size > format_version.max_position() {
+        return Err(IoError::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "archive size of {} bytes is not supported by {:?}",
+                archive_size, format_version
+            ),
+        ));
+    }
 
-    Ok(blocktable_pos)
+    Ok(())
 }
 
 fn write_header<W>(
     mut writer: W,
     (archive_start, archive_end): (u64, u64),
-    (hashtable_pos, hashtable_size): (u64, usize),
-    (blocktable_pos, blocktable_size): (u64, usize),
+    hashtable: &TableLocation,
+    blocktable: &TableLocation,
+    hiblocktable: Option<&TableLocation>,
     sector_size: u64,
+    format_version: FormatVersion,
 ) -> Result<(), IoError>
 where
     W: Write + Seek,
 {
-    let header = FileHeader::new_v1(
-        (archive_end - archive_start) as u32,
-        sector_size as u32,
-        (hashtable_pos - archive_start) as u32,
-        (blocktable_pos - archive_start) as u32,
-        hashtable_size as u32,
-        blocktable_size as u32,
-    );
+    let header = match format_version {
+        FormatVersion::V1 => FileHeader::new_v1(
+            (archive_end - archive_start) as u32,
+            sector_size as u32,
+            hashtable.offset as u32,
+            blocktable.offset as u32,
+            hashtable.entries as u32,
+            blocktable.entries as u32,
+        ),
+        FormatVersion::V2 => FileHeader::new_v2(
+            archive_end - archive_start,
+            sector_size as u32,
+            hashtable,
+            blocktable,
+            hiblocktable,
+        ),
+        FormatVersion::V3 => FileHeader::new_v3(
+            archive_end - archive_start,
+            sector_size as u32,
+            hashtable,
+            blocktable,
+            hiblocktable,
+        ),
+        FormatVersion::V4 => FileHeader::new_v4(
+            archive_end - archive_start,
+            sector_size as u32,
+            hashtable,
+            blocktable,
+            hiblocktable,
+        ),
+    };
 
     writer.seek(SeekFrom::Start(archive_start))?;
     header.write(&mut writer)?;
@@ -328,45 +2456,137 @@ where
 /// If the file is marked for compression, a Sector Offset Table (SOT) will be written, and all sectors will attempt compression.
 /// If the file is not marked for compression, no SOT will be written.
 /// If the file is marked for encryption, it will also be encrypted after compression.
+///
+/// The contents of lazily added files are produced before, and released after writing them.
 fn write_file<W>(
+    sector_size: u64,
+    archive_start: u64,
+    writer: W,
+    file: &mut FileRecord,
+    cache: Option<&CompressionCache>,
+) -> Result<(), IoError>
+where
+    W: Write + Seek,
+{
+    let provider = match file.provider.take() {
+        Some(ContentProvider(provider)) => provider,
+        None => return write_file_contents(sector_size, archive_start, writer, file, cache),
+    };
+
+    file.contents = match provider() {
+        Ok(contents) => contents.into(),
+        Err(error) => {
+            file.provider = Some(ContentProvider::unavailable(PROVIDER_FAILED));
+            return Err(error);
+        }
+    };
+    // if writing fails, the produced contents are kept to be written again
+    write_file_contents(sector_size, archive_start, writer, file, cache)?;
+
+    let contents = std::mem::take(&mut file.contents);
+    file.released = Some((contents.len() as u64, Digests::of(&contents)));
+    file.provider = Some(ContentProvider::unavailable(PROVIDER_RELEASED));
+
+    Ok(())
+}
+
+fn write_file_contents<W>(
     sector_size: u64,
     archive_start: u64,
     mut writer: W,
     file: &mut FileRecord,
+    cache: Option<&CompressionCache>,
 ) -> Result<(), IoError>
 where
     W: Write + Seek,
 {
     let options = file.options;
-    let sector_count = sector_count_from_size(file.contents.len() as u64, sector_size);
-    let file_start = writer.seek(SeekFrom::Current(0))?;
+    let file_size = file.uncompressed_size();
+    let sector_count = sector_count_from_size(file_size, sector_size);
+    let file_start = writer.stream_position()?;
+
+    // files in their stored form are written as-is
+    if let Some(raw) = file.raw {
+        if raw.previous_key.is_some() || (raw.encrypt && raw.flags & MPQ_FILE_ENCRYPTED != 0) {
+            let key = calculate_file_key(
+                &file.file_name,
+                (file_start - archive_start) as u32,
+                raw.uncompressed_size as u32,
+                raw.flags & MPQ_FILE_ADJUST_KEY != 0,
+            );
+            let block_entry = BlockEntry::new(
+                0,
+                file.contents.len() as u64,
+                raw.uncompressed_size,
+                raw.flags,
+            );
+            let encrypted = block_entry
+                .reencrypt(&file.contents, sector_size, raw.previous_key, key)
+                .map_err(|_| {
+                    IoError::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("cannot encrypt {}", file.file_name),
+                    )
+                })?;
+
+            writer.write_all(&encrypted)?;
+        } else {
+            writer.write_all(&file.contents)?;
+        }
+
+        file.offset = file_start - archive_start;
+        file.compressed_size = file.contents.len() as u64;
+
+        return Ok(());
+    }
 
     // calculate the encryption key if encryption was requested
     let encryption_key = if options.encrypt {
         Some(calculate_file_key(
             &file.file_name,
             (file_start - archive_start) as u32,
-            file.contents.len() as u32,
+            file_size as u32,
             options.adjust_key,
         ))
     } else {
         None
     };
 
-    if options.compress {
+    if options.compression != Compression::None {
         let mut offsets: Vec<u32> = Vec::new();
 
+        let compressed_sectors = match (&file.precompressed, cache) {
+            (None, Some(cache)) => {
+                Some(cache.compress(&file.contents, sector_size, options.compression))
+            }
+            #[cfg(feature = "parallel")]
+            (None, None) => Some(compress_all_sectors(
+                &file.contents,
+                sector_size,
+                options.compression,
+            )),
+            _ => None,
+        };
+
         // store the start of the first sector and prepare to write there
         let first_sector_start = ((sector_count + 1) * 4) as u32;
         writer.seek(SeekFrom::Current(i64::from(first_sector_start)))?;
         offsets.push(first_sector_start);
         // write each sector and the offset of its end
         for i in 0..sector_count {
-            let sector_start = i * sector_size;
-            let sector_end = min((i + 1) * sector_size, file.contents.len() as u64);
-            let data = &file.contents[sector_start as usize..sector_end as usize];
-
-            let mut compressed = compress_mpq_block(data);
+            let mut compressed = match (&file.precompressed, &compressed_sectors) {
+                (Some(precompressed), _) => Cow::Borrowed(&precompressed.sectors[i as usize][..]),
+                (None, Some(sectors)) => Cow::Borrowed(&sectors[i as usize][..]),
+                (None, None) => {
+                    let sector_start = i * sector_size;
+                    let sector_end = min((i + 1) * sector_size, file_size);
+
+                    compress_mpq_block(
+                        &file.contents[sector_start as usize..sector_end as usize],
+                        options.compression.for_sector(i),
+                    )
+                }
+            };
 
             // encrypt the block if encryption was requested
             if let Some(key) = encryption_key.map(|k| k + i as u32) {
@@ -378,11 +2598,11 @@ where
             // store the end of the current sector
             // which is also the start of the next sector if there is one
 
-            let current_offset = writer.seek(SeekFrom::Current(0))?;
+            let current_offset = writer.stream_position()?;
             offsets.push((current_offset - file_start) as u32);
         }
 
-        let file_end = writer.seek(SeekFrom::Current(0))?;
+        let file_end = writer.stream_position()?;
 
         // write the sector offset table
         {
@@ -412,7 +2632,7 @@ where
         // write each sector
         for i in 0..sector_count {
             let sector_start = i * sector_size;
-            let sector_end = min((i + 1) * sector_size, file.contents.len() as u64);
+            let sector_end = min((i + 1) * sector_size, file_size);
             let data = &file.contents[sector_start as usize..sector_end as usize];
             let mut buf = Cow::Borrowed(data);
 
@@ -424,7 +2644,7 @@ where
             writer.write_all(&buf)?;
         }
 
-        let file_end = writer.seek(SeekFrom::Current(0))?;
+        let file_end = writer.stream_position()?;
 
         file.offset = file_start - archive_start;
         file.compressed_size = file_end - file_start;
@@ -432,3 +2652,82 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const NAMES: [&str; 3] = ["war3map.j", "units\\footman.mdx", "readme.txt"];
+
+    fn contents(name: &str) -> Vec<u8> {
+        name.bytes().cycle().take(name.len() * 300).collect()
+    }
+
+    #[test]
+    fn append_keeps_format_version() {
+        for format_version in [
+            FormatVersion::V1,
+            FormatVersion::V2,
+            FormatVersion::V3,
+            FormatVersion::V4,
+        ] {
+            let mut creator = Creator::default();
+            creator.set_format_version(format_version);
+            for name in NAMES {
+                creator.add_file(name, contents(name), FileOptions::default());
+            }
+            let mut buf = Vec::new();
+            creator.write(Cursor::new(&mut buf)).unwrap();
+
+            let mut creator = Creator::default();
+            creator.add_file("added.txt", contents("added.txt"), FileOptions::default());
+            let end = creator.append(Cursor::new(&mut buf)).unwrap();
+            buf.truncate(end as usize);
+
+            let mut archive = Archive::open(Cursor::new(&mut buf)).unwrap();
+            archive.remove_file("readme.txt").unwrap();
+
+            let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+            assert_eq!(
+                FormatVersion::of_header(archive.info().header().format_version()),
+                format_version
+            );
+            for name in ["war3map.j", "units\\footman.mdx", "added.txt"] {
+                assert_eq!(archive.read_file(name).unwrap(), contents(name));
+            }
+            assert!(archive.read_file("readme.txt").is_err());
+
+            let mut files = archive.files().unwrap();
+            files.sort();
+            assert_eq!(files, ["added.txt", "units\\footman.mdx", "war3map.j"]);
+        }
+    }
+
+    #[test]
+    fn appends_to_emptied_archive() {
+        let mut creator = Creator::default();
+        creator.set_format_version(FormatVersion::V4);
+        creator.add_file("readme.txt", contents("readme.txt"), FileOptions::default());
+        let mut buf = Vec::new();
+        creator.write(Cursor::new(&mut buf)).unwrap();
+
+        let mut archive = Archive::open(Cursor::new(&mut buf)).unwrap();
+        archive.remove_file("readme.txt").unwrap();
+        archive.remove_file("(listfile)").unwrap();
+        assert!(archive.blocks().all(|block| !block.exists()));
+
+        let mut creator = Creator::default();
+        creator.add_file("added.txt", contents("added.txt"), FileOptions::default());
+        creator.append(Cursor::new(&mut buf)).unwrap();
+
+        // the file data must not be overwritten by the header
+        let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+        assert_eq!(archive.info().header().format_version(), 3);
+        assert_eq!(
+            archive.read_file("added.txt").unwrap(),
+            contents("added.txt")
+        );
+    }
+}
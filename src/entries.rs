@@ -0,0 +1,65 @@
+use std::collections::HashSet;
+use std::io::{Read, Seek};
+
+use super::archive::{Archive, FileInfo};
+
+/// An iterator over the files of an archive whose names are known, yielding each name
+/// together with the file's metadata, as returned by [iter](struct.Archive.html#method.iter).
+///
+/// Names are taken lazily from the `(listfile)` and the names registered with
+/// [add_known_names](struct.Archive.html#method.add_known_names). Names of files which do not exist
+/// are skipped, and every file is yielded only once, even if it is listed under several names.
+pub struct Entries<'a, R: Read + Seek> {
+    archive: &'a Archive<R>,
+    names: Box<dyn Iterator<Item = &'a str> + Send + 'a>,
+    seen: HashSet<usize>,
+}
+
+impl<'a, R: Read + Seek> Entries<'a, R> {
+    pub(crate) fn new(
+        archive: &'a Archive<R>,
+        names: Box<dyn Iterator<Item = &'a str> + Send + 'a>,
+    ) -> Self {
+        Entries {
+            archive,
+            names,
+            seen: HashSet::new(),
+        }
+    }
+}
+
+impl<'a, R: Read + Seek> Iterator for Entries<'a, R> {
+    type Item = (&'a str, FileInfo);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for name in self.names.by_ref() {
+            let info = match self.archive.file_info(name) {
+                Some(info) if info.exists() => info,
+                _ => continue,
+            };
+
+            if self.seen.insert(info.block_index()) {
+                return Some((name, info));
+            }
+        }
+
+        None
+    }
+}
+
+impl<'a, R: Read + Seek> IntoIterator for &'a Archive<R> {
+    type Item = (&'a str, FileInfo);
+    type IntoIter = Entries<'a, R>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, R: Read + Seek> std::fmt::Debug for Entries<'a, R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Entries")
+            .field("seen", &self.seen.len())
+            .finish()
+    }
+}
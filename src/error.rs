@@ -1,3 +1,6 @@
+// `err-derive` generates its trait impls inside an anonymous const
+#![allow(non_local_definitions)]
+
 use std::io::Error as IoError;
 
 use err_derive::Error;
@@ -7,15 +10,71 @@ pub enum Error {
     #[error(display = "No header found")]
     NoHeader,
     #[error(display = "IO Error: {}", cause)]
-    IoError { cause: IoError },
+    IoError {
+        #[error(source, no_from)]
+        cause: IoError,
+    },
     #[error(display = "Unsupported MPQ version")]
     UnsupportedVersion,
     #[error(display = "Corrupted archive")]
     Corrupted,
+    #[error(display = "Checksum mismatch in sector {}", sector)]
+    ChecksumMismatch { sector: usize },
+    #[error(
+        display = "Contents of {} do not match the checksums in (attributes)",
+        name
+    )]
+    AttributesMismatch { name: String },
+    #[error(display = "MD5 checksum mismatch in the {}", table)]
+    TableChecksumMismatch { table: String },
     #[error(display = "File not found")]
     FileNotFound,
+    #[error(display = "Name of block {} is unknown", block_index)]
+    UnknownName { block_index: usize },
     #[error(display = "Compression type unsupported: {}", kind)]
     UnsupportedCompression { kind: String },
+    #[error(display = "Unsafe file path: {}", path)]
+    UnsafePath { path: String },
+    #[error(display = "Invalid pattern {}: {}", pattern, message)]
+    InvalidPattern { pattern: String, message: String },
+    #[error(display = "Invalid manifest on line {}", line)]
+    InvalidManifest { line: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Broad classification of [Error](enum.Error.html)s, allowing applications
+/// to handle categories of failures without matching every variant.
+pub enum ErrorKind {
+    /// The requested file does not exist in the archive.
+    NotFound,
+    /// The archive is malformed or damaged.
+    Corrupt,
+    /// The archive uses a feature which is not supported.
+    Unsupported,
+    /// An error occurred in the underlying reader or writer.
+    Io,
+    /// An argument supplied by the caller was invalid.
+    InvalidInput,
+}
+
+impl Error {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::FileNotFound => ErrorKind::NotFound,
+            Error::NoHeader
+            | Error::Corrupted
+            | Error::ChecksumMismatch { .. }
+            | Error::AttributesMismatch { .. }
+            | Error::TableChecksumMismatch { .. }
+            | Error::UnsafePath { .. } => ErrorKind::Corrupt,
+            Error::UnsupportedVersion
+            | Error::UnsupportedCompression { .. }
+            | Error::UnknownName { .. } => ErrorKind::Unsupported,
+            Error::IoError { .. } => ErrorKind::Io,
+            Error::InvalidPattern { .. } | Error::InvalidManifest { .. } => ErrorKind::InvalidInput,
+        }
+    }
 }
 
 impl From<IoError> for Error {
@@ -23,3 +82,19 @@ impl From<IoError> for Error {
         Error::IoError { cause: other }
     }
 }
+
+#[derive(Debug, Clone, Error)]
+#[error(
+    display = "Archive size of {} bytes exceeds the limit of {} bytes",
+    size,
+    limit
+)]
+/// Returned when a written archive exceeds the size limit of a [Creator](struct.Creator.html).
+pub struct SizeLimitExceeded {
+    /// The size limit.
+    pub limit: u64,
+    /// The size of the written archive.
+    pub size: u64,
+    /// The names and compressed sizes of all written files, largest first.
+    pub files: Vec<(String, u64)>,
+}
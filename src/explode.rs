@@ -0,0 +1,228 @@
+//! PKWare Data Compression Library decompression, also known as "explode".
+//!
+//! This is used both for files with the `MPQ_FILE_IMPLODE` flag, whose sectors are imploded
+//! without a compression type byte, and for sectors with the PKWare compression type.
+//!
+//! The implementation follows Mark Adler's `blast.c` from the zlib contributions.
+
+const MAX_BITS: usize = 13;
+
+const END_OF_STREAM: usize = 519;
+
+/// Bit lengths of the literal codes, in the compact run-length form used by `blast.c`.
+const LITERAL_LENGTHS: [u8; 98] = [
+    11, 124, 8, 7, 28, 7, 188, 13, 76, 4, 10, 8, 12, 10, 12, 10, 8, 23, 8, 9, 7, 6, 7, 8, 7, 6, 55,
+    8, 23, 24, 12, 11, 7, 9, 11, 12, 6, 7, 22, 5, 7, 24, 6, 11, 9, 6, 7, 22, 7, 11, 38, 7, 9, 8,
+    25, 11, 8, 11, 9, 12, 8, 12, 5, 38, 5, 38, 5, 11, 7, 5, 6, 21, 6, 10, 53, 8, 7, 24, 10, 27, 44,
+    253, 253, 253, 252, 252, 252, 13, 12, 45, 12, 45, 12, 61, 12, 45, 44, 173,
+];
+
+const LENGTH_LENGTHS: [u8; 6] = [2, 35, 36, 53, 38, 23];
+
+const DISTANCE_LENGTHS: [u8; 7] = [2, 20, 53, 230, 247, 151, 248];
+
+const LENGTH_BASE: [usize; 16] = [3, 2, 4, 5, 6, 7, 8, 9, 10, 12, 16, 24, 40, 72, 136, 264];
+
+const LENGTH_EXTRA_BITS: [u32; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8];
+
+/// A canonical Huffman code, stored as the number of codes of each length
+/// and the symbols ordered by their codes.
+struct Huffman {
+    count: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds the code from bit lengths given as `(repeat - 1) << 4 | length` bytes.
+    fn new(compact_lengths: &[u8]) -> Huffman {
+        let lengths: Vec<usize> = compact_lengths
+            .iter()
+            .flat_map(|&byte| {
+                let repeat = (byte >> 4) as usize + 1;
+                std::iter::repeat_n((byte & 0x0F) as usize, repeat)
+            })
+            .collect();
+
+        let mut count = [0u16; MAX_BITS + 1];
+        for &length in &lengths {
+            count[length] += 1;
+        }
+
+        let mut offsets = [0usize; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + count[length] as usize;
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length]] = symbol as u16;
+                offsets[length] += 1;
+            }
+        }
+
+        Huffman { count, symbols }
+    }
+}
+
+/// Reads the compressed stream least significant bit first.
+struct BitReader<'a> {
+    input: &'a [u8],
+    position: usize,
+    buffer: u32,
+    available: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(input: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            input,
+            position: 0,
+            buffer: 0,
+            available: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        while self.available < count {
+            let byte = *self.input.get(self.position)?;
+            self.position += 1;
+            self.buffer |= u32::from(byte) << self.available;
+            self.available += 8;
+        }
+
+        let value = self.buffer & ((1 << count) - 1);
+        self.buffer >>= count;
+        self.available -= count;
+
+        Some(value)
+    }
+
+    /// Decodes one symbol. The codes are stored bit-inverted in the stream.
+    fn decode(&mut self, huffman: &Huffman) -> Option<usize> {
+        let mut code = 0;
+        let mut first = 0;
+        let mut index = 0;
+
+        for length in 1..=MAX_BITS {
+            code |= (self.bits(1)? ^ 1) as usize;
+
+            let count = huffman.count[length] as usize;
+            if code < first + count {
+                return Some(huffman.symbols[index + code - first] as usize);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        None
+    }
+}
+
+/// Decompresses `input` into `output`, returning the decompressed size.
+///
+/// Returns `None` if the data is corrupted or does not fit into `output`.
+pub(crate) fn explode(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    let mut reader = BitReader::new(input);
+
+    let coded_literals = match reader.bits(8)? {
+        0 => false,
+        1 => true,
+        _ => return None,
+    };
+
+    let dictionary_bits = reader.bits(8)?;
+    if !(4..=6).contains(&dictionary_bits) {
+        return None;
+    }
+
+    let literal_code = Huffman::new(&LITERAL_LENGTHS);
+    let length_code = Huffman::new(&LENGTH_LENGTHS);
+    let distance_code = Huffman::new(&DISTANCE_LENGTHS);
+
+    let mut position = 0;
+
+    loop {
+        if reader.bits(1)? == 1 {
+            let symbol = reader.decode(&length_code)?;
+            let length = LENGTH_BASE[symbol] + reader.bits(LENGTH_EXTRA_BITS[symbol])? as usize;
+            if length == END_OF_STREAM {
+                break;
+            }
+
+            let low_bits = if length == 2 { 2 } else { dictionary_bits };
+            let distance =
+                (reader.decode(&distance_code)? << low_bits) + reader.bits(low_bits)? as usize + 1;
+
+            if distance > position || position + length > output.len() {
+                return None;
+            }
+
+            // the source and destination may overlap, so copy byte by byte
+            for i in position..position + length {
+                output[i] = output[i - distance];
+            }
+
+            position += length;
+        } else {
+            let literal = if coded_literals {
+                reader.decode(&literal_code)? as u8
+            } else {
+                reader.bits(8)? as u8
+            };
+
+            *output.get_mut(position)? = literal;
+            position += 1;
+        }
+    }
+
+    Some(position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The example stream from the comments of `blast.c`, with uncoded literals
+    /// and a 1024 byte dictionary.
+    const COMPRESSED: [u8; 8] = [0x00, 0x04, 0x82, 0x24, 0x25, 0x8F, 0x80, 0x7F];
+    const CONTENTS: &[u8] = b"AIAIAIAIAIAIA";
+
+    #[test]
+    fn explodes_known_stream() {
+        let mut output = vec![0; 64];
+        let size = explode(&COMPRESSED, &mut output).unwrap();
+
+        assert_eq!(&output[..size], CONTENTS);
+    }
+
+    #[test]
+    fn rejects_truncated_and_corrupted_streams() {
+        let mut output = vec![0; 64];
+
+        for length in 0..COMPRESSED.len() {
+            assert_eq!(explode(&COMPRESSED[..length], &mut output), None);
+        }
+
+        // the output is too small for the stream
+        assert_eq!(
+            explode(&COMPRESSED, &mut output[..CONTENTS.len() - 1]),
+            None
+        );
+
+        // invalid literal mode and dictionary size
+        assert_eq!(explode(&[0x02, 0x04, 0x82], &mut output), None);
+        assert_eq!(explode(&[0x00, 0x07, 0x82], &mut output), None);
+
+        // corrupted streams must not panic, though they may decode to garbage
+        for position in 2..COMPRESSED.len() {
+            for flip in [0x01, 0x10, 0xFF] {
+                let mut corrupted = COMPRESSED;
+                corrupted[position] ^= flip;
+                let _ = explode(&corrupted, &mut output);
+            }
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::path::{Path, PathBuf};
+
+/// Converts an archive file name into a path inside of `base`.
+///
+/// Both backslashes and forward slashes are treated as separators. Empty components,
+/// `.` and `..` components, and components containing a colon (e.g. drive letters or
+/// alternate data streams) are removed, so that the resulting path can never point
+/// outside of `base`.
+///
+/// Returns `None` if nothing remains of the name after sanitization.
+pub(crate) fn sanitize_path(base: &Path, name: &str) -> Option<PathBuf> {
+    let components: Vec<&str> = name
+        .split(['\\', '/'])
+        .filter(|component| !component.is_empty())
+        .filter(|component| *component != "." && *component != "..")
+        .filter(|component| !component.contains(':'))
+        .collect();
+
+    if components.is_empty() {
+        return None;
+    }
+
+    let mut path = base.to_path_buf();
+    for component in components {
+        path.push(component);
+    }
+
+    Some(path)
+}
+
+/// Whether the name contains anything that [sanitize_path] would have to remove
+/// in order to keep the path inside of its base directory: a leading separator,
+/// `.` or `..` components, or a colon.
+pub(crate) fn is_unsafe_name(name: &str) -> bool {
+    name.starts_with(['\\', '/'])
+        || name
+            .split(['\\', '/'])
+            .any(|component| component == "." || component == ".." || component.contains(':'))
+}
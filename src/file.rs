@@ -0,0 +1,230 @@
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Seek, SeekFrom};
+
+use super::archive::Archive;
+use super::attributes::DigestHasher;
+use super::error::Error;
+use super::table::{BlockEntry, SectorOffsets};
+
+/// Everything needed to read the sectors of a file one by one.
+#[derive(Debug)]
+pub(crate) struct FileSectors {
+    pub block_index: usize,
+    pub block_entry: BlockEntry,
+    pub encryption_key: Option<u32>,
+    pub offsets: SectorOffsets,
+    pub checksums: Option<Vec<u32>>,
+    pub sector_size: u64,
+}
+
+impl FileSectors {
+    /// The size of the decoded sector at `index`, accounting for the last sector being smaller.
+    pub fn uncompressed_size(&self, index: usize) -> u64 {
+        self.sector_size
+            .min(self.block_entry.uncompressed_size - index as u64 * self.sector_size)
+    }
+}
+
+/// A handle to a file in an archive, which decodes its sectors on demand as it is read,
+/// as returned by [open_file](struct.Archive.html#method.open_file).
+///
+/// Only one decoded sector is held in memory at a time. Seeking is cheap, since only the sectors
+/// covering the data that is read afterwards are decoded. If every sector of the file is read
+/// in order and the archive was opened with
+/// [OpenOptions::verify_attributes](struct.OpenOptions.html#structfield.verify_attributes),
+/// the contents are verified once the last sector is reached.
+///
+/// Errors are reported as [std::io::Error]s. Errors of the underlying reader are passed through,
+/// while all other [Error](enum.Error.html)s are wrapped with [std::io::ErrorKind::InvalidData].
+pub struct MpqFile<'a, R: Read + Seek> {
+    archive: &'a mut Archive<R>,
+    name: String,
+    sectors: FileSectors,
+    position: u64,
+    // the index and contents of the most recently decoded sector
+    current: Option<(usize, Vec<u8>)>,
+    digests: DigestHasher,
+    // the number of sectors which were decoded in order from the start, and are covered by `digests`
+    hashed_sectors: usize,
+}
+
+impl<'a, R: Read + Seek> MpqFile<'a, R> {
+    pub(crate) fn new(archive: &'a mut Archive<R>, name: &str, sectors: FileSectors) -> Self {
+        MpqFile {
+            archive,
+            name: name.to_string(),
+            sectors,
+            position: 0,
+            current: None,
+            digests: DigestHasher::default(),
+            hashed_sectors: 0,
+        }
+    }
+
+    /// The name the file was opened with.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The size of the file's contents once decoded.
+    pub fn size(&self) -> u64 {
+        self.sectors.block_entry.uncompressed_size
+    }
+
+    /// Decodes the sector at `index`, unless it is the current one.
+    fn load_sector(&mut self, index: usize) -> Result<&[u8], Error> {
+        if self.current.as_ref().map(|(current, _)| *current) != Some(index) {
+            let sector = self
+                .archive
+                .decode_file_sector(&self.name, &self.sectors, index)?;
+
+            if index == self.hashed_sectors {
+                self.digests.update(&sector);
+                self.hashed_sectors += 1;
+
+                if self.hashed_sectors == self.sectors.offsets.count() {
+                    self.archive.verify_attributes(
+                        &self.name,
+                        self.sectors.block_index,
+                        &self.digests.finish(),
+                    )?;
+                }
+            }
+
+            self.current = Some((index, sector));
+        }
+
+        Ok(self
+            .current
+            .as_ref()
+            .map(|(_, sector)| &sector[..])
+            .unwrap_or(&[]))
+    }
+}
+
+impl<'a, R: Read + Seek> Read for MpqFile<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() || self.position >= self.size() {
+            return Ok(0);
+        }
+
+        let sector_size = self.sectors.sector_size;
+        let index = (self.position / sector_size) as usize;
+        let offset = (self.position % sector_size) as usize;
+
+        let sector = self.load_sector(index).map_err(into_io_error)?;
+        let available = match sector.get(offset..) {
+            Some(available) if !available.is_empty() => available,
+            _ => return Err(into_io_error(Error::Corrupted)),
+        };
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+
+        self.position += count as u64;
+
+        Ok(count)
+    }
+}
+
+impl<'a, R: Read + Seek> Seek for MpqFile<'a, R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => (0, offset as i64),
+            SeekFrom::End(offset) => (self.size(), offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+
+        // seeking past the end is allowed, reads will then return no data
+        self.position = base.checked_add_signed(offset).ok_or_else(|| {
+            IoError::new(
+                IoErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        Ok(self.position)
+    }
+}
+
+impl<'a, R: Read + Seek> std::fmt::Debug for MpqFile<'a, R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MpqFile")
+            .field("name", &self.name)
+            .field("size", &self.size())
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+fn into_io_error(error: Error) -> IoError {
+    match error {
+        Error::IoError { cause } => cause,
+        error => IoError::new(IoErrorKind::InvalidData, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{Compression, Creator, FileOptions};
+
+    /// Contents spanning several sectors of the default size, with a partial last sector.
+    fn contents() -> Vec<u8> {
+        (0..0x10000 * 3 + 0x1234)
+            .map(|i: u32| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn seeks_and_reads_across_sectors() {
+        let mut creator = Creator::default();
+        let options = FileOptions {
+            compression: Compression::Deflate,
+            ..Default::default()
+        };
+        creator.add_file("large.bin", contents(), options);
+        let mut buf = Vec::new();
+        creator.write(Cursor::new(&mut buf)).unwrap();
+
+        let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+        let expected = archive.read_file("large.bin").unwrap();
+        assert_eq!(expected, contents());
+        let size = expected.len() as u64;
+
+        let mut file = archive.open_file("large.bin").unwrap();
+        assert_eq!(file.size(), size);
+
+        let mut read_at = |pos: SeekFrom, len: usize| {
+            let start = file.seek(pos).unwrap() as usize;
+            let mut data = vec![0; len];
+            file.read_exact(&mut data).unwrap();
+            (start, data)
+        };
+
+        // the start, across the first sector boundary, and within the last sector
+        for (pos, len) in [
+            (SeekFrom::Start(0), 100),
+            (SeekFrom::Start(0x10000 - 10), 20),
+            (SeekFrom::Current(0x10000), 0x10000 + 1),
+            (SeekFrom::End(-0x1000), 0x1000),
+            (SeekFrom::End(-0x2000), 0x1000),
+        ] {
+            let (start, data) = read_at(pos, len);
+            assert_eq!(data, &expected[start..start + len]);
+        }
+
+        // reading everything from the middle returns the rest of the file
+        file.seek(SeekFrom::Start(size / 2)).unwrap();
+        let mut rest = Vec::new();
+        file.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &expected[size as usize / 2..]);
+
+        // past the end, no data is returned
+        assert_eq!(file.seek(SeekFrom::End(10)).unwrap(), size + 10);
+        assert_eq!(file.read(&mut [0; 16]).unwrap(), 0);
+        assert_eq!(file.seek(SeekFrom::Current(-10)).unwrap(), size);
+        assert_eq!(file.read(&mut [0; 16]).unwrap(), 0);
+        assert!(file.seek(SeekFrom::Current(-(size as i64) - 1)).is_err());
+    }
+}
@@ -0,0 +1,33 @@
+use bitflags::bitflags;
+
+use super::consts::*;
+
+bitflags! {
+    /// The flags of a block table entry, describing how a file is stored.
+    ///
+    /// Returned by [`FileInfo::file_flags`](struct.FileInfo.html#method.file_flags).
+    /// Bits which are not known to this library are retained.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct FileFlags: u32 {
+        /// The file is compressed using PKWare DCL as a whole.
+        const IMPLODE = MPQ_FILE_IMPLODE;
+        /// The file's sectors are compressed.
+        const COMPRESSED = MPQ_FILE_COMPRESS;
+        /// The file is encrypted.
+        const ENCRYPTED = MPQ_FILE_ENCRYPTED;
+        /// The encryption key of the file is adjusted by its position and size.
+        const ADJUST_KEY = MPQ_FILE_ADJUST_KEY;
+        /// The file is a patch file.
+        const PATCH_FILE = MPQ_FILE_PATCH_FILE;
+        /// The file is stored as a single block instead of being split into sectors.
+        const SINGLE_UNIT = MPQ_FILE_SINGLE_UNIT;
+        /// The file is a deletion marker, which removes the file from the archives it patches.
+        const DELETE_MARKER = MPQ_FILE_DELETE_MARKER;
+        /// The file has checksums of its sectors.
+        const SECTOR_CRC = MPQ_FILE_SECTOR_CRC;
+        /// The block table entry is in use.
+        const EXISTS = MPQ_FILE_EXISTS;
+
+        const _ = !0;
+    }
+}
@@ -2,11 +2,12 @@ use std::io::Error as IoError;
 use std::io::{Read, Write};
 
 use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use md5::{Digest, Md5};
 
 use super::consts::*;
 use super::error::Error;
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone)]
 pub(crate) struct FileHeader {
     pub header_size: u32,
     pub archive_size: u32,
@@ -16,6 +17,27 @@ pub(crate) struct FileHeader {
     pub block_table_offset: u32,
     pub hash_table_entries: u32,
     pub block_table_entries: u32,
+
+    // v2
+    pub hi_block_table_offset: u64,
+    pub hash_table_offset_hi: u16,
+    pub block_table_offset_hi: u16,
+    // v3
+    pub archive_size_64: u64,
+    pub bet_table_offset: u64,
+    pub het_table_offset: u64,
+    // v4
+    pub hash_table_size_64: u64,
+    pub block_table_size_64: u64,
+    pub hi_block_table_size_64: u64,
+    pub het_table_size_64: u64,
+    pub bet_table_size_64: u64,
+    pub raw_chunk_size: u32,
+    pub md5_block_table: [u8; 16],
+    pub md5_hash_table: [u8; 16],
+    pub md5_hi_block_table: [u8; 16],
+    pub md5_bet_table: [u8; 16],
+    pub md5_het_table: [u8; 16],
 }
 
 impl FileHeader {
@@ -43,6 +65,141 @@ impl FileHeader {
             hash_table_entries,
             block_table_offset,
             block_table_entries,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a v2 header, which additionally stores the upper 16 bits of the table positions,
+    /// and the position of the hi-block table, if any. The archive size is truncated to 32 bits.
+    pub fn new_v2(
+        archive_size: u64,
+        block_size: u32,
+        hash_table: &TableLocation,
+        block_table: &TableLocation,
+        hi_block_table: Option<&TableLocation>,
+    ) -> FileHeader {
+        let mut header = FileHeader::new_v1(
+            archive_size as u32,
+            block_size,
+            hash_table.offset as u32,
+            block_table.offset as u32,
+            hash_table.entries as u32,
+            block_table.entries as u32,
+        );
+
+        header.format_version = 1;
+        header.header_size = HEADER_MPQ_SIZE_V2 as u32;
+
+        header.hash_table_offset_hi = (hash_table.offset >> 32) as u16;
+        header.block_table_offset_hi = (block_table.offset >> 32) as u16;
+
+        if let Some(hi_block_table) = hi_block_table {
+            header.hi_block_table_offset = hi_block_table.offset;
+        }
+
+        header
+    }
+
+    /// Creates a v3 header, which additionally stores the 64-bit archive size.
+    /// The positions of the HET and BET tables are left empty.
+    pub fn new_v3(
+        archive_size: u64,
+        block_size: u32,
+        hash_table: &TableLocation,
+        block_table: &TableLocation,
+        hi_block_table: Option<&TableLocation>,
+    ) -> FileHeader {
+        let mut header = FileHeader::new_v2(
+            archive_size,
+            block_size,
+            hash_table,
+            block_table,
+            hi_block_table,
+        );
+
+        header.format_version = 2;
+        header.header_size = HEADER_MPQ_SIZE_V3 as u32;
+        header.archive_size_64 = archive_size;
+
+        header
+    }
+
+    /// Creates a v4 header, which additionally stores 64-bit table positions and sizes,
+    /// as well as MD5 checksums of the tables. The checksum of the header itself is calculated on write.
+    pub fn new_v4(
+        archive_size: u64,
+        block_size: u32,
+        hash_table: &TableLocation,
+        block_table: &TableLocation,
+        hi_block_table: Option<&TableLocation>,
+    ) -> FileHeader {
+        let mut header = FileHeader::new_v3(
+            archive_size,
+            block_size,
+            hash_table,
+            block_table,
+            hi_block_table,
+        );
+
+        header.format_version = 3;
+        header.header_size = HEADER_MPQ_SIZE_V4 as u32;
+
+        header.hash_table_size_64 = hash_table.size;
+        header.block_table_size_64 = block_table.size;
+        header.md5_hash_table = hash_table.md5;
+        header.md5_block_table = block_table.md5;
+
+        if let Some(hi_block_table) = hi_block_table {
+            header.hi_block_table_size_64 = hi_block_table.size;
+            header.md5_hi_block_table = hi_block_table.md5;
+        }
+
+        header
+    }
+
+    /// Updates the archive size and the locations of the tables, keeping all other fields,
+    /// e.g. when the tables of an existing archive are rewritten.
+    /// Only the fields present in the header's format version are set.
+    pub fn set_tables(
+        &mut self,
+        archive_size: u64,
+        hash_table: &TableLocation,
+        block_table: &TableLocation,
+        hi_block_table: Option<&TableLocation>,
+        (het_table, bet_table): (Option<&TableLocation>, Option<&TableLocation>),
+    ) {
+        self.archive_size = archive_size as u32;
+        self.hash_table_offset = hash_table.offset as u32;
+        self.block_table_offset = block_table.offset as u32;
+        self.hash_table_entries = hash_table.entries as u32;
+        self.block_table_entries = block_table.entries as u32;
+
+        if self.format_version >= 1 {
+            self.hash_table_offset_hi = (hash_table.offset >> 32) as u16;
+            self.block_table_offset_hi = (block_table.offset >> 32) as u16;
+            self.hi_block_table_offset = hi_block_table.map_or(0, |table| table.offset);
+        }
+
+        if self.format_version >= 2 {
+            self.archive_size_64 = archive_size;
+            self.het_table_offset = het_table.map_or(0, |table| table.offset);
+            self.bet_table_offset = bet_table.map_or(0, |table| table.offset);
+        }
+
+        if self.format_version >= 3 {
+            let size = |table: Option<&TableLocation>| table.map_or(0, |table| table.size);
+            let md5 = |table: Option<&TableLocation>| table.map_or([0; 16], |table| table.md5);
+
+            self.hash_table_size_64 = hash_table.size;
+            self.block_table_size_64 = block_table.size;
+            self.hi_block_table_size_64 = size(hi_block_table);
+            self.het_table_size_64 = size(het_table);
+            self.bet_table_size_64 = size(bet_table);
+            self.md5_hash_table = hash_table.md5;
+            self.md5_block_table = block_table.md5;
+            self.md5_hi_block_table = md5(hi_block_table);
+            self.md5_het_table = md5(het_table);
+            self.md5_bet_table = md5(bet_table);
         }
     }
 
@@ -56,11 +213,11 @@ impl FileHeader {
         let hash_table_entries = reader.read_u32::<LE>()?;
         let block_table_entries = reader.read_u32::<LE>()?;
 
-        if format_version != 0 {
+        if format_version > 3 {
             return Err(Error::UnsupportedVersion);
         }
 
-        Ok(FileHeader {
+        let mut header = FileHeader {
             header_size,
             archive_size,
             format_version,
@@ -69,24 +226,206 @@ impl FileHeader {
             block_table_offset,
             hash_table_entries,
             block_table_entries,
-        })
+            ..Default::default()
+        };
+
+        if format_version >= 1 && u64::from(header_size) >= HEADER_MPQ_SIZE_V2 {
+            header.hi_block_table_offset = reader.read_u64::<LE>()?;
+            header.hash_table_offset_hi = reader.read_u16::<LE>()?;
+            header.block_table_offset_hi = reader.read_u16::<LE>()?;
+        }
+
+        // the additional fields of v3 headers are optional
+        if format_version >= 2 && u64::from(header_size) >= HEADER_MPQ_SIZE_V3 {
+            header.archive_size_64 = reader.read_u64::<LE>()?;
+            header.bet_table_offset = reader.read_u64::<LE>()?;
+            header.het_table_offset = reader.read_u64::<LE>()?;
+        }
+
+        if format_version >= 3 && u64::from(header_size) >= HEADER_MPQ_SIZE_V4 {
+            header.hash_table_size_64 = reader.read_u64::<LE>()?;
+            header.block_table_size_64 = reader.read_u64::<LE>()?;
+            header.hi_block_table_size_64 = reader.read_u64::<LE>()?;
+            header.het_table_size_64 = reader.read_u64::<LE>()?;
+            header.bet_table_size_64 = reader.read_u64::<LE>()?;
+            header.raw_chunk_size = reader.read_u32::<LE>()?;
+            reader.read_exact(&mut header.md5_block_table)?;
+            reader.read_exact(&mut header.md5_hash_table)?;
+            reader.read_exact(&mut header.md5_hi_block_table)?;
+            reader.read_exact(&mut header.md5_bet_table)?;
+            reader.read_exact(&mut header.md5_het_table)?;
+
+            let mut md5_header = [0u8; 16];
+            reader.read_exact(&mut md5_header)?;
+
+            // writing the header calculates the checksum of its contents again
+            let mut written = Vec::with_capacity(HEADER_MPQ_SIZE_V4 as usize);
+            header.write(&mut written)?;
+            if !is_zero_md5(&md5_header) && written[written.len() - 16..] != md5_header {
+                return Err(Error::TableChecksumMismatch {
+                    table: "header".to_string(),
+                });
+            }
+        }
+
+        Ok(header)
+    }
+
+    /// Offset of the hash table, including the upper bits stored by v2 headers.
+    pub fn hash_table_offset_64(&self) -> u64 {
+        u64::from(self.hash_table_offset) | (u64::from(self.hash_table_offset_hi) << 32)
+    }
+
+    /// Offset of the block table, including the upper bits stored by v2 headers.
+    pub fn block_table_offset_64(&self) -> u64 {
+        u64::from(self.block_table_offset) | (u64::from(self.block_table_offset_hi) << 32)
     }
 
     pub fn write<W: Write>(&self, mut writer: W) -> Result<(), IoError> {
-        writer.write_u32::<LE>(HEADER_MPQ_MAGIC)?;
-        writer.write_u32::<LE>(self.header_size)?;
-        writer.write_u32::<LE>(self.archive_size)?;
-        writer.write_u16::<LE>(self.format_version)?;
-        writer.write_u16::<LE>(self.block_size)?;
-        writer.write_u32::<LE>(self.hash_table_offset)?;
-        writer.write_u32::<LE>(self.block_table_offset)?;
-        writer.write_u32::<LE>(self.hash_table_entries)?;
-        writer.write_u32::<LE>(self.block_table_entries)?;
+        let mut buf = Vec::with_capacity(self.header_size as usize);
+
+        buf.write_u32::<LE>(HEADER_MPQ_MAGIC)?;
+        buf.write_u32::<LE>(self.header_size)?;
+        buf.write_u32::<LE>(self.archive_size)?;
+        buf.write_u16::<LE>(self.format_version)?;
+        buf.write_u16::<LE>(self.block_size)?;
+        buf.write_u32::<LE>(self.hash_table_offset)?;
+        buf.write_u32::<LE>(self.block_table_offset)?;
+        buf.write_u32::<LE>(self.hash_table_entries)?;
+        buf.write_u32::<LE>(self.block_table_entries)?;
+
+        if self.format_version >= 1 {
+            buf.write_u64::<LE>(self.hi_block_table_offset)?;
+            buf.write_u16::<LE>(self.hash_table_offset_hi)?;
+            buf.write_u16::<LE>(self.block_table_offset_hi)?;
+        }
+
+        if self.format_version >= 2 {
+            buf.write_u64::<LE>(self.archive_size_64)?;
+            buf.write_u64::<LE>(self.bet_table_offset)?;
+            buf.write_u64::<LE>(self.het_table_offset)?;
+        }
+
+        if self.format_version >= 3 {
+            buf.write_u64::<LE>(self.hash_table_size_64)?;
+            buf.write_u64::<LE>(self.block_table_size_64)?;
+            buf.write_u64::<LE>(self.hi_block_table_size_64)?;
+            buf.write_u64::<LE>(self.het_table_size_64)?;
+            buf.write_u64::<LE>(self.bet_table_size_64)?;
+            buf.write_u32::<LE>(self.raw_chunk_size)?;
+            buf.write_all(&self.md5_block_table)?;
+            buf.write_all(&self.md5_hash_table)?;
+            buf.write_all(&self.md5_hi_block_table)?;
+            buf.write_all(&self.md5_bet_table)?;
+            buf.write_all(&self.md5_het_table)?;
+
+            // the header's own checksum covers everything preceding it
+            let md5_header = Md5::digest(&buf);
+            buf.write_all(&md5_header)?;
+        }
+
+        writer.write_all(&buf)?;
 
         Ok(())
     }
 }
 
+#[derive(Debug, Clone)]
+/// A read-only view of the MPQ header of an archive, as returned by
+/// [ArchiveInfo::header](struct.ArchiveInfo.html#method.header).
+///
+/// All offsets are relative to the start of the archive, i.e. the position of the header.
+pub struct ArchiveHeader {
+    header: FileHeader,
+}
+
+impl ArchiveHeader {
+    pub(crate) fn new(header: &FileHeader) -> ArchiveHeader {
+        ArchiveHeader {
+            header: header.clone(),
+        }
+    }
+
+    pub(crate) fn file_header(&self) -> &FileHeader {
+        &self.header
+    }
+
+    /// The format version, where `0` is the original format and `3` is the latest one.
+    pub fn format_version(&self) -> u16 {
+        self.header.format_version
+    }
+
+    /// The size of the header in bytes.
+    pub fn header_size(&self) -> u32 {
+        self.header.header_size
+    }
+
+    /// The size of the archive as declared in the header,
+    /// using the 64-bit size of v3 and v4 headers if it is set.
+    pub fn archive_size(&self) -> u64 {
+        if self.header.archive_size_64 != 0 {
+            self.header.archive_size_64
+        } else {
+            u64::from(self.header.archive_size)
+        }
+    }
+
+    /// The size in bytes of the sectors that files in the archive are split into.
+    pub fn sector_size(&self) -> u64 {
+        512 * 2u64.pow(u32::from(self.header.block_size))
+    }
+
+    /// The offset of the hash table.
+    pub fn hash_table_offset(&self) -> u64 {
+        self.header.hash_table_offset_64()
+    }
+
+    /// The number of entries in the hash table.
+    pub fn hash_table_entries(&self) -> u32 {
+        self.header.hash_table_entries
+    }
+
+    /// The offset of the block table.
+    pub fn block_table_offset(&self) -> u64 {
+        self.header.block_table_offset_64()
+    }
+
+    /// The number of entries in the block table.
+    pub fn block_table_entries(&self) -> u32 {
+        self.header.block_table_entries
+    }
+
+    /// The offset of the hi-block table, which only v2 and later archives over 4 GiB have.
+    pub fn hi_block_table_offset(&self) -> Option<u64> {
+        Some(self.header.hi_block_table_offset).filter(|&offset| offset != 0)
+    }
+
+    /// The offset of the HET table, which only v3 and v4 archives may have.
+    pub fn het_table_offset(&self) -> Option<u64> {
+        Some(self.header.het_table_offset).filter(|&offset| offset != 0)
+    }
+
+    /// The offset of the BET table, which only v3 and v4 archives may have.
+    pub fn bet_table_offset(&self) -> Option<u64> {
+        Some(self.header.bet_table_offset).filter(|&offset| offset != 0)
+    }
+}
+
+/// Whether an MD5 checksum in a v4 header is unset, in which case it is not verified.
+pub(crate) fn is_zero_md5(md5: &[u8; 16]) -> bool {
+    md5.iter().all(|&byte| byte == 0)
+}
+
+/// Position, size and checksum of a table as it is stored in the archive.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TableLocation {
+    /// Offset of the table relative to the archive start.
+    pub offset: u64,
+    pub size: u64,
+    pub entries: u64,
+    pub md5: [u8; 16],
+}
+
 #[derive(Debug)]
 pub struct UserHeader {
     pub(crate) user_data_size: u32,
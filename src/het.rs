@@ -0,0 +1,736 @@
+//! The HET and BET tables of format version 3 and later, which can replace the hash and block tables.
+//!
+//! The HET table is a hash table of 64-bit Jenkins hashes of the file names, whose entries refer to
+//! entries of the BET table. The BET table describes the files in a bit-packed form, and holds the
+//! lower bits of their name hashes. Only the parts needed to locate and read files are used.
+//!
+//! When the tables of an existing archive are rewritten, its HET and BET tables are rebuilt
+//! from the name hashes of its files, so that they stay consistent with the hash and block tables.
+
+use std::io::{Read, Seek};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+
+use super::consts::*;
+use super::error::Error;
+use super::seeker::*;
+use super::table::BlockEntry;
+use super::util::*;
+
+#[derive(Debug)]
+pub(crate) struct HetTable {
+    name_hash_bits: u32,
+    // the upper 8 bits of the name hash of each entry, or `HET_ENTRY_FREE`
+    name_hashes: Vec<u8>,
+    bet_indexes: Vec<u8>,
+    index_size_total: u32,
+    index_size: u32,
+    // the remaining bits of the name hash of each BET entry
+    bet_name_hashes: Vec<u64>,
+}
+
+impl HetTable {
+    pub fn from_seeker<R>(
+        seeker: &mut Seeker<R>,
+        info: &TableInfo,
+        bet_name_hashes: Vec<u64>,
+    ) -> Result<HetTable, Error>
+    where
+        R: Read + Seek,
+    {
+        let data = read_ext_table(seeker, info, HET_TABLE_MAGIC, HASH_TABLE_KEY, "HET table")?;
+        let mut slice = &data[..];
+
+        let _table_size = slice.read_u32::<LE>()?;
+        let _entry_count = slice.read_u32::<LE>()?;
+        let total_count = slice.read_u32::<LE>()? as usize;
+        let name_hash_bits = slice.read_u32::<LE>()?;
+        let index_size_total = slice.read_u32::<LE>()?;
+        let _index_size_extra = slice.read_u32::<LE>()?;
+        let index_size = slice.read_u32::<LE>()?;
+        let index_table_size = slice.read_u32::<LE>()? as usize;
+
+        if !(8..=64).contains(&name_hash_bits) || index_size > 32 || index_size > index_size_total {
+            return Err(Error::Corrupted);
+        }
+
+        let name_hashes = take(&mut slice, total_count)?.to_vec();
+        let bet_indexes = take(&mut slice, index_table_size)?.to_vec();
+
+        Ok(HetTable {
+            name_hash_bits,
+            name_hashes,
+            bet_indexes,
+            index_size_total,
+            index_size,
+            bet_name_hashes,
+        })
+    }
+
+    /// Finds the index of the BET entry of a file.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        if self.name_hashes.is_empty() {
+            return None;
+        }
+
+        let name_hash = het_name_hash(name, self.name_hash_bits);
+        let upper_hash = (name_hash >> (self.name_hash_bits - 8)) as u8;
+        // an 8-bit name hash has no lower bits
+        let lower_hash = name_hash & u64::MAX.checked_shr(72 - self.name_hash_bits).unwrap_or(0);
+
+        let total_count = self.name_hashes.len();
+        let start_index = (name_hash % total_count as u64) as usize;
+        let mut index = start_index;
+
+        while self.name_hashes[index] != HET_ENTRY_FREE {
+            if self.name_hashes[index] == upper_hash {
+                let bet_index = read_bits(
+                    &self.bet_indexes,
+                    index as u64 * u64::from(self.index_size_total),
+                    self.index_size,
+                )? as usize;
+
+                if self.bet_name_hashes.get(bet_index) == Some(&lower_hash) {
+                    return Some(bet_index);
+                }
+            }
+
+            index = (index + 1) % total_count;
+            if index == start_index {
+                break;
+            }
+        }
+
+        None
+    }
+
+    /// Returns the name hash of every BET entry that is referenced by the HET table,
+    /// as used to locate it, by the index of the BET entry.
+    fn entry_hashes(&self) -> Vec<Option<u64>> {
+        let mut hashes = vec![None; self.bet_name_hashes.len()];
+
+        for (index, &upper_hash) in self.name_hashes.iter().enumerate() {
+            if upper_hash == HET_ENTRY_FREE {
+                continue;
+            }
+
+            let bet_index = match read_bits(
+                &self.bet_indexes,
+                index as u64 * u64::from(self.index_size_total),
+                self.index_size,
+            ) {
+                Some(bet_index) => bet_index as usize,
+                None => continue,
+            };
+
+            if let (Some(hash), Some(&lower_hash)) = (
+                hashes.get_mut(bet_index),
+                self.bet_name_hashes.get(bet_index),
+            ) {
+                *hash = Some((u64::from(upper_hash) << (self.name_hash_bits - 8)) | lower_hash);
+            }
+        }
+
+        hashes
+    }
+}
+
+/// The HET and BET tables of an existing archive, which are rebuilt when its tables are rewritten.
+#[derive(Debug)]
+pub(crate) struct ExtendedTables {
+    // the tables in their stored form, reused if no file was changed
+    het_table: Vec<u8>,
+    bet_table: Vec<u8>,
+    name_hash_bits: u32,
+    // the name hash of each block table entry which can be looked up by name
+    entry_hashes: Vec<Option<u64>>,
+}
+
+impl ExtendedTables {
+    pub fn from_seeker<R>(
+        seeker: &mut Seeker<R>,
+        het_info: &TableInfo,
+        bet_info: &TableInfo,
+    ) -> Result<ExtendedTables, Error>
+    where
+        R: Read + Seek,
+    {
+        let bet = BetTable::from_seeker(seeker, bet_info)?;
+        let het = HetTable::from_seeker(seeker, het_info, bet.name_hashes)?;
+
+        Ok(ExtendedTables {
+            het_table: read_stored_ext_table(seeker, het_info)?,
+            bet_table: read_stored_ext_table(seeker, bet_info)?,
+            name_hash_bits: het.name_hash_bits,
+            entry_hashes: het.entry_hashes(),
+        })
+    }
+
+    /// The HET and BET tables as they were stored in the archive.
+    pub fn stored(&self) -> (&[u8], &[u8]) {
+        (&self.het_table, &self.bet_table)
+    }
+
+    /// The name hash of an existing block table entry.
+    pub fn entry_hash(&self, block_index: usize) -> Option<u64> {
+        self.entry_hashes.get(block_index).copied().flatten()
+    }
+
+    /// The name hash of a file, with the same size as the existing name hashes.
+    pub fn name_hash(&self, name: &str) -> u64 {
+        het_name_hash(name, self.name_hash_bits)
+    }
+
+    /// Builds the stored form of new HET and BET tables, describing `block_entries`,
+    /// whose name hashes are taken from `entry_hashes`.
+    pub fn build(
+        &self,
+        block_entries: &[BlockEntry],
+        entry_hashes: &[Option<u64>],
+    ) -> (Vec<u8>, Vec<u8>) {
+        let het_table = build_het_table(entry_hashes, self.name_hash_bits);
+        let bet_table = build_bet_table(block_entries, entry_hashes, self.name_hash_bits);
+
+        (
+            encode_ext_table(HET_TABLE_MAGIC, HASH_TABLE_KEY, het_table),
+            encode_ext_table(BET_TABLE_MAGIC, BLOCK_TABLE_KEY, bet_table),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct BetTable {
+    pub entries: Vec<BlockEntry>,
+    pub name_hashes: Vec<u64>,
+}
+
+impl BetTable {
+    pub fn from_seeker<R>(seeker: &mut Seeker<R>, info: &TableInfo) -> Result<BetTable, Error>
+    where
+        R: Read + Seek,
+    {
+        let data = read_ext_table(seeker, info, BET_TABLE_MAGIC, BLOCK_TABLE_KEY, "BET table")?;
+        let mut slice = &data[..];
+
+        let _table_size = slice.read_u32::<LE>()?;
+        let entry_count = slice.read_u32::<LE>()? as usize;
+        let _unknown = slice.read_u32::<LE>()?;
+        let entry_size = u64::from(slice.read_u32::<LE>()?);
+
+        let mut bit_indexes = [0u64; 5];
+        for bit_index in &mut bit_indexes {
+            *bit_index = u64::from(slice.read_u32::<LE>()?);
+        }
+        let mut bit_counts = [0u32; 5];
+        for bit_count in &mut bit_counts {
+            *bit_count = slice.read_u32::<LE>()?;
+        }
+        let [file_pos_index, file_size_index, compressed_size_index, flag_index_index, _] =
+            bit_indexes;
+        let [file_pos_bits, file_size_bits, compressed_size_bits, flag_index_bits, _] = bit_counts;
+
+        let name_hash_size_total = u64::from(slice.read_u32::<LE>()?);
+        let _name_hash_size_extra = slice.read_u32::<LE>()?;
+        let name_hash_size = slice.read_u32::<LE>()?;
+        let name_hash_array_size = slice.read_u32::<LE>()? as usize;
+        let flag_count = slice.read_u32::<LE>()? as usize;
+
+        let mut flags = Vec::with_capacity(flag_count.min(slice.len() / 4));
+        for _ in 0..flag_count {
+            flags.push(slice.read_u32::<LE>()?);
+        }
+
+        // entries without any bits would not take up space in the table
+        if entry_size == 0 && entry_count > 0 {
+            return Err(Error::Corrupted);
+        }
+
+        let table_size = (entry_count as u64 * entry_size).div_ceil(8) as usize;
+        let table = take(&mut slice, table_size)?;
+        let name_hash_array = take(&mut slice, name_hash_array_size)?;
+
+        let mut entries = Vec::with_capacity(entry_count.min(table.len()));
+        let mut name_hashes = Vec::with_capacity(entry_count.min(table.len()));
+        for i in 0..entry_count as u64 {
+            let entry_start = i * entry_size;
+            let field = |index: u64, bits: u32| {
+                read_bits(table, entry_start + index, bits).ok_or(Error::Corrupted)
+            };
+
+            let flag_index = field(flag_index_index, flag_index_bits)? as usize;
+            let flags = if flags.is_empty() {
+                0
+            } else {
+                *flags.get(flag_index).ok_or(Error::Corrupted)?
+            };
+
+            entries.push(BlockEntry::new(
+                field(file_pos_index, file_pos_bits)?,
+                field(compressed_size_index, compressed_size_bits)?,
+                field(file_size_index, file_size_bits)?,
+                flags,
+            ));
+
+            name_hashes.push(
+                read_bits(name_hash_array, i * name_hash_size_total, name_hash_size)
+                    .ok_or(Error::Corrupted)?,
+            );
+        }
+
+        Ok(BetTable {
+            entries,
+            name_hashes,
+        })
+    }
+}
+
+/// Reads the stored form of a HET or BET table, including its common header.
+fn read_stored_ext_table<R>(seeker: &mut Seeker<R>, info: &TableInfo) -> Result<Vec<u8>, Error>
+where
+    R: Read + Seek,
+{
+    // without a size from a v4 header, the size of the uncompressed data is used
+    if info.size != 0 {
+        seeker.read(info.offset, info.size)
+    } else {
+        let header = seeker.read(info.offset, EXT_TABLE_HEADER_SIZE)?;
+        let data_size = u64::from((&header[8..]).read_u32::<LE>()?);
+
+        seeker.read(info.offset, EXT_TABLE_HEADER_SIZE + data_size)
+    }
+}
+
+/// Reads and decodes the data of a HET or BET table, following its common header.
+fn read_ext_table<R>(
+    seeker: &mut Seeker<R>,
+    info: &TableInfo,
+    magic: u32,
+    encryption_key: u32,
+    table: &str,
+) -> Result<Vec<u8>, Error>
+where
+    R: Read + Seek,
+{
+    let raw_table = read_stored_ext_table(seeker, info)?;
+    info.verify(&raw_table, table)?;
+
+    let (header, raw_data) = raw_table
+        .split_at_checked(EXT_TABLE_HEADER_SIZE as usize)
+        .ok_or(Error::Corrupted)?;
+    let mut header = header;
+    if header.read_u32::<LE>()? != magic {
+        return Err(Error::Corrupted);
+    }
+    let _version = header.read_u32::<LE>()?;
+    let data_size = u64::from(header.read_u32::<LE>()?);
+
+    let decoded_data = decode_mpq_block(raw_data, data_size, Some(encryption_key))?;
+
+    Ok(decoded_data.into_owned())
+}
+
+/// Prepends the common header to the data of a HET or BET table, and encrypts the data.
+/// The data is never compressed.
+fn encode_ext_table(magic: u32, encryption_key: u32, mut data: Vec<u8>) -> Vec<u8> {
+    encrypt_mpq_block(&mut data, encryption_key);
+
+    let mut table = Vec::with_capacity(EXT_TABLE_HEADER_SIZE as usize + data.len());
+    table.extend_from_slice(&magic.to_le_bytes());
+    table.extend_from_slice(&EXT_TABLE_VERSION.to_le_bytes());
+    table.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    table.extend_from_slice(&data);
+
+    table
+}
+
+/// Builds the data of a HET table, which refers to the BET entry of every name hash.
+fn build_het_table(entry_hashes: &[Option<u64>], name_hash_bits: u32) -> Vec<u8> {
+    let entry_count = entry_hashes.iter().flatten().count();
+    // leave some free entries, so that lookups of missing names end early
+    let total_count = (entry_count * 4 / 3).max(entry_count + 1);
+    let index_size = bit_count(entry_hashes.len() as u64).max(1);
+    let index_table_size = (total_count as u64 * u64::from(index_size)).div_ceil(8) as usize;
+
+    let mut name_hashes = vec![HET_ENTRY_FREE; total_count];
+    let mut bet_indexes = vec![0u8; index_table_size];
+    for (bet_index, hash) in entry_hashes.iter().enumerate() {
+        let hash = match hash {
+            Some(hash) => *hash,
+            None => continue,
+        };
+
+        let mut index = (hash % total_count as u64) as usize;
+        while name_hashes[index] != HET_ENTRY_FREE {
+            index = (index + 1) % total_count;
+        }
+
+        name_hashes[index] = (hash >> (name_hash_bits - 8)) as u8;
+        write_bits(
+            &mut bet_indexes,
+            index as u64 * u64::from(index_size),
+            index_size,
+            bet_index as u64,
+        );
+    }
+
+    // the header consists of 8 fields
+    let mut data = Vec::new();
+    let table_size = EXT_TABLE_HEADER_SIZE as usize + 8 * 4 + total_count + index_table_size;
+    for value in [
+        table_size as u32,
+        entry_count as u32,
+        total_count as u32,
+        name_hash_bits,
+        index_size,
+        0,
+        index_size,
+        index_table_size as u32,
+    ] {
+        data.write_u32::<LE>(value).unwrap();
+    }
+    data.extend_from_slice(&name_hashes);
+    data.extend_from_slice(&bet_indexes);
+
+    data
+}
+
+/// Builds the data of a BET table, which describes every block entry in bit-packed form,
+/// followed by the lower bits of the name hashes.
+fn build_bet_table(
+    block_entries: &[BlockEntry],
+    entry_hashes: &[Option<u64>],
+    name_hash_bits: u32,
+) -> Vec<u8> {
+    let mut flags: Vec<u32> = Vec::new();
+    for entry in block_entries {
+        if !flags.contains(&entry.flags) {
+            flags.push(entry.flags);
+        }
+    }
+
+    let max_field = |field: fn(&BlockEntry) -> u64| {
+        bit_count(block_entries.iter().map(field).max().unwrap_or(0))
+    };
+    let file_pos_bits = max_field(|entry| entry.file_pos);
+    let file_size_bits = max_field(|entry| entry.uncompressed_size);
+    let compressed_size_bits = max_field(|entry| entry.compressed_size);
+    let flag_index_bits = bit_count(flags.len().saturating_sub(1) as u64);
+    let entry_size = file_pos_bits + file_size_bits + compressed_size_bits + flag_index_bits;
+
+    let entry_count = block_entries.len() as u64;
+    let name_hash_size = name_hash_bits - 8;
+    let mut table = vec![0u8; (entry_count * u64::from(entry_size)).div_ceil(8) as usize];
+    let mut name_hash_array =
+        vec![0u8; (entry_count * u64::from(name_hash_size)).div_ceil(8) as usize];
+
+    for (i, entry) in block_entries.iter().enumerate() {
+        let flag_index = flags
+            .iter()
+            .position(|&flags| flags == entry.flags)
+            .unwrap_or(0);
+        let mut bit = i as u64 * u64::from(entry_size);
+        for (bits, value) in [
+            (file_pos_bits, entry.file_pos),
+            (file_size_bits, entry.uncompressed_size),
+            (compressed_size_bits, entry.compressed_size),
+            (flag_index_bits, flag_index as u64),
+        ] {
+            write_bits(&mut table, bit, bits, value);
+            bit += u64::from(bits);
+        }
+
+        let lower_hash = entry_hashes.get(i).copied().flatten().unwrap_or(0);
+        write_bits(
+            &mut name_hash_array,
+            i as u64 * u64::from(name_hash_size),
+            name_hash_size,
+            lower_hash,
+        );
+    }
+
+    // the header consists of 19 fields, followed by the flags
+    let header_size = 19 * 4 + flags.len() * 4;
+    let table_size =
+        EXT_TABLE_HEADER_SIZE as usize + header_size + table.len() + name_hash_array.len();
+
+    let mut data = Vec::new();
+    for value in [
+        table_size as u32,
+        entry_count as u32,
+        0x10,
+        entry_size,
+        0,
+        file_pos_bits,
+        file_pos_bits + file_size_bits,
+        file_pos_bits + file_size_bits + compressed_size_bits,
+        entry_size,
+        file_pos_bits,
+        file_size_bits,
+        compressed_size_bits,
+        flag_index_bits,
+        0,
+        name_hash_size,
+        0,
+        name_hash_size,
+        name_hash_array.len() as u32,
+        flags.len() as u32,
+    ] {
+        data.write_u32::<LE>(value).unwrap();
+    }
+    for &flags in &flags {
+        data.write_u32::<LE>(flags).unwrap();
+    }
+    data.extend_from_slice(&table);
+    data.extend_from_slice(&name_hash_array);
+
+    data
+}
+
+/// The name hash used to locate a file in a HET table, reduced to `name_hash_bits`
+/// with the uppermost bit set, so that it never matches a free entry.
+fn het_name_hash(name: &str, name_hash_bits: u32) -> u64 {
+    let and_mask = u64::MAX >> (64 - name_hash_bits);
+    let or_mask = 1u64 << (name_hash_bits - 1);
+
+    (hash_string_jenkins(name.as_bytes()) & and_mask) | or_mask
+}
+
+/// The number of bits needed to store `value`.
+fn bit_count(value: u64) -> u32 {
+    64 - value.leading_zeros()
+}
+
+fn take<'a>(slice: &mut &'a [u8], size: usize) -> Result<&'a [u8], Error> {
+    if slice.len() < size {
+        return Err(Error::Corrupted);
+    }
+
+    let (taken, rest) = slice.split_at(size);
+    *slice = rest;
+
+    Ok(taken)
+}
+
+/// Writes the lower `count` bits of `value`, starting at the specified bit, into a little-endian bit array.
+fn write_bits(data: &mut [u8], start: u64, count: u32, value: u64) {
+    for i in 0..u64::from(count) {
+        let bit = start + i;
+
+        if (value >> i) & 1 != 0 {
+            data[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+    }
+}
+
+/// Reads up to 64 bits, starting at the specified bit, from a little-endian bit array.
+fn read_bits(data: &[u8], start: u64, count: u32) -> Option<u64> {
+    if count > 64 {
+        return None;
+    }
+
+    let mut value = 0u64;
+    for i in 0..u64::from(count) {
+        let bit = start + i;
+        let byte = *data.get((bit / 8) as usize)?;
+
+        value |= u64::from((byte >> (bit % 8)) & 1) << i;
+    }
+
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use md5::{Digest, Md5};
+
+    use super::*;
+    use crate::header::{FileHeader, TableLocation};
+    use crate::{Archive, Compression, Creator, FileOptions, FormatVersion};
+
+    const NAMES: [&str; 4] = [
+        "war3map.j",
+        "units\\footman.mdx",
+        "Sound\\Music.mp3",
+        "readme.txt",
+    ];
+
+    fn contents(name: &str) -> Vec<u8> {
+        name.bytes().cycle().take(name.len() * 300).collect()
+    }
+
+    fn fixture(format_version: FormatVersion, remove_classic_tables: bool) -> Vec<u8> {
+        fixture_with_hash_bits(format_version, remove_classic_tables, 64)
+    }
+
+    /// Writes a v3 or v4 archive, and adds HET and BET tables with name hashes of the specified size
+    /// to it, optionally removing the hash and block tables from the header, so that files can only
+    /// be found through the HET table.
+    fn fixture_with_hash_bits(
+        format_version: FormatVersion,
+        remove_classic_tables: bool,
+        name_hash_bits: u32,
+    ) -> Vec<u8> {
+        let mut creator = Creator::default();
+        creator.set_format_version(format_version);
+        for name in NAMES {
+            let options = FileOptions {
+                compression: Compression::Deflate,
+                encrypt: true,
+                ..Default::default()
+            };
+            creator.add_file(name, contents(name), options);
+        }
+
+        let mut buf = Vec::new();
+        creator.write(Cursor::new(&mut buf)).unwrap();
+
+        let archive = Archive::open(Cursor::new(buf.clone())).unwrap();
+        let block_entries = archive.block_table().entries().to_vec();
+        let mut entry_hashes = vec![None; block_entries.len()];
+        for name in NAMES.iter().chain(&["(listfile)"]) {
+            let block_index = archive.file_info(*name).unwrap().block_index();
+            entry_hashes[block_index] = Some(het_name_hash(name, name_hash_bits));
+        }
+
+        let het_table = encode_ext_table(
+            HET_TABLE_MAGIC,
+            HASH_TABLE_KEY,
+            build_het_table(&entry_hashes, name_hash_bits),
+        );
+        let bet_table = encode_ext_table(
+            BET_TABLE_MAGIC,
+            BLOCK_TABLE_KEY,
+            build_bet_table(&block_entries, &entry_hashes, name_hash_bits),
+        );
+
+        let location = |offset: usize, data: &[u8]| TableLocation {
+            offset: offset as u64,
+            size: data.len() as u64,
+            entries: 0,
+            md5: Md5::digest(data).into(),
+        };
+        let het_location = location(buf.len(), &het_table);
+        buf.extend_from_slice(&het_table);
+        let bet_location = location(buf.len(), &bet_table);
+        buf.extend_from_slice(&bet_table);
+
+        let mut header = FileHeader::from_reader(&buf[4..]).unwrap();
+        let info = archive.info();
+        let table = |table: &TableInfo| TableLocation {
+            offset: table.offset(),
+            size: table.size(),
+            entries: table.entries(),
+            md5: table.md5().unwrap_or_default(),
+        };
+        let (mut hash_table, mut block_table) =
+            (table(info.hash_table()), table(info.block_table()));
+        if remove_classic_tables {
+            hash_table.entries = 0;
+            block_table.entries = 0;
+        }
+        header.set_tables(
+            buf.len() as u64,
+            &hash_table,
+            &block_table,
+            None,
+            (Some(&het_location), Some(&bet_location)),
+        );
+        let header_size = header.header_size as usize;
+        header.write(&mut buf[..header_size]).unwrap();
+
+        buf
+    }
+
+    #[test]
+    fn finds_files_through_het_table() {
+        for format_version in [FormatVersion::V3, FormatVersion::V4] {
+            let mut archive = Archive::open(Cursor::new(fixture(format_version, true))).unwrap();
+
+            assert_eq!(archive.hash_table_entries(), 0);
+            for name in NAMES {
+                assert_eq!(archive.read_file(name).unwrap(), contents(name));
+            }
+            assert!(archive.file_info("missing.txt").is_none());
+        }
+    }
+
+    #[test]
+    fn finds_files_through_het_table_with_8_bit_name_hashes() {
+        let buf = fixture_with_hash_bits(FormatVersion::V4, true, 8);
+        let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+
+        // without lower hash bits, names are only told apart by the upper 8 bits
+        for name in NAMES {
+            assert_eq!(archive.read_file(name).unwrap(), contents(name));
+        }
+    }
+
+    #[test]
+    fn rebuilds_extended_tables_of_exact_copies() {
+        let mut archive = Archive::open(Cursor::new(fixture(FormatVersion::V4, false))).unwrap();
+        let mut creator = Creator::copy_exact(&mut archive).unwrap();
+        creator.add_file("added.txt", contents("added.txt"), FileOptions::default());
+
+        let mut buf = Vec::new();
+        creator.write(Cursor::new(&mut buf)).unwrap();
+
+        let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+        let info = archive.info().clone();
+        let extended = ExtendedTables::from_seeker(
+            archive.seeker(),
+            info.het_table().unwrap(),
+            info.bet_table().unwrap(),
+        )
+        .unwrap();
+
+        for name in NAMES.iter().chain(&["added.txt"]) {
+            let block_index = archive.file_info(*name).unwrap().block_index();
+            assert_eq!(
+                extended.entry_hash(block_index),
+                Some(het_name_hash(name, 64))
+            );
+        }
+        assert_eq!(
+            archive.read_file("added.txt").unwrap(),
+            contents("added.txt")
+        );
+    }
+
+    #[test]
+    fn rebuilds_extended_tables_when_appending() {
+        let mut buf = fixture(FormatVersion::V4, false);
+
+        let mut creator = Creator::default();
+        creator.add_file("added.txt", contents("added.txt"), FileOptions::default());
+        creator.append(Cursor::new(&mut buf)).unwrap();
+
+        let mut archive = Archive::open(Cursor::new(&mut buf)).unwrap();
+        archive.remove_file("readme.txt").unwrap();
+
+        let mut archive = Archive::open(Cursor::new(buf)).unwrap();
+        let info = archive.info().clone();
+        let extended = ExtendedTables::from_seeker(
+            archive.seeker(),
+            info.het_table().unwrap(),
+            info.bet_table().unwrap(),
+        )
+        .unwrap();
+
+        for name in [
+            "war3map.j",
+            "units\\footman.mdx",
+            "Sound\\Music.mp3",
+            "added.txt",
+        ] {
+            let block_index = archive.file_info(name).unwrap().block_index();
+            assert_eq!(
+                extended.entry_hash(block_index),
+                Some(het_name_hash(name, 64))
+            );
+            assert_eq!(archive.read_file(name).unwrap(), contents(name));
+        }
+        assert!(archive.file_info("readme.txt").is_none());
+    }
+}
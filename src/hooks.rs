@@ -0,0 +1,45 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// Observes files as they are read from an [Archive](struct.Archive.html) or written by a
+/// [Creator](struct.Creator.html), e.g. for auditing, metrics or scanning archive contents.
+///
+/// Set with [Archive.set_hooks](struct.Archive.html#method.set_hooks) and
+/// [Creator.set_hooks](struct.Creator.html#method.set_hooks). All methods do nothing by default.
+/// Since hooks are shared, implementations that need to record state must use interior mutability.
+pub trait Hooks: Send + Sync {
+    /// Called after a file has been read and decoded completely by
+    /// [read_file](struct.Archive.html#method.read_file) and related methods.
+    fn on_file_read(&self, _name: &str, _contents: &[u8]) {}
+
+    /// Called after every sector that was decoded while reading a file,
+    /// including sectors passed to [read_file_chunked](struct.Archive.html#method.read_file_chunked).
+    fn on_sector_decoded(&self, _name: &str, _index: usize, _sector: &[u8]) {}
+
+    /// Called after a file has been written to an archive, with its offset
+    /// relative to the archive start and its size as stored in the archive.
+    fn on_file_written(&self, _name: &str, _offset: u64, _stored_size: u64) {}
+}
+
+#[derive(Clone, Default)]
+/// The hooks set on an archive or creator, if any.
+pub(crate) struct HookSlot(Option<Arc<dyn Hooks>>);
+
+impl HookSlot {
+    pub(crate) fn new(hooks: Option<Arc<dyn Hooks>>) -> HookSlot {
+        HookSlot(hooks)
+    }
+
+    pub(crate) fn get(&self) -> Option<&dyn Hooks> {
+        self.0.as_deref()
+    }
+}
+
+impl fmt::Debug for HookSlot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("Some(Hooks)"),
+            None => f.write_str("None"),
+        }
+    }
+}
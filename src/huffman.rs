@@ -0,0 +1,471 @@
+//! Adaptive Huffman coding as used by MPQ archives, mostly on top of ADPCM for `.wav` files.
+//!
+//! The first byte of the data selects a table of initial byte weights, from which the tree is built.
+//! Two symbols are added to every tree: one that ends the stream, and one that introduces a byte
+//! which is not in the tree yet, followed by that byte's 8 bits. Codes are walked from the root,
+//! reading the stream least significant bit first.
+//!
+//! New bytes are inserted into the tree as they appear. With weight table 0, the weight of every
+//! decoded byte is incremented as well, and the tree is rebalanced to keep it ordered by weight.
+//!
+//! Only weight table 0 and the tables which Storm uses for ADPCM-compressed audio (6 to 8)
+//! are supported. The others are used for text and binary data, which MPQ archives do not
+//! compress with Huffman coding in practice.
+
+/// The symbol which ends the stream.
+const END_OF_STREAM: u16 = 0x100;
+/// The symbol which is followed by a byte that is not in the tree yet.
+const NEW_BYTE: u16 = 0x101;
+
+/// Two leaves for each of the 258 symbols, and the nodes joining them.
+const MAX_ITEMS: usize = 0x203;
+
+/// The sentinel of the list of items, whose `next` is the root and whose `prev` is the lightest item.
+const HEAD: usize = 0;
+
+/// Initial weights of the bytes in ADPCM-compressed audio with 3 bits per sample.
+const WAVE_WEIGHTS_3_BITS: [(usize, &[u8]); 3] = [
+    (0x00, &[0xC3, 0xCB, 0xF5, 0x41, 0xFF, 0x7B, 0xF7, 0x21]),
+    (0x40, &[0xBF, 0xCC, 0xF2, 0x40, 0xFD, 0x7C, 0xF7, 0x22]),
+    (0x80, &[0x7A, 0x46]),
+];
+
+/// Initial weights of the bytes in ADPCM-compressed audio with 4 bits per sample.
+const WAVE_WEIGHTS_4_BITS: [(usize, &[u8]); 3] = [
+    (
+        0x00,
+        &[
+            0xC3, 0xD9, 0xEF, 0x3D, 0xF9, 0x7C, 0xE9, 0x1E, 0xFD, 0xAB, 0xF1, 0x2C, 0xFC, 0x5B,
+            0xFE, 0x17,
+        ],
+    ),
+    (
+        0x40,
+        &[
+            0xBD, 0xD9, 0xEC, 0x3D, 0xF5, 0x7D, 0xE8, 0x1D, 0xFB, 0xAE, 0xF0, 0x2C, 0xFB, 0x5C,
+            0xFF, 0x18,
+        ],
+    ),
+    (0x80, &[0x70, 0x6C]),
+];
+
+/// Initial weights of the bytes in ADPCM-compressed audio with 5 bits per sample.
+const WAVE_WEIGHTS_5_BITS: [(usize, &[u8]); 3] = [
+    (
+        0x00,
+        &[
+            0xBA, 0xC5, 0xDA, 0x33, 0xE3, 0x6D, 0xD8, 0x18, 0xE5, 0x94, 0xDA, 0x23, 0xDF, 0x4A,
+            0xD1, 0x10, 0xEE, 0xAF, 0xE4, 0x2C, 0xEA, 0x5A, 0xDE, 0x15, 0xF4, 0x87, 0xE9, 0x21,
+            0xF6, 0x43, 0xFC, 0x12,
+        ],
+    ),
+    (
+        0x40,
+        &[
+            0xB0, 0xC7, 0xD8, 0x33, 0xE3, 0x6B, 0xD6, 0x18, 0xE7, 0x95, 0xD8, 0x23, 0xDB, 0x49,
+            0xD0, 0x11, 0xE9, 0xB2, 0xE2, 0x2B, 0xE8, 0x5C, 0xDD, 0x15, 0xF1, 0x87, 0xE7, 0x20,
+            0xF7, 0x44, 0xFF, 0x13,
+        ],
+    ),
+    (0x80, &[0x5F, 0x9E]),
+];
+
+/// Returns the initial weights of all bytes for the weight table selected by the stream,
+/// or `None` if the table is not supported.
+fn initial_weights(table: u8) -> Option<[u8; 256]> {
+    let ranges: &[(usize, &[u8])] = match table {
+        0 => return Some([1; 256]),
+        6 => &WAVE_WEIGHTS_3_BITS,
+        7 => &WAVE_WEIGHTS_4_BITS,
+        8 => &WAVE_WEIGHTS_5_BITS,
+        _ => return None,
+    };
+
+    let mut weights = [0; 256];
+    for (start, range) in ranges {
+        weights[*start..*start + range.len()].copy_from_slice(range);
+    }
+
+    Some(weights)
+}
+
+/// An item of the tree, which is also part of a list of all items ordered by descending weight.
+///
+/// Nodes only link to their lower-weight child, the other child is the item preceding it in the list.
+#[derive(Debug, Clone, Copy)]
+struct Item {
+    symbol: u16,
+    weight: u32,
+    parent: Option<usize>,
+    child: Option<usize>,
+    prev: usize,
+    next: usize,
+}
+
+struct Tree {
+    items: Vec<Item>,
+    leaves: [Option<usize>; 0x102],
+}
+
+impl Tree {
+    fn new(weights: &[u8; 256]) -> Option<Tree> {
+        let head = Item {
+            symbol: 0,
+            weight: 0,
+            parent: None,
+            child: None,
+            prev: HEAD,
+            next: HEAD,
+        };
+        let mut tree = Tree {
+            items: vec![head],
+            leaves: [None; 0x102],
+        };
+
+        for (byte, &weight) in weights.iter().enumerate() {
+            if weight != 0 {
+                let item = tree.insert_by_weight(byte as u16, u32::from(weight))?;
+                tree.leaves[byte] = Some(item);
+            }
+        }
+
+        for &symbol in &[END_OF_STREAM, NEW_BYTE] {
+            let item = tree.push_last(symbol, 1)?;
+            tree.leaves[symbol as usize] = Some(item);
+        }
+
+        // join the two lightest items without a parent until only the root is left
+        let mut child = tree.items[HEAD].prev;
+        loop {
+            let sibling = tree.items[child].prev;
+            if sibling == HEAD {
+                break;
+            }
+
+            let weight = tree.items[child].weight + tree.items[sibling].weight;
+            let parent = tree.insert_by_weight(0, weight)?;
+            tree.items[parent].child = Some(child);
+            tree.items[child].parent = Some(parent);
+            tree.items[sibling].parent = Some(parent);
+
+            child = tree.items[sibling].prev;
+        }
+
+        Some(tree)
+    }
+
+    fn allocate(&mut self, symbol: u16, weight: u32) -> Option<usize> {
+        if self.items.len() > MAX_ITEMS {
+            return None;
+        }
+
+        self.items.push(Item {
+            symbol,
+            weight,
+            parent: None,
+            child: None,
+            prev: HEAD,
+            next: HEAD,
+        });
+
+        Some(self.items.len() - 1)
+    }
+
+    /// Adds an item after all items that weigh at least as much.
+    fn insert_by_weight(&mut self, symbol: u16, weight: u32) -> Option<usize> {
+        let item = self.allocate(symbol, weight)?;
+        let position = self.heavier_or_equal(self.items[HEAD].prev, weight);
+        self.link_after(item, position);
+
+        Some(item)
+    }
+
+    /// Adds an item at the end of the list.
+    fn push_last(&mut self, symbol: u16, weight: u32) -> Option<usize> {
+        let item = self.allocate(symbol, weight)?;
+        self.link_after(item, self.items[HEAD].prev);
+
+        Some(item)
+    }
+
+    /// Searches backwards from `item` for an item weighing at least `weight`,
+    /// returning `HEAD` if there is none.
+    fn heavier_or_equal(&self, mut item: usize, weight: u32) -> usize {
+        while item != HEAD && self.items[item].weight < weight {
+            item = self.items[item].prev;
+        }
+
+        item
+    }
+
+    fn link_after(&mut self, item: usize, position: usize) {
+        let next = self.items[position].next;
+
+        self.items[item].prev = position;
+        self.items[item].next = next;
+        self.items[next].prev = item;
+        self.items[position].next = item;
+    }
+
+    fn unlink(&mut self, item: usize) {
+        let Item { prev, next, .. } = self.items[item];
+
+        self.items[prev].next = next;
+        self.items[next].prev = prev;
+    }
+
+    /// Decodes one symbol, returning `None` if the stream ends before it.
+    fn decode(&self, reader: &mut BitReader) -> Option<u16> {
+        let mut item = self.items[HEAD].next;
+
+        while let Some(child) = self.items[item].child {
+            item = if reader.bits(1)? != 0 {
+                self.items[child].prev
+            } else {
+                child
+            };
+        }
+
+        Some(self.items[item].symbol)
+    }
+
+    /// Splits the lightest leaf into itself and a new leaf for `byte`.
+    fn insert_byte(&mut self, byte: u8) -> Option<()> {
+        let lightest = self.items[HEAD].prev;
+        let Item { symbol, weight, .. } = self.items[lightest];
+
+        let copy = self.push_last(symbol, weight)?;
+        self.items[copy].parent = Some(lightest);
+        self.leaves[symbol as usize] = Some(copy);
+
+        let new = self.push_last(u16::from(byte), 0)?;
+        self.items[new].parent = Some(lightest);
+        self.items[lightest].child = Some(new);
+        self.leaves[byte as usize] = Some(new);
+
+        self.increment(new);
+
+        Some(())
+    }
+
+    fn increment_byte(&mut self, byte: u8) {
+        if let Some(leaf) = self.leaves[byte as usize] {
+            self.increment(leaf);
+        }
+    }
+
+    /// Increments the weight of `item` and all of its ancestors, swapping each of them
+    /// with the first item of lower weight preceding it, to keep the list ordered by weight.
+    fn increment(&mut self, item: usize) {
+        let mut current = Some(item);
+
+        while let Some(item) = current {
+            self.items[item].weight += 1;
+
+            let heavier = self.heavier_or_equal(self.items[item].prev, self.items[item].weight);
+            let other = self.items[heavier].next;
+
+            if other != item {
+                self.unlink(other);
+                self.link_after(other, item);
+                self.unlink(item);
+                self.link_after(item, heavier);
+
+                let item_parent = self.items[item].parent;
+                let other_parent = self.items[other].parent;
+                let other_parent_child = other_parent.and_then(|parent| self.items[parent].child);
+
+                if let Some(parent) = item_parent {
+                    if self.items[parent].child == Some(item) {
+                        self.items[parent].child = Some(other);
+                    }
+                }
+                if let Some(parent) = other_parent {
+                    if other_parent_child == Some(other) {
+                        self.items[parent].child = Some(item);
+                    }
+                }
+
+                self.items[item].parent = other_parent;
+                self.items[other].parent = item_parent;
+            }
+
+            current = self.items[item].parent;
+        }
+    }
+}
+
+/// Reads the compressed stream least significant bit first.
+struct BitReader<'a> {
+    input: &'a [u8],
+    position: usize,
+    buffer: u32,
+    available: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(input: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            input,
+            position: 0,
+            buffer: 0,
+            available: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        while self.available < count {
+            let byte = *self.input.get(self.position)?;
+            self.position += 1;
+            self.buffer |= u32::from(byte) << self.available;
+            self.available += 8;
+        }
+
+        let value = self.buffer & ((1 << count) - 1);
+        self.buffer >>= count;
+        self.available -= count;
+
+        Some(value)
+    }
+}
+
+/// Decompresses `input` into `output`, returning the decompressed size.
+/// Decoding stops early if `output` is full.
+///
+/// Returns `None` if the data is corrupted or uses an unsupported weight table.
+pub(crate) fn decompress(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    let mut reader = BitReader::new(input);
+
+    let table = reader.bits(8)? as u8;
+    let mut tree = Tree::new(&initial_weights(table)?)?;
+    let adaptive = table == 0;
+
+    let mut position = 0;
+    while position < output.len() {
+        let byte = match tree.decode(&mut reader)? {
+            END_OF_STREAM => break,
+            NEW_BYTE => {
+                let byte = reader.bits(8)? as u8;
+                tree.insert_byte(byte)?;
+
+                // adaptive trees count the byte below, like every other one
+                if !adaptive {
+                    tree.increment_byte(byte);
+                }
+
+                byte
+            }
+            symbol => symbol as u8,
+        };
+
+        output[position] = byte;
+        position += 1;
+
+        if adaptive {
+            tree.increment_byte(byte);
+        }
+    }
+
+    Some(position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adpcm;
+    use crate::consts::{COMPRESSION_HUFFMAN, COMPRESSION_IMA_ADPCM_MONO_MONO};
+    use crate::util::decode_mpq_block;
+
+    /// Encodes `input` with the same tree updates as `decompress`.
+    fn compress(input: &[u8], table: u8) -> Vec<u8> {
+        let mut tree = Tree::new(&initial_weights(table).unwrap()).unwrap();
+        let mut bits = Vec::new();
+
+        fn push_symbol(tree: &Tree, symbol: u16, bits: &mut Vec<bool>) {
+            let mut code = Vec::new();
+            let mut item = tree.leaves[symbol as usize].unwrap();
+            while let Some(parent) = tree.items[item].parent {
+                code.push(tree.items[parent].child != Some(item));
+                item = parent;
+            }
+            bits.extend(code.iter().rev());
+        }
+
+        for &byte in input {
+            if tree.leaves[byte as usize].is_some() {
+                push_symbol(&tree, u16::from(byte), &mut bits);
+            } else {
+                push_symbol(&tree, NEW_BYTE, &mut bits);
+                bits.extend((0..8).map(|bit| byte & (1 << bit) != 0));
+                tree.insert_byte(byte).unwrap();
+                if table != 0 {
+                    tree.increment_byte(byte);
+                }
+            }
+
+            if table == 0 {
+                tree.increment_byte(byte);
+            }
+        }
+        push_symbol(&tree, END_OF_STREAM, &mut bits);
+
+        let mut output = vec![table];
+        for chunk in bits.chunks(8) {
+            let byte = chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (bit, &set)| byte | (u8::from(set) << bit));
+            output.push(byte);
+        }
+
+        output
+    }
+
+    #[test]
+    fn decompresses_adpcm_compressed_audio() {
+        let samples: Vec<u8> = (0..4000i32)
+            .flat_map(|i| (((i * 37) % 2000 - 1000) as i16 * 12).to_le_bytes())
+            .collect();
+
+        let mut compressed = vec![0; samples.len()];
+        let size = adpcm::compress(&samples, &mut compressed, 1, 4).unwrap();
+        compressed.truncate(size);
+
+        let mut expected = vec![0; samples.len()];
+        let size = adpcm::decompress(&compressed, &mut expected, 1).unwrap();
+        expected.truncate(size);
+
+        let mut sector = vec![COMPRESSION_IMA_ADPCM_MONO_MONO | COMPRESSION_HUFFMAN];
+        sector.extend(compress(&compressed, 7));
+
+        let decoded = decode_mpq_block(&sector, samples.len() as u64, None).unwrap();
+        assert_eq!(&decoded[..], &expected[..]);
+    }
+
+    #[test]
+    fn round_trips_with_every_supported_table() {
+        let input: Vec<u8> = b"Huffman coding of a (listfile)\r\nwar3map.j\r\n"
+            .iter()
+            .copied()
+            .chain(0..=255)
+            .collect();
+
+        for &table in &[0, 6, 7, 8] {
+            let compressed = compress(&input, table);
+
+            let mut output = vec![0; input.len()];
+            assert_eq!(decompress(&compressed, &mut output), Some(input.len()));
+            assert_eq!(output, input);
+
+            // a truncated stream ends before the end of stream symbol
+            let truncated = &compressed[..compressed.len() / 2];
+            assert_eq!(decompress(truncated, &mut output), None);
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_tables() {
+        assert_eq!(decompress(&[1, 0xFF, 0xFF], &mut [0; 16]), None);
+        assert_eq!(decompress(&[], &mut [0; 16]), None);
+    }
+}
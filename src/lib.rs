@@ -1,27 +1,43 @@
 //! A library for reading and writing Blizzard's proprietary MoPaQ archive format.
 //!
-//! Currently, `ceres-mpq` only supports reading and writing Version 1 MoPaQ
-//! archives, as this is the only version of the format still actively encountered
-//! in the wild, used by Warcraft III custom maps.
+//! `ceres-mpq` is primarily concerned with Version 1 MoPaQ archives, as this is the
+//! version of the format most actively encountered in the wild, used by Warcraft III custom maps.
 //!
-//! For this reason, no effort was made to support features found in newer
-//! versions of the format, though this may change in the future if there is
-//! a need for this.
+//! Archives of format versions 2 to 4 can be read as well. The HET and BET tables of versions 3 and 4
+//! are only used to locate files in archives which have no hash table. The MD5 checksums of the
+//! header and tables of version 4 archives are verified when opening them.
 //!
-//! `ceres-mpq` provides no support to edit existing archives yet, thought it may in the future.
+//! Existing archives can be edited in place with [insert_file](struct.Archive.html#method.insert_file),
+//! [remove_file](struct.Archive.html#method.remove_file) and [Creator::append](struct.Creator.html#method.append),
+//! which write new file data and tables after the existing data, leaving replaced and removed files behind
+//! as unused space. [compact](struct.Archive.html#method.compact) rewrites the archive to reclaim that space.
+//! Added files must fit into the archive's existing hash table.
+//!
+//! Since the archive is overwritten in place, none of these edits are crash-safe: if writing fails midway,
+//! the archive is left damaged. The `(attributes)` are not updated by in-place edits, and are carried over
+//! as they are by `compact`, so their checksums no longer match added or replaced files, and reading those with
+//! [OpenOptions::verify_attributes](struct.OpenOptions.html#structfield.verify_attributes) fails.
 //!
 //! # Supported features
 //!
 //! Not the whole range of MPQ features is supported yet for reading archives. Notably:
 //!
-//! * IMA ADPCM compression is unsupported. This is usually present on `.wav` files.
-//! * Huffman coding compression is unsupported. This is usually present on `.wav` files.
-//! * PKWare DCL compression is unsupported. However, I haven't seen any WC3 maps that use it.
-//! * Single-unit files are unsupported.
-//! * Checksums and file attributes are not checked or read.
+//! * Huffman coding is only supported with the weight tables used for ADPCM-compressed `.wav` files,
+//!   and the uniform weight table 0. Other data is not compressed with Huffman coding in practice.
+//! * Only the weak digital signature can be verified, the strong signature appended to some archives is ignored.
 //!
 //! Additionally, for writing archives:
-//! * You cannot choose which compression type to use for added files in [Creator](struct.Creator.html). DEFLATE is used by default.
+//! * Huffman coding, PKWare DCL and LZMA cannot be used for added files, see [Compression](enum.Compression.html) for the supported methods.
+//!
+//! # Cargo features
+//!
+//! * `parallel`: Uses [rayon](https://docs.rs/rayon) to spread bulk operations, such as [hash_names](fn.hash_names.html)
+//!   and compressing the sectors of files when [writing](struct.Creator.html#method.write) archives, over multiple threads.
+//! * `bytes`: Adds support for [bytes](https://docs.rs/bytes)' `Bytes` as contents of added files, and [read_file_bytes](struct.Archive.html#method.read_file_bytes).
+//! * `lua`: Exposes archives to Lua 5.4 scripts through [mlua](https://docs.rs/mlua), see [lua_module](fn.lua_module.html).
+//!   This links against a system installation of Lua 5.4, which is located through pkg-config.
+//! * `lua-vendored`: Same as `lua`, but builds Lua 5.4 from source, so that no system installation is needed.
+//!   Not needed when building a loadable Lua module with mlua's `module` feature.
 //!
 //! # Protected MPQs
 //!
@@ -38,6 +54,7 @@
 //! # Example
 //!
 //! ```
+//! # use ceres_mpq::Compression;
 //! # use ceres_mpq::Creator;
 //! # use ceres_mpq::FileOptions;
 //! # use ceres_mpq::Archive;
@@ -52,8 +69,9 @@
 //! creator.add_file("hello.txt", "hello world!",
 //!     FileOptions {
 //!         encrypt: false,
-//!         compress: true,
-//!         adjust_key: false
+//!         compression: Compression::Deflate,
+//!         adjust_key: false,
+//!         ..Default::default()
 //!     }
 //! );
 //! creator.write(&mut cursor)?;
@@ -71,17 +89,87 @@
 
 #![allow(dead_code)]
 
-pub(crate) mod consts;
+pub(crate) mod adpcm;
+pub(crate) mod attributes;
+pub(crate) mod cache;
+pub mod consts;
+pub(crate) mod contents;
+pub(crate) mod entries;
+pub(crate) mod explode;
+pub(crate) mod extract;
+pub(crate) mod file;
+pub(crate) mod flags;
 pub(crate) mod header;
+pub(crate) mod het;
+pub(crate) mod hooks;
+pub(crate) mod huffman;
+pub(crate) mod listfile;
+#[cfg(feature = "lua")]
+pub(crate) mod lua;
+pub(crate) mod lzma;
+pub(crate) mod manifest;
+pub(crate) mod map;
+pub(crate) mod matching;
+pub(crate) mod names;
 pub(crate) mod seeker;
+pub(crate) mod signature;
+pub(crate) mod sparse;
 pub(crate) mod table;
+pub(crate) mod tree;
 pub(crate) mod util;
+pub(crate) mod validation;
+pub(crate) mod verify;
 
 pub(crate) mod archive;
 pub(crate) mod creator;
 pub(crate) mod error;
 
 pub use archive::Archive;
+pub use archive::DynArchive;
+pub use archive::ExtractOptions;
+pub use archive::ExtractedFile;
+pub use archive::FileInfo;
+pub use archive::OpenOptions;
+pub use archive::PathPolicy;
+pub use archive::RawSectors;
+pub use archive::ReadOptions;
+pub use archive::ReadSeek;
+pub use archive::SlashPolicy;
+pub use archive::UnresolvedFile;
+pub use archive::UnresolvedReason;
+pub use attributes::Attributes;
+pub use cache::CompressionCache;
+pub use contents::FileContents;
+pub use creator::Compression;
 pub use creator::Creator;
 pub use creator::FileOptions;
+pub use creator::FileWriter;
+pub use creator::FormatVersion;
+pub use creator::StagedFile;
+pub use entries::Entries;
 pub use error::Error;
+pub use error::ErrorKind;
+pub use error::SizeLimitExceeded;
+pub use file::MpqFile;
+pub use flags::FileFlags;
+pub use header::ArchiveHeader;
+pub use hooks::Hooks;
+pub use listfile::Listfile;
+#[cfg(feature = "lua")]
+pub use lua::lua_module;
+pub use manifest::Divergence;
+pub use manifest::Manifest;
+pub use manifest::ManifestEntry;
+pub use map::MapIssue;
+pub use names::hash_names;
+pub use names::AsFileName;
+pub use names::FileName;
+pub use names::NameHash;
+pub use names::NameMatcher;
+pub use seeker::ArchiveInfo;
+pub use seeker::TableInfo;
+pub use tree::Directory;
+pub use tree::TreeFile;
+pub use util::decode_sector_into;
+pub use validation::NameProblem;
+pub use verify::FileVerification;
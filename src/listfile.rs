@@ -0,0 +1,25 @@
+/// The decoded contents of an archive's `(listfile)`,
+/// as returned by [listfile](struct.Archive.html#method.listfile).
+///
+/// Names are only parsed while iterating, and are borrowed from the listfile's data.
+#[derive(Debug, Clone)]
+pub struct Listfile {
+    data: Vec<u8>,
+}
+
+impl Listfile {
+    pub(crate) fn new(data: Vec<u8>) -> Listfile {
+        Listfile { data }
+    }
+
+    /// Returns an iterator over all names in the listfile, in the order they are listed.
+    ///
+    /// Names are separated by line breaks. Empty lines and lines which
+    /// are not valid UTF-8 are skipped.
+    pub fn iter(&self) -> impl Iterator<Item = &str> + '_ {
+        self.data
+            .split(|byte| *byte == b'\r' || *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| std::str::from_utf8(line).ok())
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+
+use mlua::{Lua, Table, UserData, UserDataMethods};
+
+use super::archive::{Archive, DynArchive};
+use super::creator::{Compression, Creator, FileOptions};
+
+impl UserData for DynArchive {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut("files", |_, archive, ()| {
+            archive.files_result().map_err(mlua::Error::external)
+        });
+
+        methods.add_method("has_file", |_, archive, name: String| {
+            Ok(archive.file_info(&name).is_some())
+        });
+
+        methods.add_method_mut("read_file", |lua, archive, name: String| {
+            let contents = archive.read_file(&name).map_err(mlua::Error::external)?;
+
+            lua.create_string(&contents)
+        });
+    }
+}
+
+impl UserData for Creator {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut(
+            "add_file",
+            |_, creator, (name, contents, options): (String, mlua::String, Option<Table>)| {
+                let options = match options {
+                    Some(options) => FileOptions {
+                        compression: if options.get::<_, Option<bool>>("compress")?.unwrap_or(false)
+                        {
+                            Compression::Deflate
+                        } else {
+                            Compression::None
+                        },
+                        encrypt: options.get::<_, Option<bool>>("encrypt")?.unwrap_or(false),
+                        adjust_key: options
+                            .get::<_, Option<bool>>("adjust_key")?
+                            .unwrap_or(false),
+                        ..Default::default()
+                    },
+                    None => FileOptions {
+                        compression: Compression::Deflate,
+                        ..Default::default()
+                    },
+                };
+
+                creator.add_file(&name, contents.as_bytes(), options);
+
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut("write", |lua, creator, ()| {
+            let mut cursor = Cursor::new(Vec::new());
+            creator.write(&mut cursor).map_err(mlua::Error::external)?;
+
+            lua.create_string(cursor.get_ref())
+        });
+
+        methods.add_method_mut("write_to_file", |_, creator, path: String| {
+            let file = File::create(path).map_err(mlua::Error::external)?;
+
+            creator.write(file).map_err(mlua::Error::external)
+        });
+    }
+}
+
+/// Creates a Lua table exposing archives to Lua scripts, enabled with the `lua` feature.
+///
+/// The table contains the following functions:
+/// - `open(path)` opens the archive at `path`.
+/// - `from_string(data)` opens an archive held in a Lua string.
+/// - `creator()` returns a new, empty creator.
+///
+/// Archives provide the methods `files()`, `has_file(name)` and `read_file(name)`,
+/// which returns the file's contents as a string. Creators provide `add_file(name, data, options)`,
+/// where `options` is an optional table with the boolean fields `compress`, `encrypt` and `adjust_key`
+/// (files are compressed if it is omitted), as well as `write()`, which returns the archive as a string,
+/// and `write_to_file(path)`.
+///
+/// [DynArchive](type.DynArchive.html) and [Creator](struct.Creator.html) implement `mlua::UserData`,
+/// so existing values can also be passed to Lua directly.
+pub fn lua_module(lua: &Lua) -> mlua::Result<Table<'_>> {
+    let module = lua.create_table()?;
+
+    module.set(
+        "open",
+        lua.create_function(|_, path: String| {
+            let file = File::open(path).map_err(mlua::Error::external)?;
+
+            Archive::open_dyn(BufReader::new(file)).map_err(mlua::Error::external)
+        })?,
+    )?;
+
+    module.set(
+        "from_string",
+        lua.create_function(|_, data: mlua::String| {
+            let data = data.as_bytes().to_vec();
+
+            Archive::open_dyn(Cursor::new(data)).map_err(mlua::Error::external)
+        })?,
+    )?;
+
+    module.set(
+        "creator",
+        lua.create_function(|_, ()| Ok(Creator::default()))?,
+    )?;
+
+    Ok(module)
+}
@@ -0,0 +1,472 @@
+//! LZMA decompression, as used by some third-party MPQ editors.
+//!
+//! MPQ stores LZMA data as a filter byte, which must be zero, followed by the 5 bytes
+//! of LZMA properties, the 64-bit uncompressed size, and the compressed stream.
+//! The decoder follows the reference decoder from the LZMA SDK's `LzmaSpec.cpp`,
+//! using the output buffer itself as the dictionary.
+
+const PROPERTIES_SIZE: usize = 5;
+const HEADER_SIZE: usize = 1 + PROPERTIES_SIZE + 8;
+
+const BIT_MODEL_TOTAL_BITS: u32 = 11;
+const MOVE_BITS: u32 = 5;
+const TOP_VALUE: u32 = 1 << 24;
+const INITIAL_PROBABILITY: u16 = 1 << (BIT_MODEL_TOTAL_BITS - 1);
+
+const STATES: usize = 12;
+const MAX_POS_BITS: usize = 4;
+const LEN_TO_POS_STATES: usize = 4;
+const ALIGN_BITS: usize = 4;
+const START_POS_MODEL_INDEX: u32 = 4;
+const END_POS_MODEL_INDEX: u32 = 14;
+const FULL_DISTANCES: usize = 1 << (END_POS_MODEL_INDEX >> 1);
+const MATCH_MIN_LEN: usize = 2;
+
+const END_MARKER_DISTANCE: u32 = 0xFFFF_FFFF;
+
+struct RangeDecoder<'a> {
+    input: &'a [u8],
+    position: usize,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> RangeDecoder<'a> {
+    fn new(input: &'a [u8]) -> Option<RangeDecoder<'a>> {
+        let (&first, rest) = input.split_first()?;
+        if first != 0 || rest.len() < 4 {
+            return None;
+        }
+
+        let decoder = RangeDecoder {
+            input,
+            position: 5,
+            range: 0xFFFF_FFFF,
+            code: u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]),
+        };
+
+        if decoder.code == decoder.range {
+            return None;
+        }
+
+        Some(decoder)
+    }
+
+    fn next_byte(&mut self) -> Option<u8> {
+        let byte = *self.input.get(self.position)?;
+        self.position += 1;
+
+        Some(byte)
+    }
+
+    fn normalize(&mut self) -> Option<()> {
+        if self.range < TOP_VALUE {
+            self.range <<= 8;
+            self.code = (self.code << 8) | u32::from(self.next_byte()?);
+        }
+
+        Some(())
+    }
+
+    fn decode_bit(&mut self, probability: &mut u16) -> Option<u32> {
+        let bound = (self.range >> BIT_MODEL_TOTAL_BITS) * u32::from(*probability);
+
+        let bit = if self.code < bound {
+            *probability += ((1 << BIT_MODEL_TOTAL_BITS) - *probability) >> MOVE_BITS;
+            self.range = bound;
+            0
+        } else {
+            *probability -= *probability >> MOVE_BITS;
+            self.code -= bound;
+            self.range -= bound;
+            1
+        };
+
+        self.normalize()?;
+
+        Some(bit)
+    }
+
+    fn decode_direct_bits(&mut self, count: u32) -> Option<u32> {
+        let mut result = 0u32;
+
+        for _ in 0..count {
+            self.range >>= 1;
+            self.code = self.code.wrapping_sub(self.range);
+            let mask = 0u32.wrapping_sub(self.code >> 31);
+            self.code = self.code.wrapping_add(self.range & mask);
+
+            if self.code == self.range {
+                return None;
+            }
+
+            result = (result << 1).wrapping_add(mask.wrapping_add(1));
+            self.normalize()?;
+        }
+
+        Some(result)
+    }
+
+    fn decode_tree(&mut self, probabilities: &mut [u16], bits: u32) -> Option<u32> {
+        let mut m = 1;
+        for _ in 0..bits {
+            m = (m << 1) + self.decode_bit(&mut probabilities[m as usize])?;
+        }
+
+        Some(m - (1 << bits))
+    }
+
+    fn decode_reverse_tree(&mut self, probabilities: &mut [u16], bits: u32) -> Option<u32> {
+        let mut m = 1;
+        let mut symbol = 0;
+        for i in 0..bits {
+            let bit = self.decode_bit(&mut probabilities[m as usize])?;
+            m = (m << 1) + bit;
+            symbol |= bit << i;
+        }
+
+        Some(symbol)
+    }
+}
+
+struct LenDecoder {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 1 << 3]; 1 << MAX_POS_BITS],
+    mid: [[u16; 1 << 3]; 1 << MAX_POS_BITS],
+    high: [u16; 1 << 8],
+}
+
+impl LenDecoder {
+    fn new() -> LenDecoder {
+        LenDecoder {
+            choice: INITIAL_PROBABILITY,
+            choice2: INITIAL_PROBABILITY,
+            low: [[INITIAL_PROBABILITY; 1 << 3]; 1 << MAX_POS_BITS],
+            mid: [[INITIAL_PROBABILITY; 1 << 3]; 1 << MAX_POS_BITS],
+            high: [INITIAL_PROBABILITY; 1 << 8],
+        }
+    }
+
+    fn decode(&mut self, range: &mut RangeDecoder, pos_state: usize) -> Option<usize> {
+        let len = if range.decode_bit(&mut self.choice)? == 0 {
+            range.decode_tree(&mut self.low[pos_state], 3)?
+        } else if range.decode_bit(&mut self.choice2)? == 0 {
+            8 + range.decode_tree(&mut self.mid[pos_state], 3)?
+        } else {
+            16 + range.decode_tree(&mut self.high, 8)?
+        };
+
+        Some(len as usize)
+    }
+}
+
+/// Decompresses `input` into `output`, returning the decompressed size.
+///
+/// Returns `None` if the data is corrupted or does not fit into `output`.
+pub(crate) fn decompress(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    if input.len() <= HEADER_SIZE || input[0] != 0 {
+        return None;
+    }
+
+    let properties = input[1];
+    if properties >= 9 * 5 * 5 {
+        return None;
+    }
+
+    let lc = u32::from(properties % 9);
+    let lp = u32::from((properties / 9) % 5);
+    let pb = u32::from(properties / 45);
+    let dictionary_size = u32::from_le_bytes([input[2], input[3], input[4], input[5]]);
+
+    let mut size_bytes = [0u8; 8];
+    size_bytes.copy_from_slice(&input[1 + PROPERTIES_SIZE..HEADER_SIZE]);
+    let unpacked_size = u64::from_le_bytes(size_bytes);
+
+    // an unknown size is marked with all bits set, in which case the stream has an end marker
+    let size = if unpacked_size == u64::MAX {
+        output.len()
+    } else if unpacked_size <= output.len() as u64 {
+        unpacked_size as usize
+    } else {
+        return None;
+    };
+
+    let mut range = RangeDecoder::new(&input[HEADER_SIZE..])?;
+    let mut decoder = Decoder::new(lc, lp, pb);
+
+    decoder.decode(&mut range, &mut output[..size], dictionary_size)
+}
+
+struct Decoder {
+    lc: u32,
+    lp: u32,
+    pb: u32,
+    literals: Vec<u16>,
+    pos_slot: [[u16; 1 << 6]; LEN_TO_POS_STATES],
+    pos_decoders: [u16; 1 + FULL_DISTANCES - END_POS_MODEL_INDEX as usize],
+    align: [u16; 1 << ALIGN_BITS],
+    len_decoder: LenDecoder,
+    rep_len_decoder: LenDecoder,
+    is_match: [u16; STATES << MAX_POS_BITS],
+    is_rep: [u16; STATES],
+    is_rep_g0: [u16; STATES],
+    is_rep_g1: [u16; STATES],
+    is_rep_g2: [u16; STATES],
+    is_rep0_long: [u16; STATES << MAX_POS_BITS],
+}
+
+impl Decoder {
+    fn new(lc: u32, lp: u32, pb: u32) -> Decoder {
+        Decoder {
+            lc,
+            lp,
+            pb,
+            literals: vec![INITIAL_PROBABILITY; 0x300 << (lc + lp)],
+            pos_slot: [[INITIAL_PROBABILITY; 1 << 6]; LEN_TO_POS_STATES],
+            pos_decoders: [INITIAL_PROBABILITY; 1 + FULL_DISTANCES - END_POS_MODEL_INDEX as usize],
+            align: [INITIAL_PROBABILITY; 1 << ALIGN_BITS],
+            len_decoder: LenDecoder::new(),
+            rep_len_decoder: LenDecoder::new(),
+            is_match: [INITIAL_PROBABILITY; STATES << MAX_POS_BITS],
+            is_rep: [INITIAL_PROBABILITY; STATES],
+            is_rep_g0: [INITIAL_PROBABILITY; STATES],
+            is_rep_g1: [INITIAL_PROBABILITY; STATES],
+            is_rep_g2: [INITIAL_PROBABILITY; STATES],
+            is_rep0_long: [INITIAL_PROBABILITY; STATES << MAX_POS_BITS],
+        }
+    }
+
+    fn decode_literal(
+        &mut self,
+        range: &mut RangeDecoder,
+        output: &[u8],
+        position: usize,
+        state: usize,
+        rep0: u32,
+    ) -> Option<u8> {
+        let previous_byte = if position > 0 {
+            u32::from(output[position - 1])
+        } else {
+            0
+        };
+
+        let literal_state = (((position as u32) & ((1 << self.lp) - 1)) << self.lc)
+            + (previous_byte >> (8 - self.lc));
+        let start = 0x300 * literal_state as usize;
+        let probabilities = &mut self.literals[start..start + 0x300];
+
+        let mut symbol = 1u32;
+        if state >= 7 {
+            let mut match_byte = u32::from(*output.get(position.checked_sub(rep0 as usize + 1)?)?);
+
+            while symbol < 0x100 {
+                let match_bit = (match_byte >> 7) & 1;
+                match_byte <<= 1;
+                let index = (((1 + match_bit) << 8) + symbol) as usize;
+                let bit = range.decode_bit(&mut probabilities[index])?;
+                symbol = (symbol << 1) | bit;
+
+                if match_bit != bit {
+                    break;
+                }
+            }
+        }
+
+        while symbol < 0x100 {
+            symbol = (symbol << 1) | range.decode_bit(&mut probabilities[symbol as usize])?;
+        }
+
+        Some((symbol - 0x100) as u8)
+    }
+
+    fn decode_distance(&mut self, range: &mut RangeDecoder, len: usize) -> Option<u32> {
+        let len_state = len.min(LEN_TO_POS_STATES - 1);
+        let pos_slot = range.decode_tree(&mut self.pos_slot[len_state], 6)?;
+        if pos_slot < START_POS_MODEL_INDEX {
+            return Some(pos_slot);
+        }
+
+        let direct_bits = (pos_slot >> 1) - 1;
+        let mut distance = (2 | (pos_slot & 1)) << direct_bits;
+
+        if pos_slot < END_POS_MODEL_INDEX {
+            let start = (distance - pos_slot) as usize;
+            distance += range.decode_reverse_tree(&mut self.pos_decoders[start..], direct_bits)?;
+        } else {
+            distance = distance.wrapping_add(
+                range.decode_direct_bits(direct_bits - ALIGN_BITS as u32)? << ALIGN_BITS,
+            );
+            distance = distance
+                .wrapping_add(range.decode_reverse_tree(&mut self.align, ALIGN_BITS as u32)?);
+        }
+
+        Some(distance)
+    }
+
+    fn decode(
+        &mut self,
+        range: &mut RangeDecoder,
+        output: &mut [u8],
+        dictionary_size: u32,
+    ) -> Option<usize> {
+        let dictionary_size = dictionary_size.max(1 << 12) as usize;
+        let mut reps = [0u32; 4];
+        let mut state = 0usize;
+        let mut position = 0usize;
+
+        loop {
+            // the stream may end without an end marker once all data is decoded
+            if position == output.len() && range.code == 0 {
+                return Some(position);
+            }
+
+            let pos_state = position & ((1 << self.pb) - 1);
+
+            if range.decode_bit(&mut self.is_match[(state << MAX_POS_BITS) + pos_state])? == 0 {
+                // once the data is complete, the stream may only continue with an end marker
+                if position == output.len() {
+                    return None;
+                }
+
+                output[position] = self.decode_literal(range, output, position, state, reps[0])?;
+                position += 1;
+                state = match state {
+                    0..=3 => 0,
+                    4..=9 => state - 3,
+                    _ => state - 6,
+                };
+
+                continue;
+            }
+
+            let len = if range.decode_bit(&mut self.is_rep[state])? != 0 {
+                if position == 0 || position == output.len() {
+                    return None;
+                }
+
+                if range.decode_bit(&mut self.is_rep_g0[state])? == 0 {
+                    let index = (state << MAX_POS_BITS) + pos_state;
+                    if range.decode_bit(&mut self.is_rep0_long[index])? == 0 {
+                        state = if state < 7 { 9 } else { 11 };
+                        output[position] =
+                            *output.get(position.checked_sub(reps[0] as usize + 1)?)?;
+                        position += 1;
+
+                        continue;
+                    }
+                } else {
+                    let distance = if range.decode_bit(&mut self.is_rep_g1[state])? == 0 {
+                        reps[1]
+                    } else if range.decode_bit(&mut self.is_rep_g2[state])? == 0 {
+                        let distance = reps[2];
+                        reps[2] = reps[1];
+                        distance
+                    } else {
+                        let distance = reps[3];
+                        reps[3] = reps[2];
+                        reps[2] = reps[1];
+                        distance
+                    };
+
+                    reps[1] = reps[0];
+                    reps[0] = distance;
+                }
+
+                state = if state < 7 { 8 } else { 11 };
+                self.rep_len_decoder.decode(range, pos_state)?
+            } else {
+                reps[3] = reps[2];
+                reps[2] = reps[1];
+                reps[1] = reps[0];
+
+                let len = self.len_decoder.decode(range, pos_state)?;
+                state = if state < 7 { 7 } else { 10 };
+                reps[0] = self.decode_distance(range, len)?;
+
+                if reps[0] == END_MARKER_DISTANCE {
+                    return if range.code == 0 {
+                        Some(position)
+                    } else {
+                        None
+                    };
+                }
+
+                if position == output.len()
+                    || reps[0] as usize >= dictionary_size
+                    || reps[0] as usize >= position
+                {
+                    return None;
+                }
+
+                len
+            };
+
+            let len = len + MATCH_MIN_LEN;
+            let distance = reps[0] as usize + 1;
+            if position + len > output.len() || distance > position {
+                return None;
+            }
+
+            // the source and destination may overlap, so copy byte by byte
+            for i in position..position + len {
+                output[i] = output[i - distance];
+            }
+
+            position += len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENTS: &[u8] = b"war3map.j war3map.w3e war3map.j war3map.w3e war3map.j\n";
+
+    /// `CONTENTS` in the `.lzma` format, with an unknown size and an end marker,
+    /// preceded by the filter byte of MPQ's LZMA compression.
+    const COMPRESSED: [u8; 45] = [
+        0x00, 0x5D, 0x00, 0x10, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00,
+        0x3B, 0x98, 0x4A, 0xA7, 0x69, 0x72, 0x86, 0x56, 0xBA, 0x05, 0xD3, 0xD5, 0xA3, 0x0F, 0xB5,
+        0x22, 0xF0, 0xC4, 0x43, 0x64, 0xE8, 0x80, 0x76, 0xB7, 0xFF, 0xFF, 0x82, 0x14, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn decompresses_known_stream() {
+        let mut output = vec![0; 256];
+        let size = decompress(&COMPRESSED, &mut output).unwrap();
+        assert_eq!(&output[..size], CONTENTS);
+
+        // with a known size, the end marker is still accepted
+        let mut sized = COMPRESSED;
+        sized[1 + PROPERTIES_SIZE..HEADER_SIZE]
+            .copy_from_slice(&(CONTENTS.len() as u64).to_le_bytes());
+        let size = decompress(&sized, &mut output).unwrap();
+        assert_eq!(&output[..size], CONTENTS);
+    }
+
+    #[test]
+    fn rejects_truncated_and_corrupted_streams() {
+        let mut output = vec![0; 256];
+
+        for length in 0..COMPRESSED.len() {
+            assert_eq!(decompress(&COMPRESSED[..length], &mut output), None);
+        }
+
+        // a size larger than the output is rejected up front
+        assert_eq!(
+            decompress(&COMPRESSED, &mut output[..CONTENTS.len() - 1]),
+            None
+        );
+
+        // corrupted streams must not panic, though they may decode to garbage
+        for position in HEADER_SIZE..COMPRESSED.len() {
+            for flip in [0x01, 0x80, 0xFF] {
+                let mut corrupted = COMPRESSED;
+                corrupted[position] ^= flip;
+                let _ = decompress(&corrupted, &mut output);
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::Error;
+use super::util::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A fingerprint of an archive, as returned by [manifest](struct.Archive.html#method.manifest).
+///
+/// Formatting a manifest with `Display` produces a line-based text form suitable for
+/// CI artifacts or distribution metadata. It starts with an `archive` line holding
+/// the archive's MD5 and size, followed by a `file` line per file holding its MD5,
+/// size, compressed size, block table flags and name:
+///
+/// ```text
+/// archive 4a7d1ed414474e4033ac29ccb8653d9b 1536
+/// file 5eb63bbbe01eeed093cb22bb8f5acdc3 11 19 80000200 hello.txt
+/// ```
+///
+/// The text form can be parsed back with `FromStr`.
+pub struct Manifest {
+    /// MD5 of the whole archive, from the MPQ header to the end of the archive.
+    pub archive_md5: [u8; 16],
+    /// Size of the archive, as hashed for `archive_md5`.
+    pub archive_size: u64,
+    /// All files listed in the archive's `(listfile)`, sorted by name.
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A single file in a [Manifest](struct.Manifest.html).
+pub struct ManifestEntry {
+    /// Name of the file inside the archive.
+    pub name: String,
+    /// MD5 of the file's uncompressed contents.
+    pub md5: [u8; 16],
+    /// Uncompressed size of the file.
+    pub size: u64,
+    /// Size of the file as it is stored in the archive.
+    pub compressed_size: u64,
+    /// Block table flags of the file.
+    pub flags: u32,
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "archive {} {}",
+            to_hex(&self.archive_md5),
+            self.archive_size
+        )?;
+
+        for file in &self.files {
+            writeln!(
+                f,
+                "file {} {} {} {:08x} {}",
+                to_hex(&file.md5),
+                file.size,
+                file.compressed_size,
+                file.flags,
+                file.name
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The first difference found when comparing archives with
+/// [Manifest.compare](struct.Manifest.html#method.compare).
+pub enum Divergence {
+    /// The file only exists in the expected archive.
+    Missing { name: String },
+    /// The file only exists in the actual archive.
+    Unexpected { name: String },
+    /// The file exists in both archives, but its contents differ.
+    Contents { name: String },
+    /// The file exists in both archives, but its block table flags differ.
+    Flags {
+        name: String,
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl Manifest {
+    /// Checks whether the archive described by `actual` is logically identical to this one,
+    /// i.e. whether it contains the same files with the same contents and flags,
+    /// regardless of how they are laid out. Returns the first difference in name order,
+    /// or `None` if there is none.
+    ///
+    /// The hash of the whole archive and the compressed sizes of files are not compared.
+    pub fn compare(&self, actual: &Manifest) -> Option<Divergence> {
+        let mut expected_files: Vec<&ManifestEntry> = self.files.iter().collect();
+        let mut actual_files: Vec<&ManifestEntry> = actual.files.iter().collect();
+        expected_files.sort_by(|a, b| a.name.cmp(&b.name));
+        actual_files.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut expected_files = expected_files.into_iter().peekable();
+        let mut actual_files = actual_files.into_iter().peekable();
+
+        loop {
+            let (expected, actual) = match (expected_files.peek(), actual_files.peek()) {
+                (None, None) => return None,
+                (Some(expected), None) => {
+                    return Some(Divergence::Missing {
+                        name: expected.name.clone(),
+                    })
+                }
+                (None, Some(actual)) => {
+                    return Some(Divergence::Unexpected {
+                        name: actual.name.clone(),
+                    })
+                }
+                (Some(expected), Some(actual)) => (*expected, *actual),
+            };
+
+            match expected.name.cmp(&actual.name) {
+                Ordering::Less => {
+                    return Some(Divergence::Missing {
+                        name: expected.name.clone(),
+                    })
+                }
+                Ordering::Greater => {
+                    return Some(Divergence::Unexpected {
+                        name: actual.name.clone(),
+                    })
+                }
+                Ordering::Equal => {}
+            }
+
+            if expected.md5 != actual.md5 || expected.size != actual.size {
+                return Some(Divergence::Contents {
+                    name: expected.name.clone(),
+                });
+            }
+
+            if expected.flags != actual.flags {
+                return Some(Divergence::Flags {
+                    name: expected.name.clone(),
+                    expected: expected.flags,
+                    actual: actual.flags,
+                });
+            }
+
+            expected_files.next();
+            actual_files.next();
+        }
+    }
+}
+
+impl FromStr for Manifest {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Manifest, Error> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+
+        let invalid = |line: usize| Error::InvalidManifest { line: line + 1 };
+
+        let (line, archive) = lines.next().ok_or(Error::InvalidManifest { line: 1 })?;
+        let mut fields = archive.split(' ');
+        if fields.next() != Some("archive") {
+            return Err(invalid(line));
+        }
+
+        let archive_md5 = fields
+            .next()
+            .and_then(parse_md5)
+            .ok_or_else(|| invalid(line))?;
+        let archive_size = fields
+            .next()
+            .and_then(|size| size.parse().ok())
+            .ok_or_else(|| invalid(line))?;
+
+        let mut files = Vec::new();
+        for (line, file) in lines {
+            let mut fields = file.splitn(6, ' ');
+            if fields.next() != Some("file") {
+                return Err(invalid(line));
+            }
+
+            let md5 = fields.next().and_then(parse_md5);
+            let size = fields.next().and_then(|size| size.parse().ok());
+            let compressed_size = fields.next().and_then(|size| size.parse().ok());
+            let flags = fields
+                .next()
+                .and_then(|flags| u32::from_str_radix(flags, 16).ok());
+            let name = fields.next();
+
+            match (md5, size, compressed_size, flags, name) {
+                (Some(md5), Some(size), Some(compressed_size), Some(flags), Some(name)) => files
+                    .push(ManifestEntry {
+                        name: name.to_string(),
+                        md5,
+                        size,
+                        compressed_size,
+                        flags,
+                    }),
+                _ => return Err(invalid(line)),
+            }
+        }
+
+        Ok(Manifest {
+            archive_md5,
+            archive_size,
+            files,
+        })
+    }
+}
+
+fn parse_md5(hex: &str) -> Option<[u8; 16]> {
+    if hex.len() != 32 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut md5 = [0u8; 16];
+    for (i, byte) in md5.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(md5)
+}
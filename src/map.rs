@@ -0,0 +1,42 @@
+// `err-derive` generates its trait impls inside an anonymous const
+#![allow(non_local_definitions)]
+
+use err_derive::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+/// A structural problem with a Warcraft III map, as reported by
+/// [check_map](struct.Archive.html#method.check_map).
+pub enum MapIssue {
+    /// The map does not start with the `HM3W` preamble that Warcraft III expects before the archive.
+    #[error(
+        display = "The map does not start with an HM3W preamble; save it with the World Editor or prepend a 512-byte map header"
+    )]
+    MissingPreamble,
+    /// The MPQ header is not located at offset 512, directly after the preamble.
+    #[error(
+        display = "The MPQ header is at offset {} instead of 512, directly after the map header",
+        offset
+    )]
+    UnexpectedHeaderOffset { offset: u64 },
+    /// A file which every map needs is missing.
+    #[error(
+        display = "{} is missing; the game cannot load the map without it",
+        name
+    )]
+    MissingFile { name: String },
+    /// Neither a JASS nor a Lua map script was found.
+    #[error(display = "No map script was found; add war3map.j or war3map.lua")]
+    MissingScript,
+    /// The map script exists, but cannot be read.
+    #[error(display = "The map script {} cannot be read: {}", name, message)]
+    UnreadableScript { name: String, message: String },
+    /// The map has no `(listfile)`, so other tools cannot list or extract its files.
+    #[error(display = "The map has no (listfile), so its files cannot be listed")]
+    MissingListfile,
+    /// Some files are not named in the `(listfile)`.
+    #[error(
+        display = "{} files are not named in the (listfile); add their names to keep them editable",
+        count
+    )]
+    UnlistedFiles { count: usize },
+}
@@ -0,0 +1,36 @@
+use globset::{GlobBuilder, GlobMatcher};
+use regex::Regex;
+
+use super::error::Error;
+
+/// Compiles a glob pattern for matching archive file names.
+///
+/// Matching is case-insensitive like MPQ name lookup, and both backslashes and
+/// forward slashes are accepted as path separators in the pattern.
+/// Use [`glob_name`] to bring a file name into the same form before matching it.
+pub(crate) fn compile_glob(pattern: &str) -> Result<GlobMatcher, Error> {
+    let glob = GlobBuilder::new(&glob_name(pattern))
+        .case_insensitive(true)
+        .literal_separator(true)
+        .build()
+        .map_err(|error| Error::InvalidPattern {
+            pattern: pattern.to_string(),
+            message: error.kind().to_string(),
+        })?;
+
+    Ok(glob.compile_matcher())
+}
+
+/// Replaces the backslash separators used by MPQ with forward slashes,
+/// which are the only separators understood by glob patterns.
+pub(crate) fn glob_name(name: &str) -> String {
+    name.replace('\\', "/")
+}
+
+/// Compiles a regular expression for matching archive file names.
+pub(crate) fn compile_regex(pattern: &str) -> Result<Regex, Error> {
+    Regex::new(pattern).map_err(|error| Error::InvalidPattern {
+        pattern: pattern.to_string(),
+        message: error.to_string(),
+    })
+}
@@ -0,0 +1,243 @@
+use std::collections::HashSet;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use super::archive::OpenOptions;
+use super::consts::*;
+use super::util::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// The hashes of a file name which MPQ uses to locate it in the hash table.
+///
+/// Names are hashed exactly as given, i.e. without any slash normalization.
+pub struct NameHash {
+    /// The hash determining the position at which the search for the name
+    /// starts in the hash table. Must be masked by the hash table size.
+    pub index: u32,
+    /// The first hash stored in a hash table entry to identify the name.
+    pub hash_a: u32,
+    /// The second hash stored in a hash table entry to identify the name.
+    pub hash_b: u32,
+}
+
+impl NameHash {
+    /// Calculates the hashes of a file name.
+    pub fn new(name: &str) -> NameHash {
+        NameHash {
+            index: hash_string(name.as_bytes(), MPQ_HASH_TABLE_INDEX),
+            hash_a: hash_string(name.as_bytes(), MPQ_HASH_NAME_A),
+            hash_b: hash_string(name.as_bytes(), MPQ_HASH_NAME_B),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// A file name whose hashes are calculated once, for programs which look up or add the same names
+/// many times.
+///
+/// It can be passed to [read_file](struct.Archive.html#method.read_file),
+/// [contains](struct.Archive.html#method.contains), [file_info](struct.Archive.html#method.file_info)
+/// and [Creator::add_file](struct.Creator.html#method.add_file) in place of a string.
+/// The precomputed hashes are only used if the name is looked up unchanged, i.e. if no slash policy,
+/// Unicode normalization or case-sensitive matching changes how it is hashed. Otherwise, the name is
+/// hashed on every use like any other string.
+pub struct FileName {
+    name: String,
+    hash: NameHash,
+    // the hash of the name without its directories, from which encryption keys are derived
+    key_hash: u32,
+}
+
+impl FileName {
+    /// Calculates the hashes of a file name.
+    pub fn new<S: Into<String>>(name: S) -> FileName {
+        let name = name.into();
+        let hash = NameHash::new(&name);
+        let key_hash = hash_string(get_plain_name(&name), MPQ_HASH_FILE_KEY);
+
+        FileName {
+            name,
+            hash,
+            key_hash,
+        }
+    }
+
+    /// The name as a string.
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+
+    /// The precomputed hashes of the name.
+    pub fn hash(&self) -> NameHash {
+        self.hash
+    }
+
+    pub(crate) fn key_hash(&self) -> u32 {
+        self.key_hash
+    }
+}
+
+impl From<&str> for FileName {
+    fn from(name: &str) -> FileName {
+        FileName::new(name)
+    }
+}
+
+impl From<String> for FileName {
+    fn from(name: String) -> FileName {
+        FileName::new(name)
+    }
+}
+
+impl AsRef<str> for FileName {
+    fn as_ref(&self) -> &str {
+        &self.name
+    }
+}
+
+impl std::fmt::Display for FileName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// A file name which is looked up in or added to an archive,
+/// either as a string or as a [FileName](struct.FileName.html) with precomputed hashes.
+pub trait AsFileName {
+    /// The name as a string.
+    fn name(&self) -> &str;
+
+    /// The name with its precomputed hashes, if it has any.
+    fn precomputed(&self) -> Option<&FileName> {
+        None
+    }
+}
+
+impl AsFileName for str {
+    fn name(&self) -> &str {
+        self
+    }
+}
+
+impl AsFileName for String {
+    fn name(&self) -> &str {
+        self
+    }
+}
+
+impl AsFileName for std::borrow::Cow<'_, str> {
+    fn name(&self) -> &str {
+        self
+    }
+}
+
+impl AsFileName for FileName {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn precomputed(&self) -> Option<&FileName> {
+        Some(self)
+    }
+}
+
+impl<T: AsFileName + ?Sized> AsFileName for &T {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn precomputed(&self) -> Option<&FileName> {
+        (**self).precomputed()
+    }
+}
+
+/// Calculates the hashes of many file names at once, returning them in the same order.
+///
+/// With the `parallel` feature enabled, names are hashed on all available threads.
+pub fn hash_names<S>(names: &[S]) -> Vec<NameHash>
+where
+    S: AsRef<str> + Sync,
+{
+    #[cfg(feature = "parallel")]
+    let hashes = names
+        .par_iter()
+        .map(|name| NameHash::new(name.as_ref()))
+        .collect();
+
+    #[cfg(not(feature = "parallel"))]
+    let hashes = names
+        .iter()
+        .map(|name| NameHash::new(name.as_ref()))
+        .collect();
+
+    hashes
+}
+
+#[derive(Debug, Clone)]
+/// Tests candidate names against the hash table entries of an archive whose names are not known,
+/// as created by [name_matcher](struct.Archive.html#method.name_matcher).
+///
+/// Unresolved entries are indexed by their hashes, so testing a name takes constant time.
+/// Every entry is reported as resolved only once.
+pub struct NameMatcher {
+    unresolved: HashSet<(u32, u32)>,
+    resolved: Vec<String>,
+    options: OpenOptions,
+}
+
+impl NameMatcher {
+    pub(crate) fn new(unresolved: HashSet<(u32, u32)>, options: OpenOptions) -> NameMatcher {
+        NameMatcher {
+            unresolved,
+            resolved: Vec::new(),
+            options,
+        }
+    }
+
+    /// Tests a candidate name, returning `true` if it resolves any entries that were not resolved before.
+    ///
+    /// Names are hashed the same way as lookups in the archive the matcher was created from.
+    pub fn test(&mut self, name: &str) -> bool {
+        let lookup_name = self.options.lookup_name(name);
+        let hash = if self.options.case_sensitive {
+            hash_string_case_sensitive
+        } else {
+            hash_string
+        };
+
+        let hash_a = hash(lookup_name.as_bytes(), MPQ_HASH_NAME_A);
+        let hash_b = hash(lookup_name.as_bytes(), MPQ_HASH_NAME_B);
+
+        if self.unresolved.remove(&(hash_a, hash_b)) {
+            self.resolved.push(name.to_string());
+
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Tests many candidate names, returning those which resolved entries that were not resolved before.
+    pub fn test_all<I, S>(&mut self, names: I) -> Vec<String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        names
+            .into_iter()
+            .filter(|name| self.test(name.as_ref()))
+            .map(|name| name.as_ref().to_string())
+            .collect()
+    }
+
+    /// All names that resolved entries so far, in the order they were found.
+    pub fn resolved(&self) -> &[String] {
+        &self.resolved
+    }
+
+    /// The number of distinct names that remain unresolved.
+    pub fn remaining(&self) -> usize {
+        self.unresolved.len()
+    }
+}
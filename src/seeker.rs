@@ -1,15 +1,26 @@
 use std::io::{Read, Seek, SeekFrom};
 
 use byteorder::{ReadBytesExt, LE};
+use md5::{Digest, Md5};
 
 use super::consts::*;
 use super::error::Error;
 use super::header::*;
 
+/// The size of the window that is read ahead for small reads, so that reading the tables and sectors
+/// of small files does not seek and read the underlying reader every time.
+const READ_BUFFER_SIZE: u64 = 64 * 1024;
+
+/// The largest range that is read into the read buffer at once by `prefetch`.
+const MAX_PREFETCH_SIZE: u64 = 1024 * 1024;
+
 #[derive(Debug)]
 pub(crate) struct Seeker<R: Read + Seek> {
     reader: R,
     archive_info: ArchiveInfo,
+    // the most recently read window of the underlying reader, and its absolute position
+    buffer: Vec<u8>,
+    buffer_start: u64,
 }
 
 impl<R: Read + Seek> Seeker<R> {
@@ -19,9 +30,24 @@ impl<R: Read + Seek> Seeker<R> {
         Ok(Seeker {
             reader,
             archive_info,
+            buffer: Vec::new(),
+            buffer_start: 0,
         })
     }
 
+    /// Re-locates the MPQ headers, e.g. after the underlying data has changed.
+    pub(crate) fn reload(&mut self) -> Result<(), Error> {
+        self.buffer.clear();
+        self.archive_info = find_headers(&mut self.reader)?;
+
+        Ok(())
+    }
+
+    /// Returns the current length of the underlying reader.
+    pub(crate) fn stream_len(&mut self) -> Result<u64, Error> {
+        Ok(self.reader.seek(SeekFrom::End(0))?)
+    }
+
     fn archive_offset(&self, offset: u64) -> u64 {
         offset + self.archive_info.header_offset
     }
@@ -30,67 +56,365 @@ impl<R: Read + Seek> Seeker<R> {
         &self.archive_info
     }
 
+    pub(crate) fn set_info(&mut self, archive_info: ArchiveInfo) {
+        self.buffer.clear();
+        self.archive_info = archive_info;
+    }
+
     pub(crate) fn read(&mut self, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        self.read_into(offset, size, &mut buf)?;
+
+        Ok(buf)
+    }
+
+    /// Same as `read`, but reads into `buf`, replacing its contents,
+    /// so that callers can reuse its allocation.
+    pub(crate) fn read_into(
+        &mut self,
+        offset: u64,
+        size: u64,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), Error> {
         let offset = self.archive_offset(offset);
 
+        self.read_absolute_into(offset, size, buf)
+    }
+
+    /// Reads data at an absolute position in the underlying reader,
+    /// rather than relative to the start of the archive.
+    pub(crate) fn read_absolute(&mut self, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        self.read_absolute_into(offset, size, &mut buf)?;
+
+        Ok(buf)
+    }
+
+    fn read_absolute_into(
+        &mut self,
+        offset: u64,
+        size: u64,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), Error> {
         if offset + size > self.archive_info.file_size {
             return Err(Error::Corrupted);
         }
 
+        if self.read_buffered(offset, size, buf) {
+            return Ok(());
+        }
+
+        // large reads would not benefit from buffering
+        if size >= READ_BUFFER_SIZE {
+            buf.clear();
+            buf.resize(size as usize, 0);
+            self.reader.seek(SeekFrom::Start(offset))?;
+            self.reader.read_exact(buf)?;
+
+            return Ok(());
+        }
+
+        self.fill_buffer(offset, READ_BUFFER_SIZE)?;
+        self.read_buffered(offset, size, buf);
+
+        Ok(())
+    }
+
+    /// Reads a range into the read buffer with a single read of the underlying reader, so that
+    /// the following reads within it, e.g. of the sector offset table and sectors of a file,
+    /// are served from the buffer. Ranges larger than `MAX_PREFETCH_SIZE` are not prefetched.
+    pub(crate) fn prefetch(&mut self, offset: u64, size: u64) -> Result<(), Error> {
+        let offset = self.archive_offset(offset);
+
+        let covered = offset >= self.buffer_start
+            && offset + size <= self.buffer_start + self.buffer.len() as u64;
+        if covered || size > MAX_PREFETCH_SIZE || offset >= self.archive_info.file_size {
+            return Ok(());
+        }
+
+        self.fill_buffer(offset, size.max(READ_BUFFER_SIZE))
+    }
+
+    /// Replaces the read buffer with up to `window_size` bytes starting at the absolute `offset`.
+    fn fill_buffer(&mut self, offset: u64, window_size: u64) -> Result<(), Error> {
+        let window_size = window_size.min(self.archive_info.file_size - offset);
+        self.buffer.clear();
+        self.buffer.resize(window_size as usize, 0);
+        self.buffer_start = offset;
+
         self.reader.seek(SeekFrom::Start(offset))?;
-        let mut buf = vec![0u8; size as usize];
-        self.reader.read_exact(&mut buf)?;
+        if let Err(error) = self.reader.read_exact(&mut self.buffer) {
+            self.buffer.clear();
+            return Err(error.into());
+        }
 
-        Ok(buf)
+        Ok(())
     }
 
+    /// Copies the requested data from the read buffer into `buf`, if it is covered by it.
+    fn read_buffered(&self, offset: u64, size: u64, buf: &mut Vec<u8>) -> bool {
+        let data = offset
+            .checked_sub(self.buffer_start)
+            .and_then(|start| self.buffer.get(start as usize..(start + size) as usize));
+
+        match data {
+            Some(data) => {
+                buf.clear();
+                buf.extend_from_slice(data);
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Gives access to the underlying reader.
+    /// Since it may be written to, the read buffer is discarded.
     pub(crate) fn reader(&mut self) -> &mut R {
+        self.buffer.clear();
         &mut self.reader
     }
+
+    /// Gives shared access to the underlying reader, keeping the read buffer.
+    pub(crate) fn reader_ref(&self) -> &R {
+        &self.reader
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
-pub(crate) struct TableInfo {
+/// The location of a table in an archive, as part of [ArchiveInfo](struct.ArchiveInfo.html).
+pub struct TableInfo {
     pub(crate) entries: u64,
     pub(crate) offset: u64,
     pub(crate) size: u64,
+    // only stored in v4 headers
+    pub(crate) md5: Option<[u8; 16]>,
 }
 
-#[derive(Debug)]
-pub(crate) struct ArchiveInfo {
+impl TableInfo {
+    fn new(entries: u64, offset: u64, size: u64, md5: &[u8; 16]) -> TableInfo {
+        TableInfo {
+            entries,
+            offset,
+            size,
+            md5: Some(*md5).filter(|md5| !is_zero_md5(md5)),
+        }
+    }
+
+    /// The number of entries in the table.
+    ///
+    /// This is `0` for the HET and BET tables, whose headers store their number of entries.
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    /// The offset of the table, relative to the start of the archive.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The size of the table as it is stored in the archive.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The MD5 checksum of the stored table, which only v4 headers have.
+    pub fn md5(&self) -> Option<[u8; 16]> {
+        self.md5
+    }
+
+    /// Checks the stored data of the table against its MD5 checksum, if it has one.
+    pub(crate) fn verify(&self, data: &[u8], table: &str) -> Result<(), Error> {
+        match self.md5 {
+            Some(md5) if md5 != <[u8; 16]>::from(Md5::digest(data)) => {
+                Err(Error::TableChecksumMismatch {
+                    table: table.to_string(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Information about the location and layout of an archive, as returned by
+/// [info](struct.Archive.html#method.info).
+///
+/// Offsets of tables are relative to the start of the archive, so the
+/// [header_offset](struct.ArchiveInfo.html#method.header_offset) has to be added to them
+/// to get their position in the reader.
+pub struct ArchiveInfo {
     pub(crate) hash_table_info: TableInfo,
     pub(crate) block_table_info: TableInfo,
+    pub(crate) hi_block_table_info: Option<TableInfo>,
+    // the sizes of the HET and BET tables are only stored in v4 headers
+    pub(crate) het_table_info: Option<TableInfo>,
+    pub(crate) bet_table_info: Option<TableInfo>,
 
+    pub(crate) format_version: u16,
     pub(crate) sector_size: u64,
     pub(crate) file_size: u64,
     pub(crate) archive_size: u64,
     pub(crate) header_offset: u64,
+
+    pub(crate) header: ArchiveHeader,
 }
 
 impl ArchiveInfo {
+    /// The location of the hash table.
+    pub fn hash_table(&self) -> &TableInfo {
+        &self.hash_table_info
+    }
+
+    /// The location of the block table.
+    pub fn block_table(&self) -> &TableInfo {
+        &self.block_table_info
+    }
+
+    /// The location of the hi-block table, if the archive has one.
+    pub fn hi_block_table(&self) -> Option<&TableInfo> {
+        self.hi_block_table_info.as_ref()
+    }
+
+    /// The location of the HET table, if the archive has one.
+    pub fn het_table(&self) -> Option<&TableInfo> {
+        self.het_table_info.as_ref()
+    }
+
+    /// The location of the BET table, if the archive has one.
+    pub fn bet_table(&self) -> Option<&TableInfo> {
+        self.bet_table_info.as_ref()
+    }
+
+    /// The format version, where `0` is the original format and `3` is the latest one.
+    pub fn format_version(&self) -> u16 {
+        self.format_version
+    }
+
+    /// The size in bytes of the sectors that files in the archive are split into.
+    pub fn sector_size(&self) -> u64 {
+        self.sector_size
+    }
+
+    /// The size of the whole reader, which may be larger than the archive.
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// The size of the archive as specified in the MPQ header.
+    pub fn archive_size(&self) -> u64 {
+        self.archive_size
+    }
+
+    /// The offset of the MPQ header relative to the beginning of the reader.
+    pub fn header_offset(&self) -> u64 {
+        self.header_offset
+    }
+
+    /// Whether the archive is embedded in other data, such as the preamble of a Warcraft III map
+    /// or an executable, i.e. whether it does not start at the beginning of the reader.
+    pub fn is_embedded(&self) -> bool {
+        self.header_offset != 0
+    }
+
+    /// The parsed MPQ header.
+    pub fn header(&self) -> &ArchiveHeader {
+        &self.header
+    }
+
     fn new(file_size: u64, header_offset: u64, header: &FileHeader) -> ArchiveInfo {
-        let hash_table_info = TableInfo {
-            entries: u64::from(header.hash_table_entries),
-            offset: u64::from(header.hash_table_offset),
-            size: u64::from(header.block_table_offset - header.hash_table_offset),
+        let archive_size = if header.archive_size_64 != 0 {
+            header.archive_size_64
+        } else {
+            u64::from(header.archive_size)
+        };
+
+        let hash_table_entries = u64::from(header.hash_table_entries);
+        let block_table_entries = u64::from(header.block_table_entries);
+        let hash_table_entries_size = hash_table_entries * u64::from(HASH_TABLE_ENTRY_SIZE);
+        let block_table_entries_size = block_table_entries * u64::from(BLOCK_TABLE_ENTRY_SIZE);
+
+        // v1 tables may be compressed, so their size is derived from their positions,
+        // v2 and v3 never compress the classic tables, and v4 stores the sizes.
+        // If the tables are not in the expected order, they are assumed to be uncompressed.
+        let (hash_table_size, block_table_size) = match header.format_version {
+            0 => (
+                header
+                    .block_table_offset
+                    .checked_sub(header.hash_table_offset)
+                    .map_or(hash_table_entries_size, u64::from),
+                header
+                    .archive_size
+                    .checked_sub(header.block_table_offset)
+                    .map_or(block_table_entries_size, u64::from),
+            ),
+            3 if header.header_size as u64 >= HEADER_MPQ_SIZE_V4 => {
+                (header.hash_table_size_64, header.block_table_size_64)
+            }
+            _ => (hash_table_entries_size, block_table_entries_size),
+        };
+
+        let hash_table_info = TableInfo::new(
+            hash_table_entries,
+            header.hash_table_offset_64(),
+            hash_table_size,
+            &header.md5_hash_table,
+        );
+
+        let block_table_info = TableInfo::new(
+            block_table_entries,
+            header.block_table_offset_64(),
+            block_table_size,
+            &header.md5_block_table,
+        );
+
+        let hi_block_table_info = if header.hi_block_table_offset != 0 {
+            Some(TableInfo::new(
+                block_table_entries,
+                header.hi_block_table_offset,
+                block_table_entries * 2,
+                &header.md5_hi_block_table,
+            ))
+        } else {
+            None
+        };
+
+        let het_table_info = if header.het_table_offset != 0 {
+            Some(TableInfo::new(
+                0,
+                header.het_table_offset,
+                header.het_table_size_64,
+                &header.md5_het_table,
+            ))
+        } else {
+            None
         };
 
-        let block_table_info = TableInfo {
-            entries: u64::from(header.block_table_entries),
-            offset: u64::from(header.block_table_offset),
-            size: u64::from(header.archive_size - header.block_table_offset),
+        let bet_table_info = if header.bet_table_offset != 0 {
+            Some(TableInfo::new(
+                0,
+                header.bet_table_offset,
+                header.bet_table_size_64,
+                &header.md5_bet_table,
+            ))
+        } else {
+            None
         };
 
-        let archive_size = u64::from(header.archive_size);
         let sector_size = 512 * 2u64.pow(u32::from(header.block_size));
 
         ArchiveInfo {
             hash_table_info,
             block_table_info,
+            hi_block_table_info,
+            het_table_info,
+            bet_table_info,
+            format_version: header.format_version,
             sector_size,
             file_size,
             archive_size,
             header_offset,
+            header: ArchiveHeader::new(header),
         }
     }
 }
@@ -0,0 +1,196 @@
+//! Verification of the "weak" digital signature, a 512-bit RSA signature of the archive's
+//! MD5 hash stored in the `(signature)` file.
+//!
+//! The signature is verified with Blizzard's public key, so the small amount of big number
+//! arithmetic needed for a single public-key operation is implemented here.
+
+const LIMBS: usize = 16;
+
+/// A 512-bit unsigned integer, stored as little-endian 32-bit limbs.
+type Number = [u32; LIMBS];
+
+/// The modulus of Blizzard's weak public key, in big-endian byte order.
+/// The public exponent is 65537.
+const BLIZZARD_WEAK_MODULUS: [u8; 64] = [
+    0x92, 0x62, 0x77, 0x04, 0xBF, 0xB8, 0x82, 0xCC, 0x05, 0x23, 0xB9, 0x0C, 0xB1, 0xAC, 0x04, 0x59,
+    0x27, 0x21, 0x75, 0x96, 0x8D, 0x02, 0x5E, 0xDA, 0x47, 0xDD, 0x7C, 0x49, 0x37, 0x1B, 0xF8, 0xFA,
+    0xEB, 0x0E, 0x0A, 0x92, 0x16, 0x75, 0x57, 0xAD, 0x51, 0xB7, 0x8C, 0xCB, 0x68, 0xC5, 0x42, 0x62,
+    0x90, 0xEE, 0x9F, 0xB1, 0x4B, 0xC1, 0x18, 0xE4, 0x30, 0x34, 0x9E, 0xA4, 0xED, 0x6A, 0xD8, 0x37,
+];
+
+/// Checks a weak signature, as stored in the `(signature)` file in little-endian byte order,
+/// against the MD5 hash of the archive.
+pub(crate) fn verify_weak_signature(signature: &[u8], md5: &[u8; 16]) -> bool {
+    verify_signature(signature, md5, &BLIZZARD_WEAK_MODULUS)
+}
+
+/// Checks a signature against the MD5 hash with the public key of the specified modulus.
+fn verify_signature(signature: &[u8], md5: &[u8; 16], modulus: &[u8; 64]) -> bool {
+    if signature.len() != LIMBS * 4 {
+        return false;
+    }
+
+    let modulus = from_be_bytes(modulus);
+    let signature = from_le_bytes(signature);
+    if compare(&signature, &modulus).is_ge() {
+        return false;
+    }
+
+    let block = to_be_bytes(&pow_65537(&signature, &modulus));
+
+    // the block is padded as 00 01 FF .. FF 00, followed by the hash,
+    // which may be preceded by an encoded algorithm identifier
+    let Some(padding_end) = block[2..].iter().position(|&byte| byte != 0xFF) else {
+        return false;
+    };
+    let data = &block[2 + padding_end..];
+
+    block[..2] == [0x00, 0x01]
+        && padding_end >= 8
+        && data[0] == 0x00
+        && data.len() > md5.len()
+        && data.ends_with(md5)
+}
+
+fn from_be_bytes(bytes: &[u8]) -> Number {
+    let mut number = [0; LIMBS];
+    for (limb, chunk) in number.iter_mut().rev().zip(bytes.chunks_exact(4)) {
+        *limb = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+
+    number
+}
+
+fn from_le_bytes(bytes: &[u8]) -> Number {
+    let mut number = [0; LIMBS];
+    for (limb, chunk) in number.iter_mut().zip(bytes.chunks_exact(4)) {
+        *limb = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+
+    number
+}
+
+fn to_be_bytes(number: &Number) -> [u8; 64] {
+    let mut bytes = [0; 64];
+    for (chunk, limb) in bytes.chunks_exact_mut(4).zip(number.iter().rev()) {
+        chunk.copy_from_slice(&limb.to_be_bytes());
+    }
+
+    bytes
+}
+
+fn compare(a: &Number, b: &Number) -> std::cmp::Ordering {
+    a.iter().rev().cmp(b.iter().rev())
+}
+
+/// Calculates `(a + b) mod modulus`, for `a` and `b` less than `modulus`.
+fn add_mod(a: &Number, b: &Number, modulus: &Number) -> Number {
+    let mut sum = [0; LIMBS];
+    let mut carry = 0u64;
+    for i in 0..LIMBS {
+        let limb = u64::from(a[i]) + u64::from(b[i]) + carry;
+        sum[i] = limb as u32;
+        carry = limb >> 32;
+    }
+
+    if carry != 0 || compare(&sum, modulus).is_ge() {
+        let mut borrow = 0i64;
+        for i in 0..LIMBS {
+            let limb = i64::from(sum[i]) - i64::from(modulus[i]) - borrow;
+            sum[i] = limb as u32;
+            borrow = i64::from(limb < 0);
+        }
+    }
+
+    sum
+}
+
+/// Calculates `(a * b) mod modulus` by doubling and adding, for `a` and `b` less than `modulus`.
+fn mul_mod(a: &Number, b: &Number, modulus: &Number) -> Number {
+    let mut product = [0; LIMBS];
+    for bit in (0..LIMBS * 32).rev() {
+        product = add_mod(&product, &product, modulus);
+        if b[bit / 32] & (1 << (bit % 32)) != 0 {
+            product = add_mod(&product, a, modulus);
+        }
+    }
+
+    product
+}
+
+/// Calculates `base ^ 65537 mod modulus`.
+fn pow_65537(base: &Number, modulus: &Number) -> Number {
+    let mut result = *base;
+    for _ in 0..16 {
+        result = mul_mod(&result, &result, modulus);
+    }
+
+    mul_mod(&result, base, modulus)
+}
+
+#[cfg(test)]
+mod tests {
+    use md5::{Digest, Md5};
+
+    use super::*;
+
+    /// The modulus of a key generated for these tests, since no data signed
+    /// with Blizzard's private key is at hand.
+    const TEST_MODULUS: [u8; 64] = [
+        0xCD, 0x04, 0xEE, 0x8F, 0x93, 0x7F, 0x76, 0x62, 0xAB, 0xCA, 0xBA, 0x80, 0x68, 0x1B, 0x2D,
+        0x39, 0x79, 0xCE, 0x48, 0x95, 0x60, 0xD1, 0x37, 0x19, 0x6E, 0x87, 0xBB, 0xE6, 0xEC, 0x47,
+        0xA1, 0x7B, 0x83, 0x5B, 0x61, 0x51, 0xCD, 0x5D, 0x37, 0xCC, 0x5E, 0xFD, 0x94, 0x09, 0xF3,
+        0x16, 0x29, 0xC0, 0xF4, 0x02, 0xBE, 0x6F, 0xE1, 0x21, 0x87, 0x43, 0x04, 0x2A, 0xEB, 0x94,
+        0x97, 0x86, 0x18, 0x67,
+    ];
+
+    /// The signature of the MD5 hash of `SIGNED_DATA` with the test key,
+    /// in the little-endian byte order of the `(signature)` file.
+    const TEST_SIGNATURE: [u8; 64] = [
+        0x64, 0x0B, 0xEC, 0xF8, 0xD2, 0xE1, 0x85, 0xCB, 0x5B, 0x0F, 0xB2, 0x32, 0xD4, 0x6B, 0xEB,
+        0xD0, 0xD1, 0xAD, 0x0C, 0x73, 0xC6, 0x8A, 0xB1, 0x26, 0x1B, 0x3B, 0xBF, 0xB0, 0xB4, 0x03,
+        0x50, 0x0E, 0x8F, 0xD0, 0x18, 0xB5, 0x7F, 0x6B, 0xBD, 0x82, 0xAA, 0x97, 0xEC, 0x02, 0x7A,
+        0x31, 0x11, 0x36, 0xA1, 0xD9, 0x2E, 0x29, 0xB7, 0x56, 0x67, 0x91, 0x24, 0xB3, 0x17, 0xA5,
+        0x47, 0xE6, 0x4C, 0x19,
+    ];
+
+    const SIGNED_DATA: &[u8] = b"ceres-mpq";
+
+    fn md5(data: &[u8]) -> [u8; 16] {
+        Md5::digest(data).into()
+    }
+
+    #[test]
+    fn accepts_valid_signature() {
+        assert!(verify_signature(
+            &TEST_SIGNATURE,
+            &md5(SIGNED_DATA),
+            &TEST_MODULUS
+        ));
+    }
+
+    #[test]
+    fn rejects_tampered_signatures() {
+        let md5 = md5(SIGNED_DATA);
+
+        let mut tampered = TEST_SIGNATURE;
+        tampered[10] ^= 0x01;
+        assert!(!verify_signature(&tampered, &md5, &TEST_MODULUS));
+
+        // the signature of other data, or made with another key
+        assert!(!verify_signature(
+            &TEST_SIGNATURE,
+            &self::md5(b"ceres-mpp"),
+            &TEST_MODULUS
+        ));
+        assert!(!verify_weak_signature(&TEST_SIGNATURE, &md5));
+
+        // truncated signatures and signatures not less than the modulus
+        assert!(!verify_signature(
+            &TEST_SIGNATURE[..63],
+            &md5,
+            &TEST_MODULUS
+        ));
+        assert!(!verify_signature(&[0xFF; 64], &md5, &TEST_MODULUS));
+    }
+}
@@ -0,0 +1,104 @@
+//! Sparse compression, a simple run-length encoding of zero bytes.
+//!
+//! The data starts with its uncompressed size as a big-endian 32-bit integer, followed by chunks.
+//! A chunk whose header byte has the high bit set contains `(header & 0x7F) + 1` literal bytes,
+//! any other chunk stands for `header + 3` zero bytes.
+
+const LITERAL_FLAG: u8 = 0x80;
+const MAX_LITERAL_RUN: usize = 0x80;
+const MIN_ZERO_RUN: usize = 3;
+const MAX_ZERO_RUN: usize = 0x7F + MIN_ZERO_RUN;
+
+/// Decompresses `input` into `output`, returning the decompressed size.
+///
+/// Returns `None` if the data is corrupted or does not fit into `output`.
+pub(crate) fn decompress(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    let (size, mut input) = input.split_first_chunk::<4>()?;
+    let size = u32::from_be_bytes(*size) as usize;
+    let output = output.get_mut(..size)?;
+
+    let mut position = 0;
+    while position < size {
+        let (&header, rest) = input.split_first()?;
+
+        let length = if header & LITERAL_FLAG != 0 {
+            let length = ((header & !LITERAL_FLAG) as usize + 1).min(size - position);
+            let literals = rest.get(..length)?;
+            output[position..position + length].copy_from_slice(literals);
+            input = &rest[length..];
+
+            length
+        } else {
+            let length = (header as usize + MIN_ZERO_RUN).min(size - position);
+            output[position..position + length].fill(0);
+            input = rest;
+
+            length
+        };
+
+        position += length;
+    }
+
+    Some(size)
+}
+
+/// Compresses `input` into `output`, returning the compressed size,
+/// or `None` if it does not fit.
+pub(crate) fn compress(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    let mut writer = ChunkWriter {
+        output,
+        position: 0,
+    };
+    writer.write(&(input.len() as u32).to_be_bytes())?;
+
+    let mut position = 0;
+    let mut literal_start = 0;
+    while position < input.len() {
+        let zeros = input[position..]
+            .iter()
+            .take(MAX_ZERO_RUN)
+            .take_while(|&&byte| byte == 0)
+            .count();
+
+        if zeros < MIN_ZERO_RUN {
+            position += 1;
+            continue;
+        }
+
+        writer.write_literals(&input[literal_start..position])?;
+        writer.write(&[(zeros - MIN_ZERO_RUN) as u8])?;
+        position += zeros;
+        literal_start = position;
+    }
+
+    writer.write_literals(&input[literal_start..])?;
+
+    Some(writer.position)
+}
+
+/// Writes chunks into a byte buffer, refusing to write past its end.
+struct ChunkWriter<'a> {
+    output: &'a mut [u8],
+    position: usize,
+}
+
+impl<'a> ChunkWriter<'a> {
+    fn write(&mut self, data: &[u8]) -> Option<()> {
+        let end = self.position + data.len();
+        self.output
+            .get_mut(self.position..end)?
+            .copy_from_slice(data);
+        self.position = end;
+
+        Some(())
+    }
+
+    fn write_literals(&mut self, literals: &[u8]) -> Option<()> {
+        for chunk in literals.chunks(MAX_LITERAL_RUN) {
+            self.write(&[LITERAL_FLAG | (chunk.len() - 1) as u8])?;
+            self.write(chunk)?;
+        }
+
+        Some(())
+    }
+}
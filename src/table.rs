@@ -1,16 +1,65 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::io::Error as IoError;
 use std::io::{Read, Seek, Write};
+use std::sync::Mutex;
 
 use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 
 use super::consts::*;
 use super::error::Error;
+use super::explode;
+use super::het::*;
+use super::names::NameHash;
 use super::seeker::*;
 use super::util::*;
 
+/// Reads the hash and block tables of an archive. If the archive has no hash table,
+/// they are substituted by its HET and BET tables, if any.
+pub(crate) fn read_tables<R>(
+    seeker: &mut Seeker<R>,
+) -> Result<(FileHashTable, FileBlockTable), Error>
+where
+    R: Read + Seek,
+{
+    let mut hash_table = FileHashTable::from_seeker(seeker)?;
+    let mut block_table = FileBlockTable::from_seeker(seeker)?;
+
+    let info = seeker.info();
+    if let (true, Some(het_info), Some(bet_info)) = (
+        hash_table.entries.is_empty(),
+        info.het_table_info,
+        info.bet_table_info,
+    ) {
+        let bet_table = BetTable::from_seeker(seeker, &bet_info)?;
+        hash_table.het_table = Some(HetTable::from_seeker(
+            seeker,
+            &het_info,
+            bet_table.name_hashes,
+        )?);
+
+        if block_table.entries.is_empty() {
+            block_table.entries = bet_table.entries;
+        }
+    }
+
+    Ok((hash_table, block_table))
+}
+
+/// The number of names the lookup cache remembers, so that looking up arbitrary names
+/// cannot grow it indefinitely.
+const LOOKUP_CACHE_CAPACITY: usize = 64 * 1024;
+
+/// The hash table entries found for looked up names.
+type LookupCache = HashMap<String, Vec<HashEntry>>;
+
 #[derive(Debug)]
 pub(crate) struct FileHashTable {
     entries: Vec<HashEntry>,
+    // used to look up files in archives without a hash table
+    het_table: Option<HetTable>,
+    // the entries found for previously looked up names, without and with case sensitivity
+    lookup_cache: Option<Mutex<[LookupCache; 2]>>,
 }
 
 impl FileHashTable {
@@ -21,6 +70,7 @@ impl FileHashTable {
         let info = seeker.info().hash_table_info;
         let expected_size = info.entries * u64::from(HASH_TABLE_ENTRY_SIZE);
         let raw_data = seeker.read(info.offset, info.size)?;
+        info.verify(&raw_data, "hash table")?;
         let decoded_data = decode_mpq_block(&raw_data, expected_size, Some(HASH_TABLE_KEY))?;
 
         let mut entries = Vec::with_capacity(info.entries as usize);
@@ -29,14 +79,97 @@ impl FileHashTable {
             entries.push(HashEntry::from_reader(&mut slice)?);
         }
 
-        Ok(FileHashTable { entries })
+        Ok(FileHashTable {
+            entries,
+            het_table: None,
+            lookup_cache: None,
+        })
+    }
+
+    /// Remembers the entries found for every looked up name, so that repeated lookups
+    /// do not have to hash the name again.
+    pub fn enable_lookup_cache(&mut self) {
+        self.lookup_cache = Some(Mutex::default());
+    }
+
+    pub fn find_entry(&self, name: &str, case_sensitive: bool) -> Option<HashEntry> {
+        self.find_entries(name, case_sensitive)
+            .into_iter()
+            .find(|entry| entry.locale == 0)
+    }
+
+    /// Finds the hash entry of a name with the neutral locale and the specified platform.
+    pub fn find_entry_with_platform(
+        &self,
+        name: &str,
+        case_sensitive: bool,
+        platform: u16,
+    ) -> Option<HashEntry> {
+        self.find_entries(name, case_sensitive)
+            .into_iter()
+            .find(|entry| entry.locale == 0 && entry.platform == platform)
+    }
+
+    /// Finds all hash entries matching the specified name, regardless of their locale or platform.
+    ///
+    /// If `case_sensitive` is set, the name is hashed without converting it to uppercase first.
+    /// Names looked up in the HET table are always matched case-insensitively.
+    pub fn find_entries(&self, name: &str, case_sensitive: bool) -> Vec<HashEntry> {
+        let cache = match &self.lookup_cache {
+            Some(cache) => cache,
+            None => return self.lookup_entries(name, case_sensitive),
+        };
+
+        let mut cache = cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let cache = &mut cache[case_sensitive as usize];
+        if let Some(found) = cache.get(name) {
+            return found.clone();
+        }
+
+        let found = self.lookup_entries(name, case_sensitive);
+        if cache.len() < LOOKUP_CACHE_CAPACITY {
+            cache.insert(name.to_string(), found.clone());
+        }
+
+        found
+    }
+
+    fn lookup_entries(&self, name: &str, case_sensitive: bool) -> Vec<HashEntry> {
+        let hash = if case_sensitive {
+            hash_string_case_sensitive
+        } else {
+            hash_string
+        };
+
+        let name_hash = NameHash {
+            index: hash(name.as_bytes(), MPQ_HASH_TABLE_INDEX),
+            hash_a: hash(name.as_bytes(), MPQ_HASH_NAME_A),
+            hash_b: hash(name.as_bytes(), MPQ_HASH_NAME_B),
+        };
+
+        self.find_entries_hashed(name, name_hash)
     }
 
-    pub fn find_entry(&self, name: &str) -> Option<&HashEntry> {
+    /// Finds all hash entries of a name whose hashes were already calculated.
+    /// The name itself is only needed to look it up in the HET table.
+    pub fn find_entries_hashed(&self, name: &str, name_hash: NameHash) -> Vec<HashEntry> {
+        let mut found = Vec::new();
+        let part_a = name_hash.hash_a;
+        let part_b = name_hash.hash_b;
+
+        if self.entries.is_empty() {
+            // HET entries have no locale or platform
+            if let Some(block_index) = self.het_table.as_ref().and_then(|het| het.find(name)) {
+                found.push(HashEntry::new(part_a, part_b, 0, 0, block_index as u32));
+            }
+
+            return found;
+        }
+
         let hash_mask = self.entries.len() - 1;
-        let part_a = hash_string(name.as_bytes(), MPQ_HASH_NAME_A);
-        let part_b = hash_string(name.as_bytes(), MPQ_HASH_NAME_B);
-        let index = hash_string(name.as_bytes(), MPQ_HASH_TABLE_INDEX) as usize;
+        let index = name_hash.index as usize;
 
         let start_index = index & hash_mask;
         let mut index = start_index;
@@ -48,8 +181,8 @@ impl FileHashTable {
                 break;
             }
 
-            if inspected.hash_a == part_a && inspected.hash_b == part_b && inspected.locale == 0 {
-                return Some(inspected);
+            if inspected.hash_a == part_a && inspected.hash_b == part_b {
+                found.push(*inspected);
             }
 
             index = (index + 1) & hash_mask;
@@ -58,7 +191,11 @@ impl FileHashTable {
             }
         }
 
-        None
+        found
+    }
+
+    pub fn entries(&self) -> &[HashEntry] {
+        &self.entries
     }
 }
 
@@ -72,12 +209,18 @@ pub(crate) struct HashEntry {
 }
 
 impl HashEntry {
-    pub fn new(hash_a: u32, hash_b: u32, block_index: u32) -> HashEntry {
+    pub fn new(
+        hash_a: u32,
+        hash_b: u32,
+        locale: u16,
+        platform: u16,
+        block_index: u32,
+    ) -> HashEntry {
         HashEntry {
             hash_a,
             hash_b,
-            locale: 0,
-            platform: 0,
+            locale,
+            platform,
             block_index,
         }
     }
@@ -109,7 +252,13 @@ impl HashEntry {
     }
 
     pub fn is_blank(&self) -> bool {
-        self.block_index == 0xFFFF_FFFF
+        self.block_index == HASH_TABLE_EMPTY_ENTRY
+    }
+
+    /// Whether a new entry can be stored in place of this entry,
+    /// i.e. it is blank or was deleted.
+    pub fn is_free(&self) -> bool {
+        self.block_index == HASH_TABLE_EMPTY_ENTRY || self.block_index == HASH_TABLE_DELETED_ENTRY
     }
 
     pub fn write<W: Write>(&self, mut writer: W) -> Result<(), IoError> {
@@ -136,6 +285,7 @@ impl FileBlockTable {
         let info = seeker.info().block_table_info;
         let expected_size = info.entries * u64::from(BLOCK_TABLE_ENTRY_SIZE);
         let raw_data = seeker.read(info.offset, info.size)?;
+        info.verify(&raw_data, "block table")?;
         let decoded_data = decode_mpq_block(&raw_data, expected_size, Some(BLOCK_TABLE_KEY))?;
 
         let mut entries = Vec::with_capacity(info.entries as usize);
@@ -144,15 +294,29 @@ impl FileBlockTable {
             entries.push(BlockEntry::from_reader(&mut slice)?);
         }
 
+        // the hi-block table holds the upper 16 bits of each file position
+        if let Some(hi_info) = seeker.info().hi_block_table_info {
+            let raw_data = seeker.read(hi_info.offset, hi_info.size)?;
+            hi_info.verify(&raw_data, "hi-block table")?;
+            let mut slice = &raw_data[..];
+            for entry in &mut entries {
+                entry.file_pos |= u64::from(slice.read_u16::<LE>()?) << 32;
+            }
+        }
+
         Ok(FileBlockTable { entries })
     }
 
     pub fn get(&self, index: usize) -> Option<&BlockEntry> {
         self.entries.get(index)
     }
+
+    pub fn entries(&self) -> &[BlockEntry] {
+        &self.entries
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct BlockEntry {
     pub file_pos: u64,
     pub compressed_size: u64,
@@ -193,7 +357,7 @@ impl BlockEntry {
         writer.write_u32::<LE>(self.file_pos as u32)?;
         writer.write_u32::<LE>(self.compressed_size as u32)?;
         writer.write_u32::<LE>(self.uncompressed_size as u32)?;
-        writer.write_u32::<LE>(self.flags as u32)?;
+        writer.write_u32::<LE>(self.flags)?;
 
         Ok(())
     }
@@ -213,11 +377,139 @@ impl BlockEntry {
     pub fn is_key_adjusted(&self) -> bool {
         (self.flags & MPQ_FILE_ADJUST_KEY) != 0
     }
+
+    pub fn is_single_unit(&self) -> bool {
+        (self.flags & MPQ_FILE_SINGLE_UNIT) != 0
+    }
+
+    /// Whether the file has a sector with checksums of all other sectors.
+    /// Only files with a sector offset table can have one.
+    pub fn has_sector_checksums(&self) -> bool {
+        (self.flags & MPQ_FILE_SECTOR_CRC) != 0
+            && (self.is_compressed() || self.is_imploded())
+            && !self.is_single_unit()
+    }
+
+    /// The size of the sectors of this file. Single-unit files consist of one sector
+    /// spanning the whole file, regardless of the archive's sector size.
+    pub fn sector_size(&self, archive_sector_size: u64) -> u64 {
+        if self.is_single_unit() {
+            self.uncompressed_size.max(1)
+        } else {
+            archive_sector_size
+        }
+    }
+
+    /// Re-encrypts the stored data of this file from `old_key` to `new_key`,
+    /// e.g. after a file whose key is adjusted by its position was moved.
+    /// If `old_key` is `None`, the data is not encrypted yet.
+    pub fn reencrypt(
+        &self,
+        stored: &[u8],
+        archive_sector_size: u64,
+        old_key: Option<u32>,
+        new_key: u32,
+    ) -> Result<Vec<u8>, Error> {
+        let mut data = stored.to_vec();
+        let sector_size = self.sector_size(archive_sector_size);
+        let sector_count = sector_count_from_size(self.uncompressed_size, sector_size) as usize;
+
+        let sectors: Vec<(usize, usize)> = if self.is_single_unit() {
+            vec![(0, data.len())]
+        } else if self.is_compressed() || self.is_imploded() {
+            let entry_count = if self.has_sector_checksums() {
+                sector_count + 2
+            } else {
+                sector_count + 1
+            };
+
+            let mut table = data
+                .get(..entry_count * 4)
+                .ok_or(Error::Corrupted)?
+                .to_vec();
+            if let Some(old_key) = old_key {
+                decrypt_mpq_block(&mut table, old_key.wrapping_sub(1));
+            }
+            let offsets: Vec<usize> = table
+                .chunks_exact(4)
+                .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+                .collect();
+            encrypt_mpq_block(&mut table, new_key.wrapping_sub(1));
+            data[..table.len()].copy_from_slice(&table);
+
+            // the checksum sector is never encrypted
+            offsets[..=sector_count]
+                .windows(2)
+                .map(|window| (window[0], window[1]))
+                .collect()
+        } else {
+            (0..sector_count)
+                .map(|i| {
+                    (
+                        i * sector_size as usize,
+                        ((i + 1) * sector_size as usize).min(data.len()),
+                    )
+                })
+                .collect()
+        };
+
+        for (i, (start, end)) in sectors.into_iter().enumerate() {
+            // decrypting requires an aligned buffer
+            let mut sector = data.get(start..end).ok_or(Error::Corrupted)?.to_vec();
+            if let Some(old_key) = old_key {
+                decrypt_mpq_block(&mut sector, old_key.wrapping_add(i as u32));
+            }
+            encrypt_mpq_block(&mut sector, new_key.wrapping_add(i as u32));
+            data[start..end].copy_from_slice(&sector);
+        }
+
+        Ok(data)
+    }
+
+    /// Decodes an already decrypted sector of this file,
+    /// appending at most `uncompressed_size` bytes to `output`.
+    pub fn decode_decrypted_sector_append(
+        &self,
+        input: &[u8],
+        uncompressed_size: u64,
+        output: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        if !self.is_imploded() {
+            return decompress_sector_append(input, uncompressed_size, output);
+        }
+
+        if input.len() as u64 >= uncompressed_size {
+            output.extend_from_slice(&input[..uncompressed_size as usize]);
+        } else {
+            let start = output.len();
+            output.resize(start + uncompressed_size as usize, 0);
+            let size = explode::explode(input, &mut output[start..]).ok_or(Error::Corrupted)?;
+            output.truncate(start + size);
+        }
+
+        Ok(())
+    }
+
+    /// Decodes a sector of this file, exploding it if the file is imploded.
+    pub fn decode_sector<'a>(
+        &self,
+        input: &'a [u8],
+        uncompressed_size: u64,
+        encryption_key: Option<u32>,
+    ) -> Result<Cow<'a, [u8]>, Error> {
+        if self.is_imploded() {
+            explode_mpq_block(input, uncompressed_size, encryption_key)
+        } else {
+            decode_mpq_block(input, uncompressed_size, encryption_key)
+        }
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct SectorOffsets {
     offsets: Vec<u32>,
+    // the offset and size of the sector holding the checksums of all other sectors
+    checksums: Option<(u32, u32)>,
 }
 
 impl SectorOffsets {
@@ -229,21 +521,62 @@ impl SectorOffsets {
     where
         R: Read + Seek,
     {
-        let sector_count =
-            sector_count_from_size(block_entry.uncompressed_size, seeker.info().sector_size);
-        let mut raw_data = seeker.read(block_entry.file_pos, (sector_count + 1) * 4)?;
+        // single-unit files have no sector offset table,
+        // and are stored as a single block
+        if block_entry.is_single_unit() {
+            let offsets = vec![0, block_entry.compressed_size as u32];
+
+            return Ok(SectorOffsets {
+                offsets,
+                checksums: None,
+            });
+        }
+
+        let sector_size = seeker.info().sector_size;
+        let sector_count = sector_count_from_size(block_entry.uncompressed_size, sector_size);
+
+        // uncompressed files have no sector offset table,
+        // and all of their sectors are exactly one archive sector in size
+        if !block_entry.is_compressed() && !block_entry.is_imploded() {
+            let offsets = (0..=sector_count)
+                .map(|i| (i * sector_size).min(block_entry.uncompressed_size) as u32)
+                .collect();
+
+            return Ok(SectorOffsets {
+                offsets,
+                checksums: None,
+            });
+        }
+
+        // the checksum sector has an additional entry in the table
+        let entry_count = if block_entry.has_sector_checksums() {
+            sector_count + 2
+        } else {
+            sector_count + 1
+        };
+
+        let mut raw_data = seeker.read(block_entry.file_pos, entry_count * 4)?;
 
         if let Some(encryption_key) = encryption_key {
             decrypt_mpq_block(&mut raw_data, encryption_key);
         }
 
         let mut slice = &raw_data[..];
-        let mut offsets = vec![0u32; (sector_count + 1) as usize];
-        for i in 0..=sector_count {
-            offsets[i as usize] = slice.read_u32::<LE>()?;
+        let mut offsets = vec![0u32; entry_count as usize];
+        for offset in &mut offsets {
+            *offset = slice.read_u32::<LE>()?;
         }
 
-        Ok(SectorOffsets { offsets })
+        let checksums = if block_entry.has_sector_checksums() {
+            let end = offsets.pop().ok_or(Error::Corrupted)?;
+            let start = *offsets.last().ok_or(Error::Corrupted)?;
+
+            Some((start, end.checked_sub(start).ok_or(Error::Corrupted)?))
+        } else {
+            None
+        };
+
+        Ok(SectorOffsets { offsets, checksums })
     }
 
     pub fn one(&self, index: usize) -> Option<(u32, u32)> {
@@ -266,4 +599,34 @@ impl SectorOffsets {
     pub fn count(&self) -> usize {
         self.offsets.len() - 1
     }
+
+    pub fn offsets(&self) -> &[u32] {
+        &self.offsets
+    }
+
+    /// Reads the checksums of the file's sectors, if it has a checksum sector.
+    /// The checksum sector is never encrypted, but may be compressed.
+    pub fn read_checksums<R>(
+        &self,
+        seeker: &mut Seeker<R>,
+        block_entry: &BlockEntry,
+    ) -> Result<Option<Vec<u32>>, Error>
+    where
+        R: Read + Seek,
+    {
+        let (offset, size) = match self.checksums {
+            Some((_, 0)) | None => return Ok(None),
+            Some(checksums) => checksums,
+        };
+
+        let raw_data = seeker.read(block_entry.file_pos + u64::from(offset), u64::from(size))?;
+        let decoded = decode_mpq_block(&raw_data, self.count() as u64 * 4, None)?;
+
+        let checksums = decoded
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+
+        Ok(Some(checksums))
+    }
 }
@@ -0,0 +1,164 @@
+use std::cmp::Reverse;
+
+use super::archive::FileInfo;
+
+#[derive(Debug, Clone)]
+/// A directory in the tree of file names built by
+/// [directory_tree](struct.Archive.html#method.directory_tree).
+///
+/// Directories are implied by the backslash-separated names of files, since MPQ has no
+/// notion of directories itself. Names are compared case-insensitively, like in MPQ lookups,
+/// and entries are sorted by name.
+pub struct Directory {
+    name: String,
+    directories: Vec<Directory>,
+    files: Vec<TreeFile>,
+    size: u64,
+    compressed_size: u64,
+    file_count: usize,
+}
+
+#[derive(Debug, Clone)]
+/// A file in a [Directory](struct.Directory.html).
+pub struct TreeFile {
+    name: String,
+    path: String,
+    info: FileInfo,
+}
+
+impl Directory {
+    pub(crate) fn new(name: &str) -> Directory {
+        Directory {
+            name: name.to_string(),
+            directories: Vec::new(),
+            files: Vec::new(),
+            size: 0,
+            compressed_size: 0,
+            file_count: 0,
+        }
+    }
+
+    /// Adds a file to the tree, creating all directories along its path.
+    pub(crate) fn insert(&mut self, path: &str, info: FileInfo) {
+        let mut components = path.split('\\').filter(|component| !component.is_empty());
+        let name = match components.next_back() {
+            Some(name) => name,
+            None => return,
+        };
+
+        let mut directory = self;
+        for component in components {
+            directory.add_totals(&info);
+
+            let index = match directory
+                .directories
+                .iter()
+                .position(|child| child.name.eq_ignore_ascii_case(component))
+            {
+                Some(index) => index,
+                None => {
+                    directory.directories.push(Directory::new(component));
+                    directory.directories.len() - 1
+                }
+            };
+
+            directory = &mut directory.directories[index];
+        }
+
+        directory.add_totals(&info);
+        directory.files.push(TreeFile {
+            name: name.to_string(),
+            path: path.to_string(),
+            info,
+        });
+    }
+
+    /// Sorts all entries of this directory and its subdirectories by name.
+    pub(crate) fn sort(&mut self) {
+        self.directories
+            .sort_by_key(|directory| directory.name.to_ascii_uppercase());
+        self.files
+            .sort_by_key(|file| file.name.to_ascii_uppercase());
+
+        for directory in &mut self.directories {
+            directory.sort();
+        }
+    }
+
+    fn add_totals(&mut self, info: &FileInfo) {
+        self.size += info.uncompressed_size();
+        self.compressed_size += info.compressed_size();
+        self.file_count += 1;
+    }
+
+    /// The name of the directory, without its parent directories.
+    /// Empty for the root directory.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The immediate subdirectories of this directory.
+    pub fn directories(&self) -> &[Directory] {
+        &self.directories
+    }
+
+    /// The files immediately inside of this directory.
+    pub fn files(&self) -> &[TreeFile] {
+        &self.files
+    }
+
+    /// The total uncompressed size of all files in this directory and its subdirectories.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The total stored size of all files in this directory and its subdirectories.
+    pub fn compressed_size(&self) -> u64 {
+        self.compressed_size
+    }
+
+    /// The number of files in this directory and its subdirectories.
+    pub fn file_count(&self) -> usize {
+        self.file_count
+    }
+
+    /// Returns up to `count` files in this directory and its subdirectories
+    /// with the largest stored size, largest first.
+    ///
+    /// Together with the sizes of the root's [directories](#method.directories),
+    /// this shows which parts of an archive take up the most space.
+    pub fn largest_files(&self, count: usize) -> Vec<&TreeFile> {
+        let mut files = Vec::with_capacity(self.file_count);
+        self.collect_files(&mut files);
+
+        files.sort_by_key(|file| Reverse(file.info.compressed_size()));
+        files.truncate(count);
+
+        files
+    }
+
+    fn collect_files<'a>(&'a self, files: &mut Vec<&'a TreeFile>) {
+        files.extend(self.files.iter());
+
+        for directory in &self.directories {
+            directory.collect_files(files);
+        }
+    }
+}
+
+impl TreeFile {
+    /// The name of the file, without its directories.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The full name of the file in the archive.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Metadata of the file.
+    pub fn info(&self) -> &FileInfo {
+        &self.info
+    }
+}
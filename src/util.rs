@@ -1,11 +1,16 @@
 use std::borrow::Cow;
-
-use byte_slice_cast::AsMutSliceOf;
+use std::io::Read;
 
 use lazy_static::lazy_static;
 
+use super::adpcm;
 use super::consts::*;
+use super::creator::Compression;
 use super::error::*;
+use super::explode;
+use super::huffman;
+use super::lzma;
+use super::sparse;
 
 lazy_static! {
     static ref CRYPTO_TABLE: [u32; 0x500] = generate_crypto_table();
@@ -56,28 +61,106 @@ pub fn hash_string(source: &[u8], hash_type: u32) -> u32 {
     hash_string_with_table(source, hash_type, &ASCII_UPPER_LOOKUP_SLASH_SENSITIVE)
 }
 
+/// Same as `hash_string`, but does not convert the name to uppercase,
+/// so that names differing only in case produce different hashes.
+pub fn hash_string_case_sensitive(source: &[u8], hash_type: u32) -> u32 {
+    hash_string_with_table(source, hash_type, &ASCII_IDENTITY_LOOKUP)
+}
+
+/// Calculates the 64-bit Jenkins hash of a name, as used by HET tables.
+/// Names are converted to lowercase and forward slashes to backslashes first.
+pub fn hash_string_jenkins(source: &[u8]) -> u64 {
+    let normalized: Vec<u8> = source
+        .iter()
+        .map(|&byte| match byte {
+            b'/' => b'\\',
+            byte => byte.to_ascii_lowercase(),
+        })
+        .collect();
+
+    // StormLib passes the secondary hash as `pc` and the primary hash as `pb`
+    let (secondary, primary) = hashlittle2(&normalized, 2, 1);
+
+    (u64::from(primary) << 32) | u64::from(secondary)
+}
+
+/// Bob Jenkins' `hashlittle2` from `lookup3.c`, which hashes `key` with the initial values
+/// `pc` and `pb`, and returns the resulting `(pc, pb)`.
+fn hashlittle2(key: &[u8], pc: u32, pb: u32) -> (u32, u32) {
+    let mut a = 0xDEAD_BEEFu32
+        .wrapping_add(key.len() as u32)
+        .wrapping_add(pc);
+    let mut b = a;
+    let mut c = a.wrapping_add(pb);
+
+    let word = |bytes: &[u8]| {
+        let mut word = [0u8; 4];
+        word[..bytes.len()].copy_from_slice(bytes);
+        u32::from_le_bytes(word)
+    };
+
+    let mut rest = key;
+    while rest.len() > 12 {
+        a = a.wrapping_add(word(&rest[0..4]));
+        b = b.wrapping_add(word(&rest[4..8]));
+        c = c.wrapping_add(word(&rest[8..12]));
+
+        a = a.wrapping_sub(c) ^ c.rotate_left(4);
+        c = c.wrapping_add(b);
+        b = b.wrapping_sub(a) ^ a.rotate_left(6);
+        a = a.wrapping_add(c);
+        c = c.wrapping_sub(b) ^ b.rotate_left(8);
+        b = b.wrapping_add(a);
+        a = a.wrapping_sub(c) ^ c.rotate_left(16);
+        c = c.wrapping_add(b);
+        b = b.wrapping_sub(a) ^ a.rotate_left(19);
+        a = a.wrapping_add(c);
+        c = c.wrapping_sub(b) ^ b.rotate_left(4);
+        b = b.wrapping_add(a);
+
+        rest = &rest[12..];
+    }
+
+    if rest.is_empty() {
+        return (c, b);
+    }
+
+    // the last block is padded with zeros
+    let mut last = [0u8; 12];
+    last[..rest.len()].copy_from_slice(rest);
+    a = a.wrapping_add(word(&last[0..4]));
+    b = b.wrapping_add(word(&last[4..8]));
+    c = c.wrapping_add(word(&last[8..12]));
+
+    c = (c ^ b).wrapping_sub(b.rotate_left(14));
+    a = (a ^ c).wrapping_sub(c.rotate_left(11));
+    b = (b ^ a).wrapping_sub(a.rotate_left(25));
+    c = (c ^ b).wrapping_sub(b.rotate_left(16));
+    a = (a ^ c).wrapping_sub(c.rotate_left(4));
+    b = (b ^ a).wrapping_sub(a.rotate_left(14));
+    c = (c ^ b).wrapping_sub(b.rotate_left(24));
+
+    (c, b)
+}
+
 //pub fn hash_string_slash_sensitive(source: &[u8], hash_type: u32) -> u32 {
 //    hash_string_with_table(source, hash_type, &ASCII_UPPER_LOOKUP_SLASH_SENSITIVE)
 //}
 
 pub fn decrypt_mpq_block(data: &mut [u8], mut key: u32) {
-    let iterations = data.len() >> 2;
-
     let mut key_secondary: u32 = 0xEEEE_EEEE;
     let mut temp: u32;
 
-    // if the buffer is not aligned to u32s we need to truncate it
-    // this is ok because the last bytes that don't fit into the
-    // aligned slice are not encrypted
-    let u32_data = &mut data[..iterations * 4].as_mut_slice_of::<u32>().unwrap();
-
-    for i in 0..iterations {
+    // the last bytes that don't fill a whole u32 are not encrypted.
+    // the data is read in chunks, since sectors decrypted in place need not be aligned to u32s
+    for chunk in data.chunks_exact_mut(4) {
         key_secondary = key_secondary
             .overflowing_add(CRYPTO_TABLE[(MPQ_HASH_KEY2_MIX + (key & 0xFF)) as usize])
             .0;
 
-        u32_data[i] ^= key.overflowing_add(key_secondary).0;
-        temp = u32_data[i];
+        temp = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])
+            ^ key.overflowing_add(key_secondary).0;
+        chunk.copy_from_slice(&temp.to_le_bytes());
 
         key = ((!key << 0x15).overflowing_add(0x1111_1111).0) | (key >> 0x0B);
         key_secondary = temp
@@ -91,23 +174,19 @@ pub fn decrypt_mpq_block(data: &mut [u8], mut key: u32) {
 }
 
 pub fn encrypt_mpq_block(data: &mut [u8], mut key: u32) {
-    let iterations = data.len() >> 2;
-
     let mut key_secondary: u32 = 0xEEEE_EEEE;
     let mut temp: u32;
 
-    // if the buffer is not aligned to u32s we need to truncate it
-    // this is ok because the last bytes that don't fit into the
-    // aligned slice are not encrypted
-    let u32_data = &mut data[..iterations * 4].as_mut_slice_of::<u32>().unwrap();
-
-    for i in 0..iterations {
+    // the last bytes that don't fill a whole u32 are not encrypted.
+    // the data is read in chunks, since sectors encrypted in place need not be aligned to u32s
+    for chunk in data.chunks_exact_mut(4) {
         key_secondary = key_secondary
             .overflowing_add(CRYPTO_TABLE[(MPQ_HASH_KEY2_MIX + (key & 0xFF)) as usize])
             .0;
 
-        temp = u32_data[i];
-        u32_data[i] ^= key.overflowing_add(key_secondary).0;
+        temp = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let value = temp ^ key.overflowing_add(key_secondary).0;
+        chunk.copy_from_slice(&value.to_le_bytes());
 
         key = ((!key << 0x15).overflowing_add(0x1111_1111).0) | (key >> 0x0B);
         key_secondary = temp
@@ -140,28 +219,32 @@ pub fn calculate_file_key(
     adjusted: bool,
 ) -> u32 {
     let plain_name = get_plain_name(file_name);
-    let mut key = hash_string(plain_name, MPQ_HASH_FILE_KEY);
+    let key_hash = hash_string(plain_name, MPQ_HASH_FILE_KEY);
 
+    file_key_from_hash(key_hash, file_offset, file_size, adjusted)
+}
+
+/// Derives the encryption key of a file from the hash of its name without directories.
+pub fn file_key_from_hash(key_hash: u32, file_offset: u32, file_size: u32, adjusted: bool) -> u32 {
     if adjusted {
-        key = (key + file_offset) ^ file_size
+        (key_hash + file_offset) ^ file_size
+    } else {
+        key_hash
     }
-
-    key
 }
 
 /// This will try to perform the following two operations:
 /// 1) If `encryption_key` is specified, it will decrypt the block using
-/// that encryption key.
+///    that encryption key.
 /// 2) If `input.len()` < `uncompressed_size`, it will try to decompress
-/// the block. MPQ supports multiple compression types, and the compression
-/// type used for a particular block is specified in the first byte of the block
-/// as a set of bitflags.
-// TODO: Add support for IMA ADCPM Mono/Stereo
+///    the block. MPQ supports multiple compression types, and the compression
+///    type used for a particular block is specified in the first byte of the block
+///    as a set of bitflags.
 pub fn decode_mpq_block(
     input: &[u8],
     uncompressed_size: u64,
     encryption_key: Option<u32>,
-) -> Result<Cow<[u8]>, Error> {
+) -> Result<Cow<'_, [u8]>, Error> {
     let compressed_size = input.len() as u64;
     let mut buf = Cow::Borrowed(input);
 
@@ -170,59 +253,93 @@ pub fn decode_mpq_block(
     }
 
     if compressed_size < uncompressed_size {
-        let compression_type = buf[0];
+        let compression_type = *buf.first().ok_or(Error::Corrupted)?;
 
-        if compression_type & COMPRESSION_IMA_ADPCM_MONO_MONO != 0 {
-            return Err(Error::UnsupportedCompression {
-                kind: "IMA ADCPM Mono".to_string(),
-            });
-        }
+        // LZMA cannot be combined with other compressions, and its value overlaps their flags
+        if compression_type == COMPRESSION_LZMA {
+            let mut decompressed = vec![0u8; uncompressed_size as usize];
+            let size = lzma::decompress(&buf[1..], &mut decompressed).ok_or(Error::Corrupted)?;
 
-        if compression_type & COMPRESSION_IMA_ADPCM_MONO_STEREO != 0 {
-            return Err(Error::UnsupportedCompression {
-                kind: "IMA ADCPM Stereo".to_string(),
-            });
+            decompressed.truncate(size);
+            return Ok(Cow::Owned(decompressed));
         }
 
-        if compression_type & COMPRESSION_HUFFMAN != 0 {
-            return Err(Error::UnsupportedCompression {
-                kind: "Huffman".to_string(),
-            });
+        check_compression_supported(compression_type)?;
+
+        if compression_type & COMPRESSION_BZIP2 != 0 {
+            let mut decompressed = Vec::new();
+            bunzip_append(&buf[1..], uncompressed_size, &mut decompressed)?;
+
+            buf = Cow::Owned(decompressed);
         }
 
         if compression_type & COMPRESSION_PKWARE != 0 {
-            return Err(Error::UnsupportedCompression {
-                kind: "PKWare DCL".to_string(),
-            });
+            // the compression type byte is still in front of the data unless bzip2 removed it
+            let data = if compression_type & COMPRESSION_BZIP2 == 0 {
+                &buf[1..]
+            } else {
+                &buf[..]
+            };
+
+            let mut decompressed = vec![0u8; uncompressed_size as usize];
+            let size = explode::explode(data, &mut decompressed).ok_or(Error::Corrupted)?;
+
+            decompressed.truncate(size);
+            buf = Cow::Owned(decompressed);
         }
 
-        if compression_type & COMPRESSION_BZIP2 != 0 {
+        if compression_type & COMPRESSION_ZLIB != 0 {
+            let mut decompressed = Vec::new();
+            inflate_append(&buf[1..], uncompressed_size, &mut decompressed)?;
+
+            buf = Cow::Owned(decompressed);
+        }
+
+        if compression_type & COMPRESSION_SPARSE != 0 {
+            // the compression type byte only precedes the data if no other compression was applied
+            let data = if compression_type & !COMPRESSION_SPARSE == 0 {
+                &buf[1..]
+            } else {
+                &buf[..]
+            };
+
             let mut decompressed = vec![0u8; uncompressed_size as usize];
-            let mut decompressor = bzip2::Decompress::new(false);
-            let status = decompressor.decompress(&buf[1..], &mut decompressed);
+            let size = sparse::decompress(data, &mut decompressed).ok_or(Error::Corrupted)?;
 
-            if !(status.is_ok() && status.unwrap() == bzip2::Status::Ok) {
-                return Err(Error::Corrupted);
-            }
+            decompressed.truncate(size);
+            buf = Cow::Owned(decompressed);
+        }
 
-            decompressed.resize(decompressor.total_out() as usize, 0);
+        if compression_type & COMPRESSION_HUFFMAN != 0 {
+            // the compression type byte only precedes the data if no other compression was applied
+            let applied =
+                COMPRESSION_BZIP2 | COMPRESSION_PKWARE | COMPRESSION_ZLIB | COMPRESSION_SPARSE;
+            let data = if compression_type & applied == 0 {
+                &buf[1..]
+            } else {
+                &buf[..]
+            };
+
+            let mut decompressed = vec![0u8; uncompressed_size as usize];
+            let size = huffman::decompress(data, &mut decompressed).ok_or(Error::Corrupted)?;
+
+            decompressed.truncate(size);
             buf = Cow::Owned(decompressed);
         }
 
-        if compression_type & COMPRESSION_ZLIB != 0 {
+        if let Some(channels) = adpcm_channels(compression_type) {
+            // the compression type byte only precedes the data if no other compression was applied
+            let data = if compression_type & !ADPCM_COMPRESSIONS == 0 {
+                &buf[1..]
+            } else {
+                &buf[..]
+            };
+
             let mut decompressed = vec![0u8; uncompressed_size as usize];
-            let mut decompressor = flate2::Decompress::new(true);
-            let status = decompressor.decompress(
-                &buf[1..],
-                &mut decompressed,
-                flate2::FlushDecompress::Finish,
-            );
-
-            if !(status.is_ok() && status.unwrap() != flate2::Status::BufError) {
-                return Err(Error::Corrupted);
-            }
+            let size =
+                adpcm::decompress(data, &mut decompressed, channels).ok_or(Error::Corrupted)?;
 
-            decompressed.resize(decompressor.total_out() as usize, 0);
+            decompressed.truncate(size);
             buf = Cow::Owned(decompressed);
         }
     }
@@ -230,29 +347,381 @@ pub fn decode_mpq_block(
     Ok(buf)
 }
 
-/// This will try to compress the block using zlib compression.
+/// Decodes a block of a file with the `MPQ_FILE_IMPLODE` flag.
+///
+/// Same as [`decode_mpq_block`], except that imploded blocks have no compression
+/// type byte, and are always compressed with PKWare DCL if they are smaller than `uncompressed_size`.
+pub fn explode_mpq_block(
+    input: &[u8],
+    uncompressed_size: u64,
+    encryption_key: Option<u32>,
+) -> Result<Cow<'_, [u8]>, Error> {
+    let mut buf = Cow::Borrowed(input);
+
+    if let Some(encryption_key) = encryption_key {
+        decrypt_mpq_block(buf.to_mut(), encryption_key);
+    }
+
+    if (input.len() as u64) < uncompressed_size {
+        let mut decompressed = vec![0u8; uncompressed_size as usize];
+        let size = explode::explode(&buf, &mut decompressed).ok_or(Error::Corrupted)?;
+
+        decompressed.truncate(size);
+        buf = Cow::Owned(decompressed);
+    }
+
+    Ok(buf)
+}
+
+/// Decodes a single sector directly into `output`, without allocating intermediate buffers.
+///
+/// `input` is decrypted in place if `encryption_key` is specified. `output` must be exactly
+/// as large as the uncompressed sector; if `input` is not smaller than that, the sector is
+/// stored uncompressed and is copied as-is. Returns the number of bytes written to `output`.
+///
+/// Since every compression step needs its own buffer, sectors compressed
+/// with more than one compression method are not supported.
+pub fn decode_sector_into(
+    input: &mut [u8],
+    output: &mut [u8],
+    encryption_key: Option<u32>,
+) -> Result<usize, Error> {
+    if let Some(encryption_key) = encryption_key {
+        decrypt_mpq_block(input, encryption_key);
+    }
+
+    if input.len() >= output.len() {
+        let size = output.len();
+        output.copy_from_slice(&input[..size]);
+
+        return Ok(size);
+    }
+
+    let compression_type = *input.first().ok_or(Error::Corrupted)?;
+
+    check_compression_supported(compression_type)?;
+
+    decompress_single_into(compression_type, &input[1..], output).unwrap_or_else(|| {
+        Err(Error::UnsupportedCompression {
+            kind: format!("Combined compression 0x{:02X}", compression_type),
+        })
+    })
+}
+
+/// Decompresses an already decrypted sector, appending at most `uncompressed_size` bytes to `output`.
+///
+/// zlib and bzip2 data is decompressed as a stream, so `output` only grows by as much data as the sector
+/// actually decompresses to, rather than by the size it is supposed to have. Sectors compressed with
+/// more than one compression method need intermediate buffers, and are decoded with [`decode_mpq_block`].
+pub(crate) fn decompress_sector_append(
+    input: &[u8],
+    uncompressed_size: u64,
+    output: &mut Vec<u8>,
+) -> Result<(), Error> {
+    if input.len() as u64 >= uncompressed_size {
+        output.extend_from_slice(&input[..uncompressed_size as usize]);
+
+        return Ok(());
+    }
+
+    let compression_type = *input.first().ok_or(Error::Corrupted)?;
+
+    check_compression_supported(compression_type)?;
+
+    match compression_type {
+        COMPRESSION_ZLIB => inflate_append(&input[1..], uncompressed_size, output),
+        COMPRESSION_BZIP2 => bunzip_append(&input[1..], uncompressed_size, output),
+        _ => {
+            let start = output.len();
+            output.resize(start + uncompressed_size as usize, 0);
+
+            match decompress_single_into(compression_type, &input[1..], &mut output[start..]) {
+                Some(size) => output.truncate(start + size?),
+                None => {
+                    output.truncate(start);
+                    output.extend_from_slice(&decode_mpq_block(input, uncompressed_size, None)?);
+                }
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// Decompresses data compressed with a single compression method into `output`,
+/// or returns `None` if `compression_type` combines multiple methods.
+fn decompress_single_into(
+    compression_type: u8,
+    data: &[u8],
+    output: &mut [u8],
+) -> Option<Result<usize, Error>> {
+    let size = match compression_type {
+        COMPRESSION_BZIP2 => bunzip_into(data, output),
+        COMPRESSION_ZLIB => inflate_into(data, output),
+        COMPRESSION_PKWARE => explode::explode(data, output),
+        COMPRESSION_SPARSE => sparse::decompress(data, output),
+        COMPRESSION_LZMA => lzma::decompress(data, output),
+        COMPRESSION_HUFFMAN => huffman::decompress(data, output),
+        COMPRESSION_IMA_ADPCM_MONO_MONO => adpcm::decompress(data, output, 1),
+        COMPRESSION_IMA_ADPCM_MONO_STEREO => adpcm::decompress(data, output, 2),
+        _ => return None,
+    };
+
+    Some(size.ok_or(Error::Corrupted))
+}
+
+fn check_compression_supported(compression_type: u8) -> Result<(), Error> {
+    if adpcm_channels(compression_type).is_none() && compression_type & ADPCM_COMPRESSIONS != 0 {
+        return Err(Error::UnsupportedCompression {
+            kind: "IMA ADCPM Mono and Stereo".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns the number of channels of ADPCM-compressed data, if the compression type includes ADPCM.
+fn adpcm_channels(compression_type: u8) -> Option<usize> {
+    match compression_type & ADPCM_COMPRESSIONS {
+        COMPRESSION_IMA_ADPCM_MONO_MONO => Some(1),
+        COMPRESSION_IMA_ADPCM_MONO_STEREO => Some(2),
+        _ => None,
+    }
+}
+
+/// Decompresses a bzip2-compressed sector into `output`, returning the decompressed size.
+fn bunzip_into(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    let mut decompressor = bzip2::Decompress::new(false);
+    let status = decompressor.decompress(input, output).ok()?;
+
+    if status != bzip2::Status::Ok && status != bzip2::Status::StreamEnd {
+        return None;
+    }
+
+    Some(decompressor.total_out() as usize)
+}
+
+/// Decompresses a bzip2-compressed sector as a stream, appending at most `max_size` bytes to `output`.
+fn bunzip_append(input: &[u8], max_size: u64, output: &mut Vec<u8>) -> Result<(), Error> {
+    bzip2::read::BzDecoder::new(input)
+        .take(max_size)
+        .read_to_end(output)
+        .map_err(|_| Error::Corrupted)?;
+
+    Ok(())
+}
+
+/// Inflates a DEFLATE-compressed sector as a stream, appending at most `max_size` bytes to `output`.
+///
+/// Some archives (notably "protected" WC3 maps) contain sectors with a broken
+/// zlib header, or raw DEFLATE streams with no zlib header at all.
+/// If strict zlib decompression fails, this will retry decompressing the data
+/// as a raw DEFLATE stream, and then as a raw DEFLATE stream following
+/// a (possibly broken) two-byte zlib header.
+fn inflate_append(input: &[u8], max_size: u64, output: &mut Vec<u8>) -> Result<(), Error> {
+    let start = output.len();
+    let attempts: [(&[u8], bool); 3] = [
+        (input, true),
+        (input, false),
+        (input.get(2..).unwrap_or_default(), false),
+    ];
+
+    for (data, zlib_header) in attempts.iter() {
+        let result = if *zlib_header {
+            flate2::read::ZlibDecoder::new(*data)
+                .take(max_size)
+                .read_to_end(output)
+        } else {
+            flate2::read::DeflateDecoder::new(*data)
+                .take(max_size)
+                .read_to_end(output)
+        };
+
+        if result.is_ok() {
+            return Ok(());
+        }
+
+        output.truncate(start);
+    }
+
+    Err(Error::Corrupted)
+}
+
+/// Same as `inflate_append`, but decompresses into `output`, returning the decompressed size.
+fn inflate_into(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    let attempts: [(&[u8], bool); 3] = [
+        (input, true),
+        (input, false),
+        (input.get(2..).unwrap_or_default(), false),
+    ];
+
+    for (data, zlib_header) in attempts.iter() {
+        let mut decompressor = flate2::Decompress::new(*zlib_header);
+        let status = decompressor.decompress(data, output, flate2::FlushDecompress::Finish);
+
+        if let Ok(status) = status {
+            if status != flate2::Status::BufError {
+                return Some(decompressor.total_out() as usize);
+            }
+        }
+    }
+
+    None
+}
+
+/// This will try to compress the block using the given compression method.
 /// If the compression succeeded, the block will be prepended by a single
 /// byte indicating which compression method was used.
 /// The compression can fail if the compressed buffer turns out to be
 /// larger than the uncompressed one, in which case it will simply
 /// return the uncompressed buffer.
-// TODO: Add support for multiple compression types
-pub fn compress_mpq_block(input: &[u8]) -> Cow<[u8]> {
+pub fn compress_mpq_block(input: &[u8], compression: Compression) -> Cow<'_, [u8]> {
     let mut compressed: Vec<u8> = vec![0u8; input.len() + 1];
 
+    let size = match compression {
+        Compression::None => None,
+        Compression::Deflate => deflate_into(input, &mut compressed[1..]),
+        Compression::Bzip2 => bzip_into(input, &mut compressed[1..]),
+        Compression::Sparse => sparse::compress(input, &mut compressed[1..]),
+        Compression::AdpcmMono => adpcm::compress(input, &mut compressed[1..], 1, ADPCM_BIT_SHIFT),
+        Compression::AdpcmStereo => {
+            adpcm::compress(input, &mut compressed[1..], 2, ADPCM_BIT_SHIFT)
+        }
+    };
+
+    match size {
+        Some(size) if size + 1 < input.len() => {
+            compressed[0] = compression.type_byte();
+            compressed.truncate(size + 1);
+            Cow::Owned(compressed)
+        }
+        _ => Cow::Borrowed(input),
+    }
+}
+
+/// Compresses `input` with zlib into `output`, returning the compressed size,
+/// or `None` if it does not fit.
+fn deflate_into(input: &[u8], output: &mut [u8]) -> Option<usize> {
     let mut compressor = flate2::Compress::new(flate2::Compression::best(), true);
-    compressor
-        .compress(input, &mut compressed[1..], flate2::FlushCompress::Finish)
+    let status = compressor
+        .compress(input, output, flate2::FlushCompress::Finish)
         .expect("compression failed");
 
-    compressed[0] = COMPRESSION_ZLIB;
+    if status != flate2::Status::StreamEnd {
+        return None;
+    }
 
-    if (compressor.total_out() + 1) as usize >= input.len() {
-        Cow::Borrowed(input)
-    } else {
-        compressed.truncate((compressor.total_out() + 1) as usize);
-        Cow::Owned(compressed)
+    Some(compressor.total_out() as usize)
+}
+
+/// Compresses `input` with bzip2 into `output`, returning the compressed size,
+/// or `None` if it does not fit.
+fn bzip_into(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    let mut compressor = bzip2::Compress::new(bzip2::Compression::Best, 30);
+    let status = compressor
+        .compress(input, output, bzip2::Action::Finish)
+        .expect("compression failed");
+
+    if status != bzip2::Status::StreamEnd {
+        return None;
+    }
+
+    Some(compressor.total_out() as usize)
+}
+
+/// Returns the lowercase extension of the file name, ignoring its directories.
+pub fn file_extension(name: &str) -> Option<String> {
+    name.rsplit(['\\', '/'])
+        .next()
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+}
+
+/// Guesses whether a file's contents are already compressed, in which case compressing them
+/// again would only waste time and add overhead. Files are considered incompressible
+/// if their extension belongs to a compressed format, or if the bytes of their first
+/// `sample_size` bytes are close to uniformly distributed.
+pub fn is_incompressible(name: &str, contents: &[u8], sample_size: usize) -> bool {
+    if let Some(extension) = file_extension(name) {
+        if INCOMPRESSIBLE_EXTENSIONS.contains(&extension.as_str()) {
+            return true;
+        }
+    }
+
+    let sample = &contents[..contents.len().min(sample_size)];
+    if sample.len() < MIN_ENTROPY_SAMPLE_SIZE {
+        return false;
+    }
+
+    let mut histogram = [0usize; 256];
+    for byte in sample {
+        histogram[*byte as usize] += 1;
+    }
+
+    // shannon entropy in bits per byte
+    let total = sample.len() as f64;
+    let entropy: f64 = histogram
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / total;
+            -p * p.log2()
+        })
+        .sum();
+
+    entropy >= INCOMPRESSIBLE_ENTROPY
+}
+
+/// Calculates the Adler-32 checksum, which MPQ uses for sector checksums.
+pub fn adler32(data: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+
+    let mut a = 1u32;
+    let mut b = 0u32;
+
+    // 5552 is the largest number of bytes that can be summed before `b` could overflow
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+
+        a %= MODULUS;
+        b %= MODULUS;
+    }
+
+    (b << 16) | a
+}
+
+/// Checks a stored sector against its entry in the sector checksum table.
+///
+/// The checksum covers the decrypted, but still compressed sector.
+/// A checksum of zero means that the sector has no checksum.
+pub fn verify_sector_checksum(
+    sector: &[u8],
+    encryption_key: Option<u32>,
+    checksum: u32,
+    index: usize,
+) -> Result<(), Error> {
+    if checksum == 0 {
+        return Ok(());
+    }
+
+    let actual = match encryption_key {
+        Some(encryption_key) => {
+            let mut sector = sector.to_vec();
+            decrypt_mpq_block(&mut sector, encryption_key);
+
+            adler32(&sector)
+        }
+        None => adler32(sector),
+    };
+
+    if actual != checksum {
+        return Err(Error::ChecksumMismatch { sector: index });
     }
+
+    Ok(())
 }
 
 pub fn sector_count_from_size(size: u64, sector_count: u64) -> u64 {
@@ -262,3 +731,8 @@ pub fn sector_count_from_size(size: u64, sector_count: u64) -> u64 {
         ((size - 1) / sector_count) + 1
     }
 }
+
+/// Formats bytes as a lowercase hexadecimal string, e.g. for printing hashes.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
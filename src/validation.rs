@@ -0,0 +1,98 @@
+// `err-derive` generates its trait impls inside an anonymous const
+#![allow(non_local_definitions)]
+
+use std::collections::HashMap;
+
+use err_derive::Error;
+
+use super::consts::*;
+use super::util::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+/// A problem with the name of a file added to a [Creator](struct.Creator.html),
+/// as reported by [validate_names](struct.Creator.html#method.validate_names).
+pub enum NameProblem {
+    /// The name is longer than the game's maximum path length.
+    #[error(display = "{} is longer than {} bytes", name, MAX_FILE_NAME_LENGTH)]
+    TooLong { name: String },
+    /// The name contains a character which is not allowed in file names,
+    /// such as a control character or one of `:*?"<>|;`.
+    #[error(display = "{} contains the invalid character {:?}", name, character)]
+    InvalidCharacter { name: String, character: char },
+    /// The name contains an empty path component, e.g. because it starts or ends
+    /// with a backslash, or contains two consecutive backslashes.
+    #[error(display = "{} contains an empty path component", name)]
+    EmptyComponent { name: String },
+    /// The name is reserved for a technical file generated by the creator.
+    #[error(display = "{} is a reserved name", name)]
+    Reserved { name: String },
+    /// The name differs from another added name, but both produce the same hashes,
+    /// so that only one of them can be found in the archive.
+    #[error(display = "{} has the same hash as {}", name, other)]
+    HashCollision { name: String, other: String },
+}
+
+/// Checks a single name for problems which do not depend on other names.
+pub(crate) fn check_name(name: &str, problems: &mut Vec<NameProblem>) {
+    if name.len() > MAX_FILE_NAME_LENGTH {
+        problems.push(NameProblem::TooLong {
+            name: name.to_string(),
+        });
+    }
+
+    if let Some(character) = name
+        .chars()
+        .find(|c| c.is_control() || INVALID_NAME_CHARS.contains(c))
+    {
+        problems.push(NameProblem::InvalidCharacter {
+            name: name.to_string(),
+            character,
+        });
+    }
+
+    if name.split('\\').any(|component| component.is_empty()) {
+        problems.push(NameProblem::EmptyComponent {
+            name: name.to_string(),
+        });
+    }
+
+    if TECHNICAL_FILE_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(name))
+    {
+        problems.push(NameProblem::Reserved {
+            name: name.to_string(),
+        });
+    }
+}
+
+/// Finds distinct names which produce the same name hashes.
+///
+/// Names which only differ in case are the same name as far as the archive is concerned,
+/// and are not reported.
+pub(crate) fn find_collisions<'a, I>(names: I, problems: &mut Vec<NameProblem>)
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut seen: HashMap<(u32, u32), &str> = HashMap::new();
+
+    for name in names {
+        let hashes = (
+            hash_string(name.as_bytes(), MPQ_HASH_NAME_A),
+            hash_string(name.as_bytes(), MPQ_HASH_NAME_B),
+        );
+
+        match seen.get(&hashes) {
+            Some(other) if !other.eq_ignore_ascii_case(name) => {
+                problems.push(NameProblem::HashCollision {
+                    name: name.to_string(),
+                    other: other.to_string(),
+                });
+            }
+            Some(_) => {}
+            None => {
+                seen.insert(hashes, name);
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use super::archive::FileInfo;
+use super::attributes::{Attributes, DigestHasher};
+use super::error::Error;
+use super::file::FileSectors;
+use super::util::*;
+
+/// The amount of stored file data that is read before the files are verified together.
+pub(crate) const VERIFY_BATCH_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+/// The result of verifying a single file, as reported by
+/// [verify_all](struct.Archive.html#method.verify_all).
+pub struct FileVerification {
+    /// Metadata of the verified file.
+    pub info: FileInfo,
+    /// The name of the file, if it is known.
+    pub name: Option<String>,
+    /// The problem that was found, or `None` if the file is intact.
+    pub error: Option<Error>,
+}
+
+/// A file whose stored data was read, and which is waiting to be verified.
+#[derive(Debug)]
+pub(crate) struct VerifyJob {
+    pub info: FileInfo,
+    pub name: Option<String>,
+    pub sectors: FileSectors,
+    pub stored: Vec<u8>,
+}
+
+/// Verifies a batch of files, using all available threads with the `parallel` feature.
+pub(crate) fn verify_batch(
+    jobs: Vec<VerifyJob>,
+    attributes: Option<&Attributes>,
+) -> Vec<FileVerification> {
+    #[cfg(feature = "parallel")]
+    let jobs = jobs.into_par_iter();
+
+    #[cfg(not(feature = "parallel"))]
+    let jobs = jobs.into_iter();
+
+    jobs.map(|job| {
+        let error = verify_stored(&job, attributes).err();
+
+        FileVerification {
+            info: job.info,
+            name: job.name,
+            error,
+        }
+    })
+    .collect()
+}
+
+/// Decodes every sector of a file from its stored data, checking the sector checksums
+/// and the checksums in the `(attributes)`, if there are any.
+fn verify_stored(job: &VerifyJob, attributes: Option<&Attributes>) -> Result<(), Error> {
+    let sectors = &job.sectors;
+    let mut digests = DigestHasher::default();
+    let mut decoded_size = 0;
+
+    for i in 0..sectors.offsets.count() {
+        let (offset, size) = sectors.offsets.one(i).ok_or(Error::Corrupted)?;
+        let raw_sector = job
+            .stored
+            .get(offset as usize..(offset + size) as usize)
+            .ok_or(Error::Corrupted)?;
+        let sector_key = sectors.encryption_key.map(|k| k.wrapping_add(i as u32));
+
+        if let Some(checksums) = &sectors.checksums {
+            verify_sector_checksum(
+                raw_sector,
+                sector_key,
+                checksums.get(i).copied().unwrap_or(0),
+                i,
+            )?;
+        }
+
+        let decoded_sector = sectors.block_entry.decode_sector(
+            raw_sector,
+            sectors.uncompressed_size(i),
+            sector_key,
+        )?;
+
+        digests.update(&decoded_sector);
+        decoded_size += decoded_sector.len() as u64;
+    }
+
+    if decoded_size != sectors.block_entry.uncompressed_size {
+        return Err(Error::Corrupted);
+    }
+
+    match attributes {
+        Some(attributes) if !attributes.matches(sectors.block_index, &digests.finish()) => {
+            Err(Error::AttributesMismatch {
+                name: job
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("block {}", sectors.block_index)),
+            })
+        }
+        _ => Ok(()),
+    }
+}